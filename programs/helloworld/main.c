@@ -13,7 +13,7 @@ int main() {
     char password_buf[20];
     svc_puts("Enter password: ");
 
-    svc_gets(password_buf);
+    svc_gets(password_buf, sizeof(password_buf));
     svc_puts("You entered: ");
     svc_puts(password_buf);
     svc_puts("\n");
@@ -6,7 +6,7 @@ int main() {
   while (1) {
     svc_puts("Enter password: ");
     memset(password_buf, 'a', sizeof(password_buf));
-    svc_gets(password_buf);
+    svc_gets(password_buf, sizeof(password_buf));
     svc_puts("You entered: ");
     svc_puts(password_buf);
     svc_puts("\n");
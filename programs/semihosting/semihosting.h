@@ -0,0 +1,62 @@
+#ifndef semihosting_h
+#define semihosting_h
+
+// ARM semihosting calls, see src/cpu/semihosting.rs
+// Files are opened relative to the simulator's --host-dir
+#define SYS_OPEN 0x01
+#define SYS_CLOSE 0x02
+#define SYS_WRITEC 0x03
+#define SYS_WRITE0 0x04
+#define SYS_WRITE 0x05
+#define SYS_READ 0x06
+#define SYS_CLOCK 0x10
+#define SYS_GET_CMDLINE 0x15
+#define SYS_HEAPINFO 0x16
+#define SYS_EXIT_EXTENDED 0x20
+
+#define ADP_STOPPED_APPLICATION_EXIT 0x20026
+
+// Open modes, as for fopen
+#define SH_MODE_R 0
+#define SH_MODE_W 4
+#define SH_MODE_A 8
+
+static inline int semihost(unsigned op, const void *arg) {
+    register unsigned r0 __asm__("r0") = op;
+    register const void *r1 __asm__("r1") = arg;
+    __asm volatile ("bkpt 0xAB" : "+r"(r0) : "r"(r1) : "memory");
+    return r0;
+}
+
+static inline void sh_puts(const char *s) {
+    semihost(SYS_WRITE0, s);
+}
+
+static inline int sh_open(const char *path, unsigned mode) {
+    unsigned len = 0;
+    while (path[len]) len++;
+    unsigned block[3] = {(unsigned)path, mode, len};
+    return semihost(SYS_OPEN, block);
+}
+
+static inline int sh_close(int fd) {
+    return semihost(SYS_CLOSE, &fd);
+}
+
+// Both return the number of bytes not transferred
+static inline int sh_write(int fd, const void *buf, unsigned len) {
+    unsigned block[3] = {fd, (unsigned)buf, len};
+    return semihost(SYS_WRITE, block);
+}
+
+static inline int sh_read(int fd, void *buf, unsigned len) {
+    unsigned block[3] = {fd, (unsigned)buf, len};
+    return semihost(SYS_READ, block);
+}
+
+static inline void sh_exit(int code) {
+    unsigned block[2] = {ADP_STOPPED_APPLICATION_EXIT, code};
+    semihost(SYS_EXIT_EXTENDED, block);
+}
+
+#endif // semihosting_h
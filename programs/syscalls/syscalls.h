@@ -3,10 +3,42 @@
 
 #include <stddef.h>
 
-extern char __heap_start;  // Defined in the linker script
-extern char __heap_end;    // Defined in the linker script
-// Pointer to track the current position in the heap
-static char* heap_ptr = &__heap_start;
+// The svc ABI, see src/cpu/syscalls.rs. Arguments go in r0-r2 and the result
+// comes back in r0 (r0 and r1 for 64 bit results). Failed calls return -1 and
+// svc_errno() gives the reason. Files are opened relative to --host-dir.
+#define SVC_EXIT 0
+#define SVC_PUTS 1
+#define SVC_GETS 2
+#define SVC_PUTINT 3
+#define SVC_READ 4
+#define SVC_WRITE 5
+#define SVC_OPEN 6
+#define SVC_CLOSE 7
+#define SVC_LSEEK 8
+#define SVC_FSTAT 9
+#define SVC_GETTIMEOFDAY 10
+#define SVC_CLOCK 11
+#define SVC_CYCLES 12
+#define SVC_SBRK 13
+#define SVC_ERRNO 14
+#define SVC_MARKER 15
+
+struct svc_stat {
+    unsigned st_mode;
+    unsigned st_size;
+};
+
+#define SVC_CALL(n, a, b, c)                                                      \
+    ({                                                                           \
+        register unsigned r0 __asm__("r0") = (unsigned)(a);                      \
+        register unsigned r1 __asm__("r1") = (unsigned)(b);                      \
+        register unsigned r2 __asm__("r2") = (unsigned)(c);                      \
+        __asm volatile ("svc %[num]"                                             \
+                        : "+r"(r0), "+r"(r1)                                     \
+                        : [num] "i"(n), "r"(r2)                                  \
+                        : "memory");                                             \
+        (unsigned long long)r0 | ((unsigned long long)r1 << 32);                 \
+    })
 
 static inline void svc_exit(unsigned code) {
     register unsigned r0 __asm__("r0") = code;
@@ -18,27 +50,73 @@ static inline void svc_puts(const char *s) {
      __asm volatile ("svc 1" :  : "r"(r0) : "memory");
 };
 
+// Reads a line of the guest stdin, without the newline, into the size bytes at
+// s. Like fgets, a longer line is cut short and the rest left for the next call.
+// Returns 0 at the end
+static inline char *svc_gets(char *s, unsigned size) {
+    return (char *)(unsigned)SVC_CALL(SVC_GETS, s, size, 0);
+}
+
 static inline void svc_putint(int n) {
      register int r0 __asm__("r0") = n;
      __asm volatile ("svc 3" :  : "r"(r0) : "memory");
 }
 
-extern void* sbrk(ptrdiff_t increment) {
-    char* prev_heap_ptr = heap_ptr;
+static inline int svc_read(int fd, void *buf, unsigned len) {
+    return (int)SVC_CALL(SVC_READ, fd, buf, len);
+}
+
+static inline int svc_write(int fd, const void *buf, unsigned len) {
+    return (int)SVC_CALL(SVC_WRITE, fd, buf, len);
+}
+
+// flags are the newlib O_* values from fcntl.h
+static inline int svc_open(const char *path, int flags) {
+    return (int)SVC_CALL(SVC_OPEN, path, flags, 0);
+}
+
+static inline int svc_close(int fd) {
+    return (int)SVC_CALL(SVC_CLOSE, fd, 0, 0);
+}
 
-    // Check if the increment is positive (malloc style)
-    if (increment > 0) {
-        // Ensure we don’t exceed the heap boundary
-        if (heap_ptr + increment > &__heap_end) {
-            return (void*)-1;  // Return error if the heap is full
-        }
+static inline int svc_lseek(int fd, int offset, int whence) {
+    return (int)SVC_CALL(SVC_LSEEK, fd, offset, whence);
+}
+
+static inline int svc_fstat(int fd, struct svc_stat *st) {
+    return (int)SVC_CALL(SVC_FSTAT, fd, st, 0);
+}
 
-        // Update the heap pointer to the new position
-        heap_ptr += increment;
-    }
+// Simulated time, from the cycle count at the simulator's clock rate
+static inline void svc_gettimeofday(unsigned *sec, unsigned *usec) {
+    unsigned long long t = SVC_CALL(SVC_GETTIMEOFDAY, 0, 0, 0);
+    *sec = (unsigned)t;
+    *usec = (unsigned)(t >> 32);
+}
 
-    // Return the previous heap position as the allocated memory
-    return (void*)prev_heap_ptr;
+// Microseconds of simulated time since the start
+static inline unsigned svc_clock(void) {
+    return (unsigned)SVC_CALL(SVC_CLOCK, 0, 0, 0);
+}
+
+static inline unsigned long long svc_cycles(void) {
+    return SVC_CALL(SVC_CYCLES, 0, 0, 0);
+}
+
+static inline int svc_errno(void) {
+    return (int)SVC_CALL(SVC_ERRNO, 0, 0, 0);
+}
+
+// Does nothing, but --fast-forward marker stops just after the first one
+static inline void svc_marker(void) {
+    SVC_CALL(SVC_MARKER, 0, 0, 0);
+}
+
+// The heap runs between __heap_start and __heap_end from the linker script,
+// the simulator keeps track of the break. Without __heap_start, as in raw
+// binaries, it fails with ENOMEM
+extern void* sbrk(ptrdiff_t increment) {
+    return (void*)(unsigned)SVC_CALL(SVC_SBRK, increment, 0, 0);
 }
 
-#endif // syscalls_h
\ No newline at end of file
+#endif // syscalls_h
@@ -0,0 +1,19 @@
+#include "../syscalls/syscalls.h"
+#include "uart.h"
+
+// Echoes lines from the UART until an empty line
+int main() {
+  uart_puts("> ");
+  char last = '\n';
+  while (1) {
+    char c = uart_getc();
+    uart_putc(c);
+    if (c == '\n') {
+      if (last == '\n') {
+        svc_exit(0);
+      }
+      uart_puts("> ");
+    }
+    last = c;
+  }
+}
@@ -0,0 +1,34 @@
+#ifndef uart_h
+#define uart_h
+
+// Memory mapped UART, see src/model/uart.rs
+#define UART_BASE 0x40000000
+
+#define UART_DATA (*(volatile unsigned *)(UART_BASE + 0x0))
+#define UART_STATUS (*(volatile unsigned *)(UART_BASE + 0x4))
+#define UART_CTRL (*(volatile unsigned *)(UART_BASE + 0x8))
+
+#define UART_STATUS_RXNE (1 << 0)
+#define UART_STATUS_TXE (1 << 1)
+#define UART_CTRL_RXIE (1 << 0)
+
+// The RX interrupt is IRQ 0, so its handler goes in vector table entry 16
+#define UART_IRQ 0
+
+static inline void uart_putc(char c) {
+    while (!(UART_STATUS & UART_STATUS_TXE));
+    UART_DATA = c;
+}
+
+static inline void uart_puts(const char *s) {
+    while (*s) {
+        uart_putc(*s++);
+    }
+}
+
+static inline char uart_getc(void) {
+    while (!(UART_STATUS & UART_STATUS_RXNE));
+    return UART_DATA;
+}
+
+#endif // uart_h
@@ -0,0 +1,327 @@
+//! The checkpoint file format. A checkpoint is a magic number and version, then
+//! every piece of machine state written in a fixed order by `Persist`, little
+//! endian throughout. Lengths come before collections. Anything that changes what
+//! is written, or its order, needs a new version.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::hash::Hash;
+use std::io;
+
+pub const MAGIC: &[u8; 8] = b"ACACKPT\0";
+pub const VERSION: u32 = 4;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    NotACheckpoint,
+    /// A checkpoint from a different version of the simulator
+    Version(u32),
+    Truncated,
+    Invalid(&'static str),
+    /// The checkpoint is of a different program, or a different memory map
+    ProgramMismatch,
+    /// The guest has host files open, which can't be saved
+    FilesOpen,
+    /// The guest stdin or UART input runs out before what was read by the
+    /// checkpoint
+    InputTooShort,
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "{}", e),
+            CheckpointError::NotACheckpoint => write!(f, "not a checkpoint file"),
+            CheckpointError::Version(v) => write!(
+                f,
+                "checkpoint is version {}, this simulator reads version {}",
+                v, VERSION
+            ),
+            CheckpointError::Truncated => write!(f, "checkpoint is truncated"),
+            CheckpointError::Invalid(what) => write!(f, "checkpoint has an invalid {}", what),
+            CheckpointError::ProgramMismatch => {
+                write!(f, "checkpoint is of a different program or memory map")
+            }
+            CheckpointError::FilesOpen => {
+                write!(f, "the program has host files open, which can't be saved")
+            }
+            CheckpointError::InputTooShort => {
+                write!(f, "the input is shorter than what the program had read by then")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+/// A checkpoint being read
+pub struct Input<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], CheckpointError> {
+        if self.bytes.len() < n {
+            return Err(CheckpointError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], CheckpointError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+/// Something written to and read back from a checkpoint
+pub trait Persist: Sized {
+    fn save(&self, out: &mut Vec<u8>);
+    fn load(input: &mut Input) -> Result<Self, CheckpointError>;
+}
+
+/// Persist for a struct, field by field in the order given, which must be all of
+/// them. Used next to the struct so private fields can be reached
+macro_rules! persist_fields {
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        impl crate::checkpoint::Persist for $ty {
+            fn save(&self, out: &mut Vec<u8>) {
+                $(crate::checkpoint::Persist::save(&self.$field, out);)*
+            }
+
+            fn load(
+                input: &mut crate::checkpoint::Input,
+            ) -> Result<Self, crate::checkpoint::CheckpointError> {
+                Ok($ty {
+                    $($field: crate::checkpoint::Persist::load(input)?,)*
+                })
+            }
+        }
+    };
+}
+pub(crate) use persist_fields;
+
+macro_rules! persist_int {
+    ($($ty:ty),*) => {
+        $(impl Persist for $ty {
+            fn save(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+                Ok(<$ty>::from_le_bytes(input.take_array()?))
+            }
+        })*
+    };
+}
+persist_int!(u8, u16, u32, u64, i32);
+
+/// As 64 bits, so checkpoints don't depend on the host
+impl Persist for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u64).save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        usize::try_from(u64::load(input)?).map_err(|_| CheckpointError::Invalid("size"))
+    }
+}
+
+impl Persist for f64 {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.to_bits().save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(f64::from_bits(u64::load(input)?))
+    }
+}
+
+impl Persist for bool {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u8).save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        match u8::load(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(CheckpointError::Invalid("bool")),
+        }
+    }
+}
+
+impl Persist for String {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        let len = usize::load(input)?;
+        String::from_utf8(input.take(len)?.to_vec()).map_err(|_| CheckpointError::Invalid("string"))
+    }
+}
+
+impl<T: Persist> Persist for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.is_some().save(out);
+        if let Some(value) = self {
+            value.save(out);
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(match bool::load(input)? {
+            true => Some(T::load(input)?),
+            false => None,
+        })
+    }
+}
+
+impl<A: Persist, B: Persist> Persist for (A, B) {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+        self.1.save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok((A::load(input)?, B::load(input)?))
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        let len = usize::load(input)?;
+        // Not trusting the length with an allocation before the items are there
+        let mut items = Vec::with_capacity(len.min(input.bytes.len()));
+        for _ in 0..len {
+            items.push(T::load(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Persist> Persist for VecDeque<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(Vec::load(input)?.into())
+    }
+}
+
+impl<T: Persist, const N: usize> Persist for [T; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::load(input)?);
+        }
+        Ok(items.try_into().ok().unwrap())
+    }
+}
+
+impl Persist for Box<[u8]> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        out.extend_from_slice(self);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        let len = usize::load(input)?;
+        Ok(input.take(len)?.into())
+    }
+}
+
+impl<K: Persist + Eq + Hash, V: Persist> Persist for HashMap<K, V> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for (key, value) in self {
+            key.save(out);
+            value.save(out);
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(Vec::<(K, V)>::load(input)?.into_iter().collect())
+    }
+}
+
+impl<K: Persist + Ord, V: Persist> Persist for BTreeMap<K, V> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for (key, value) in self {
+            key.save(out);
+            value.save(out);
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(Vec::<(K, V)>::load(input)?.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use super::*;
+
+    fn round_trip<T: Persist>(value: &T) -> T {
+        let mut out = Vec::new();
+        value.save(&mut out);
+        let mut input = Input::new(&out);
+        let loaded = T::load(&mut input).unwrap();
+        assert!(input.is_empty());
+        loaded
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(round_trip(&0x1234_5678u32), 0x1234_5678);
+        assert_eq!(round_trip(&-5i32), -5);
+        assert_eq!(
+            round_trip(&Some(String::from("main"))),
+            Some("main".to_string())
+        );
+        assert_eq!(round_trip(&[Some(3usize), None]), [Some(3), None]);
+        let map: BTreeMap<String, f64> = [("ipc".to_string(), 1.5)].into();
+        assert_eq!(round_trip(&map), map);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut out = Vec::new();
+        vec![1u32, 2, 3].save(&mut out);
+        out.pop();
+        let loaded = Vec::<u32>::load(&mut Input::new(&out));
+        assert!(matches!(loaded, Err(CheckpointError::Truncated)));
+    }
+}
@@ -1,7 +1,9 @@
 use crate::components::ROB::ROBStatus::EMPTY;
+use crate::checkpoint::{persist_fields, CheckpointError, Input, Persist};
 use crate::cpu::{InstructionQueueEntry, LoadQueueEntry, ROB_ENTRIES, STORE_LOAD_FORWARDING};
 use crate::decode::{I, IT::*};
 use crate::model::{ASPRUpdate, Registers};
+use crate::stats::{Sampled, Stats};
 use std::fmt::Formatter;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -21,6 +23,7 @@ impl std::fmt::Display for ROBStatus {
     }
 }
 
+#[derive(Clone)]
 pub struct ROB {
     queue: [ROBEntry; ROB_ENTRIES],
     pub head: usize,
@@ -53,7 +56,9 @@ pub struct ROBEntry {
     pub asprupdate: ASPRUpdate,
     pub ready: bool,
     pub dest: ROBEntryDest,
-    pub predicted_taken: bool
+    pub predicted_taken: bool,
+    pub ends_instruction: bool,
+    pub seq: u64,
 }
 
 impl ROBEntry {
@@ -66,6 +71,8 @@ impl ROBEntry {
             status: ROBStatus::EMPTY,
             dest: ROBEntryDest::None,
             predicted_taken: false,
+            ends_instruction: false,
+            seq: 0,
             i: I::undefined(),
             asprupdate: ASPRUpdate::no_update(),
             ready: false,
@@ -151,7 +158,7 @@ impl ROB {
 
             // All ALU instructions that dont write back, as well as branches and system calls
             // Have none as a destination
-            TST | CMPImm | CMN | CMPReg | B | BX | SVC | NOP => ROBEntryDest::None,
+            TST | CMPImm | CMN | CMPReg | B | BX | SVC | BKPT | NOP => ROBEntryDest::None,
 
             // Sets LR
             BL | BLX => {
@@ -227,6 +234,8 @@ impl ROB {
             halt: false,
             dest: rob_dest,
             predicted_taken,
+            ends_instruction: iqe.ends_instruction,
+            seq: iqe.seq,
             ready: false,
             asprupdate: ASPRUpdate::no_update(),
         };
@@ -334,6 +343,11 @@ impl ROB {
         }
     }
 
+    /// Entries in use
+    pub fn occupancy(&self) -> usize {
+        self.queue.iter().filter(|e| e.status != EMPTY).count()
+    }
+
     pub fn is_empty(&self) -> bool {
         (self.head == self.tail) && !self.is_full()
     }
@@ -454,6 +468,76 @@ impl std::fmt::Display for ROBEntry {
         )
     }
 }
+impl Persist for ROBStatus {
+    fn save(&self, out: &mut Vec<u8>) {
+        let n: u8 = match self {
+            ROBStatus::EMPTY => 0,
+            ROBStatus::Execute => 1,
+            ROBStatus::Write => 2,
+        };
+        n.save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(match u8::load(input)? {
+            0 => ROBStatus::EMPTY,
+            1 => ROBStatus::Execute,
+            2 => ROBStatus::Write,
+            _ => return Err(CheckpointError::Invalid("ROB status")),
+        })
+    }
+}
+
+impl Persist for ROBEntryDest {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            ROBEntryDest::None => 0u8.save(out),
+            ROBEntryDest::AwaitingAddress => 1u8.save(out),
+            ROBEntryDest::Address(addr) => {
+                2u8.save(out);
+                addr.save(out);
+            }
+            ROBEntryDest::Register(reg) => {
+                3u8.save(out);
+                reg.save(out);
+            }
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(match u8::load(input)? {
+            0 => ROBEntryDest::None,
+            1 => ROBEntryDest::AwaitingAddress,
+            2 => ROBEntryDest::Address(u32::load(input)?),
+            3 => ROBEntryDest::Register(u8::load(input)?),
+            _ => return Err(CheckpointError::Invalid("ROB destination")),
+        })
+    }
+}
+
+persist_fields!(ROBEntry {
+    pc,
+    halt,
+    i,
+    status,
+    value,
+    target_address,
+    asprupdate,
+    ready,
+    dest,
+    predicted_taken,
+    ends_instruction,
+    seq
+});
+persist_fields!(ROB {
+    queue,
+    head,
+    tail,
+    register_status,
+    will_issue,
+    temp_register_status
+});
+
 #[cfg(test)]
 mod ROBTests {
     use super::*;
@@ -475,3 +559,9 @@ mod ROBTests {
         assert_eq!(rob.entry_is_before(62, 0), false);
     }
 }
+
+impl Sampled for ROB {
+    fn sample(&self, stats: &mut Stats, name: &str) {
+        stats.record(&format!("{}.occupancy", name), self.occupancy());
+    }
+}
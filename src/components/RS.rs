@@ -1,6 +1,8 @@
 use crate::components::ROB::ROB;
+use crate::checkpoint::{persist_fields, CheckpointError, Input, Persist};
 use crate::decode::{IssueType, I, IT::*};
 use crate::model::Registers;
+use crate::stats::{Sampled, Stats};
 use std::cmp::Ordering;
 use std::fmt::Display;
 use crate::cpu::InstructionQueueEntry;
@@ -49,6 +51,13 @@ impl RS {
         }
     }
 
+    /// Whether any operand is still to come over the CDB
+    pub fn waiting_on_operands(&self) -> bool {
+        [self.j, self.k, self.l]
+            .iter()
+            .any(|data| matches!(data, RSData::ROB(_, _)))
+    }
+
     fn receive_cdb_broadcast(&mut self, rob_entry: usize, rn: u8, result: u32) {
         match self.j {
             RSData::ROB(rob_entry_2, rn_2) => {
@@ -98,6 +107,7 @@ impl RS {
     }
 }
 
+#[derive(Clone)]
 pub struct RSSet {
     pub vec: Vec<RS>,
     issue_type: IssueType,
@@ -126,6 +136,11 @@ impl<'a> RSSet {
         self.n
     }
 
+    /// Stations in use
+    pub fn occupancy(&self) -> usize {
+        self.vec.iter().filter(|rs| rs.busy).count()
+    }
+
     /// if RST shows ROB entry then this, else get data from ARF
     fn get_rs_data(
         rn: u8,
@@ -212,8 +227,9 @@ impl<'a> RSSet {
         set
     }
 
-    fn get_dependencies(
-        &mut self,
+    /// The operands of an instruction, from the ROB where register_status says so
+    pub fn get_dependencies(
+        &self,
         i: &I,
         pc: u32,
         arf: &Registers,
@@ -380,6 +396,10 @@ impl<'a> RSSet {
                         j = RSData::Data(i.immu);
                         k = Self::get_rs_data(0, arf, register_status, rob);
                     }
+                    // Semihosting reads its arguments at commit
+                    BKPT => {
+                        j = RSData::Data(i.immu);
+                    }
                     _ => panic!(
                         "{:?} should not have been issued here. This is the res stations for {:?}",
                         i, self.issue_type
@@ -423,3 +443,45 @@ impl<'a> RSSet {
         Some(alloc)
     }
 }
+
+impl Sampled for RSSet {
+    fn sample(&self, stats: &mut Stats, name: &str) {
+        stats.record(&format!("{}.occupancy", name), self.occupancy());
+    }
+}
+
+impl Persist for RSData {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            RSData::ROB(rob, reg) => {
+                0u8.save(out);
+                rob.save(out);
+                reg.save(out);
+            }
+            RSData::Data(value) => {
+                1u8.save(out);
+                value.save(out);
+            }
+            RSData::None => 2u8.save(out),
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(match u8::load(input)? {
+            0 => RSData::ROB(usize::load(input)?, u8::load(input)?),
+            1 => RSData::Data(u32::load(input)?),
+            2 => RSData::None,
+            _ => return Err(CheckpointError::Invalid("reservation station operand")),
+        })
+    }
+}
+
+persist_fields!(RS {
+    busy,
+    j,
+    k,
+    l,
+    i,
+    rob_dest
+});
+persist_fields!(RSSet { vec, issue_type, n });
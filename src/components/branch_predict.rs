@@ -1,20 +1,29 @@
 use std::cmp::min;
 use std::collections::HashMap;
 use crate::cpu::{PREDICT, PredictionAlgorithms::*};
+use crate::stats::{Sampled, Stats};
+use crate::checkpoint::persist_fields;
 
+#[derive(Clone)]
 pub struct BTB {
     // PC: (pred pc, direction)
-    hm: HashMap<u32, u32>
+    hm: HashMap<u32, u32>,
+    lookups: u64,
+    // Lookups of branches not seen before
+    misses: u64,
 }
 
 impl BTB {
     pub fn new() -> Self { 
         Self {
-            hm: HashMap::new()
+            hm: HashMap::new(),
+            lookups: 0,
+            misses: 0,
         }
     }
     
-    pub fn make_prediction(&self, pc: u32) -> bool {
+    pub fn make_prediction(&mut self, pc: u32) -> bool {
+        self.lookups += 1;
         if let Some(counter) = self.hm.get(&pc) {
             let counter = *counter;
             
@@ -27,6 +36,7 @@ impl BTB {
                 _ => panic!()
             }
         } else {
+            self.misses += 1;
             match PREDICT {
                 Bits(_) => true,
                 _ => panic!()
@@ -50,4 +60,14 @@ impl BTB {
             self.hm.insert(pc, taken as u32);
         }
     }
-}
\ No newline at end of file
+}
+
+impl Sampled for BTB {
+    fn sample(&self, stats: &mut Stats, name: &str) {
+        stats.set(&format!("{}.entries", name), self.hm.len() as u64);
+        stats.set(&format!("{}.lookups", name), self.lookups);
+        stats.set(&format!("{}.misses", name), self.misses);
+    }
+}
+
+persist_fields!(BTB { hm, lookups, misses });
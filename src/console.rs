@@ -0,0 +1,490 @@
+//! The TUI command line, opened with : for breakpoints, watchpoints and running
+//! until something happens. Runs stop early at a breakpoint or watchpoint, when
+//! the program ends, or on Esc. Playing runs a few cycles a frame until paused,
+//! stopping the same way.
+use crate::cpu::{OoOSpeculative, StopReason, WatchKind, Watchpoint};
+use crate::model::{Memory, Registers};
+use ratatui::crossterm::event::KeyCode;
+use ratatui::layout::Rect;
+use ratatui::widgets::Paragraph;
+use ratatui::Frame;
+use std::time::Duration;
+
+const HELP: &str = "break <addr|symbol>, watch/rwatch/awatch <addr|symbol> [len], \
+                    delete [addr|symbol], cycles <n>, commit <n>, until <reg> <op> <value>, \
+                    continue, rcycles <n>, rcommit <n>, speed <cycles a frame>, \
+                    mem <addr|symbol>, follow <reg>, info";
+
+/// Ticks between checks for Esc during a run
+const INTERRUPT_POLL: usize = 4096;
+/// How often the TUI is redrawn while playing
+pub const FRAME: Duration = Duration::from_millis(100);
+/// Playing faster than this many cycles a frame wouldn't keep up with the frames
+const MAX_SPEED: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn parse(op: &str) -> Option<Self> {
+        Some(match op {
+            "==" | "=" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => return None,
+        })
+    }
+
+    /// Registers compare unsigned
+    fn holds(self, lhs: u32, rhs: u32) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Break(u32),
+    Watch(Watchpoint),
+    /// Remove the breakpoint and watchpoints at an address, or all of them
+    Delete(Option<u32>),
+    Cycles(usize),
+    /// Run until this many more instructions commit
+    Commit(usize),
+    /// Run until the register, by `Registers::get` index, compares true
+    Until {
+        reg: u8,
+        op: Comparison,
+        value: u32,
+    },
+    Continue,
+    /// Step back this many cycles
+    BackCycles(usize),
+    /// Step back until this many fewer instructions have committed
+    BackCommit(usize),
+    /// Cycles a frame to play at
+    Speed(usize),
+    /// Show memory from an address
+    Mem(u32),
+    /// Have the memory pane follow a register, by `Registers::get` index
+    Follow(u8),
+    Info,
+}
+
+/// A number, hex with 0x, or a symbol from the ELF
+fn parse_value(text: &str, mem: &Memory) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    parsed
+        .or_else(|| mem.get_symbol(text))
+        .ok_or_else(|| format!("{} is not a number or symbol", text))
+}
+
+fn parse_count(text: Option<&str>) -> Result<usize, String> {
+    let text = text.ok_or("Expected a count")?;
+    text.parse().map_err(|_| format!("{} is not a count", text))
+}
+
+/// Names as the register status pane shows them, and the flags
+fn parse_register(name: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    Some(match name.as_str() {
+        "sp" => 13,
+        "lr" => 14,
+        "pc" => 15,
+        "n" => 16,
+        "z" => 17,
+        "c" => 18,
+        "v" => 19,
+        _ => {
+            let index: u8 = name.strip_prefix('r')?.parse().ok()?;
+            if index > 15 {
+                return None;
+            }
+            index
+        }
+    })
+}
+
+pub fn parse(line: &str, mem: &Memory) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return Err(HELP.to_string());
+    };
+    let mut next_value = |what: &str| {
+        let text = words.next().ok_or(format!("Expected {}", what))?;
+        parse_value(text, mem)
+    };
+    let command = match name {
+        // Thumb function symbols have the low bit set
+        "break" | "b" => Command::Break(next_value("an address")? & !1),
+        "watch" | "rwatch" | "awatch" => {
+            let addr = next_value("an address")?;
+            // Four bytes unless a length is given
+            let len = match words.next() {
+                Some(text) => parse_value(text, mem)?,
+                None => 4,
+            };
+            if len == 0 || addr.checked_add(len).is_none() {
+                return Err(format!("Can't watch {} bytes at {:08X}", len, addr));
+            }
+            let kind = match name {
+                "watch" => WatchKind::Write,
+                "rwatch" => WatchKind::Read,
+                _ => WatchKind::Access,
+            };
+            Command::Watch(Watchpoint { addr, len, kind })
+        }
+        "delete" | "d" => Command::Delete(next_value("an address").ok()),
+        "cycles" => Command::Cycles(parse_count(words.next())?),
+        "commit" => Command::Commit(parse_count(words.next())?),
+        "until" => {
+            let reg = words.next().ok_or("Expected a register")?;
+            let reg = parse_register(reg).ok_or(format!("{} is not a register", reg))?;
+            let op = words.next().ok_or("Expected a comparison")?;
+            let op = Comparison::parse(op).ok_or(format!("{} is not a comparison", op))?;
+            let value = words.next().ok_or("Expected a value")?;
+            let value = parse_value(value, mem)?;
+            Command::Until { reg, op, value }
+        }
+        "continue" | "c" => Command::Continue,
+        "rcycles" => Command::BackCycles(parse_count(words.next())?),
+        "rcommit" => Command::BackCommit(parse_count(words.next())?),
+        "speed" => match parse_count(words.next())? {
+            0 => return Err("Speed must be at least a cycle a frame".to_string()),
+            n => Command::Speed(n.min(MAX_SPEED)),
+        },
+        "mem" | "x" => Command::Mem(next_value("an address")?),
+        "follow" => {
+            let reg = words.next().ok_or("Expected a register")?;
+            match parse_register(reg) {
+                Some(reg @ 0..=15) => Command::Follow(reg),
+                _ => return Err(format!("{} is not a register", reg)),
+            }
+        }
+        "info" | "i" => Command::Info,
+        _ => return Err(format!("Unknown command {}. {}", name, HELP)),
+    };
+    Ok(command)
+}
+
+fn describe_address(addr: u32, mem: &Memory) -> String {
+    match mem.function_containing(addr) {
+        Some((start, name)) if start == addr => format!("{:08X} ({})", addr, name),
+        Some((start, name)) => format!("{:08X} ({}+{:#x})", addr, name, addr - start),
+        None => format!("{:08X}", addr),
+    }
+}
+
+/// Carry out a command, returning the message for the command line.
+/// interrupted is polled during runs. Speed is the console's own, see
+/// Console::run
+fn execute(
+    cpu: &mut OoOSpeculative,
+    command: Command,
+    mut interrupted: impl FnMut() -> bool,
+) -> String {
+    let start_cycle = cpu.epoch;
+    let start_committed = cpu.instructions_committed;
+    let mut ticks = 0;
+    let mut by_user = false;
+    let mut keep_going = |done: bool| {
+        ticks += 1;
+        by_user = !done && ticks % INTERRUPT_POLL == 0 && interrupted();
+        !done && !by_user
+    };
+    let stop = match command {
+        Command::Break(addr) => {
+            cpu.debug.breakpoints.insert(addr);
+            return format!(
+                "Breakpoint at {}",
+                describe_address(addr, &cpu.arch_state().mem)
+            );
+        }
+        Command::Watch(watchpoint) => {
+            cpu.debug.watchpoints.push(watchpoint);
+            return format!(
+                "{:?} watchpoint on {} bytes at {:08X}",
+                watchpoint.kind, watchpoint.len, watchpoint.addr
+            );
+        }
+        Command::Delete(None) => {
+            cpu.debug.breakpoints.clear();
+            cpu.debug.watchpoints.clear();
+            return "Deleted all breakpoints and watchpoints".to_string();
+        }
+        Command::Delete(Some(addr)) => {
+            let removed = cpu.debug.breakpoints.remove(&(addr & !1)) as usize;
+            let watchpoints = cpu.debug.watchpoints.len();
+            cpu.debug.watchpoints.retain(|w| w.addr != addr);
+            let removed = removed + watchpoints - cpu.debug.watchpoints.len();
+            return format!("Deleted {} at {:08X}", removed, addr);
+        }
+        Command::BackCycles(n) | Command::BackCommit(n) => {
+            let result = match command {
+                Command::BackCycles(_) => cpu.step_back_cycles(n),
+                _ => cpu.step_back_instructions(n),
+            };
+            return match result {
+                Ok(()) => format!(
+                    "Back to cycle {}, {} instructions committed",
+                    cpu.epoch, cpu.instructions_committed
+                ),
+                Err(e) => e,
+            };
+        }
+        Command::Speed(_) => unreachable!("the console sets its own speed"),
+        Command::Mem(addr) => {
+            cpu.mem_goto(addr);
+            return format!("Memory at {}", describe_address(addr, &cpu.arch_state().mem));
+        }
+        Command::Follow(reg) => {
+            cpu.mem_follow(reg);
+            return format!("Memory follows {}", Registers::reg_id_to_str(reg));
+        }
+        Command::Info => {
+            let mem = &cpu.arch_state().mem;
+            let mut points: Vec<String> = cpu
+                .debug
+                .breakpoints
+                .iter()
+                .map(|addr| format!("break {}", describe_address(*addr, mem)))
+                .collect();
+            points.sort();
+            points.extend(
+                cpu.debug
+                    .watchpoints
+                    .iter()
+                    .map(|w| format!("{:?} watch {:08X}+{}", w.kind, w.addr, w.len)),
+            );
+            if points.is_empty() {
+                return "No breakpoints or watchpoints".to_string();
+            }
+            return points.join(", ");
+        }
+        Command::Continue => cpu.run_until_stop(|_| keep_going(false)),
+        Command::Cycles(n) => cpu.run_until_stop(|cpu| keep_going(cpu.epoch - start_cycle >= n)),
+        Command::Commit(n) => {
+            cpu.run_until_stop(|cpu| keep_going(cpu.instructions_committed - start_committed >= n))
+        }
+        Command::Until { reg, op, value } => cpu.run_until_stop(|cpu| {
+            let current = cpu.arch_state().regs.get(reg);
+            keep_going(op.holds(current, value))
+        }),
+    };
+
+    let ran = format!(
+        "after {} cycles, {} instructions",
+        cpu.epoch.saturating_sub(start_cycle),
+        cpu.instructions_committed.saturating_sub(start_committed)
+    );
+    match stop {
+        Some(stop) => stop_message(cpu, stop, &ran),
+        None if by_user => format!("Interrupted {}", ran),
+        None => match command {
+            Command::Until { reg, .. } => format!(
+                "{} is {:08X} {}",
+                Registers::reg_id_to_str(reg),
+                cpu.arch_state().regs.get(reg),
+                ran
+            ),
+            _ => format!("Stopped {}", ran),
+        },
+    }
+}
+
+/// Why a run stopped, ran saying how far it got
+fn stop_message(cpu: &OoOSpeculative, stop: StopReason, ran: &str) -> String {
+    let mem = &cpu.arch_state().mem;
+    match stop {
+        StopReason::Breakpoint(pc) => {
+            format!("Breakpoint at {} {}", describe_address(pc, mem), ran)
+        }
+        StopReason::Watchpoint(watchpoint, access) => format!(
+            "{:?} watchpoint at {:08X}: {} {} bytes at {:08X}, pc {} {}",
+            watchpoint.kind,
+            watchpoint.addr,
+            if access.write { "write of" } else { "read of" },
+            access.len,
+            access.addr,
+            describe_address(cpu.arch_state().regs.pc, mem),
+            ran
+        ),
+        StopReason::Halted(code) => format!("Program exited with code {} {}", code, ran),
+        StopReason::Step => format!("Stopped {}", ran),
+    }
+}
+
+/// The line at the bottom of the TUI
+pub struct Console {
+    /// What's being typed, none while the command line is closed
+    pub input: Option<String>,
+    /// The result of the last command
+    pub message: String,
+    /// Where playing started from, cycle and instructions committed, none while paused
+    playing: Option<(usize, usize)>,
+    /// Cycles a frame to play at
+    pub speed: usize,
+    /// Whether the help overlay is up
+    pub help: bool,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self {
+            input: None,
+            message: String::new(),
+            playing: None,
+            speed: 1,
+            help: false,
+        }
+    }
+}
+
+impl Console {
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    pub fn play_pause(&mut self, cpu: &OoOSpeculative) {
+        self.playing = match self.playing {
+            Some(_) => None,
+            None => Some((cpu.epoch, cpu.instructions_committed)),
+        };
+        self.message.clear();
+    }
+
+    /// Carry out a command line, the console's own commands here
+    pub fn run(
+        &mut self,
+        cpu: &mut OoOSpeculative,
+        line: &str,
+        interrupted: impl FnMut() -> bool,
+    ) {
+        self.message = match parse(line, &cpu.arch_state().mem) {
+            Ok(Command::Speed(n)) => {
+                self.speed = n;
+                format!("Playing at {} cycles a frame", n)
+            }
+            Ok(command) => execute(cpu, command, interrupted),
+            Err(e) => e,
+        };
+    }
+
+    /// Twice or half as fast
+    pub fn faster(&mut self) {
+        self.speed = (self.speed * 2).min(MAX_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed / 2).max(1);
+    }
+
+    /// Run a frame's cycles, pausing at a stop
+    pub fn play_frame(&mut self, cpu: &mut OoOSpeculative) {
+        let Some((start_cycle, start_committed)) = self.playing else {
+            return;
+        };
+        let frame_start = cpu.epoch;
+        let speed = self.speed;
+        if let Some(stop) = cpu.run_until_stop(|cpu| cpu.epoch - frame_start < speed) {
+            self.playing = None;
+            // Stepping back while playing can go behind where it started
+            let ran = format!(
+                "after {} cycles, {} instructions",
+                cpu.epoch.saturating_sub(start_cycle),
+                cpu.instructions_committed.saturating_sub(start_committed)
+            );
+            self.message = stop_message(cpu, stop, &ran);
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.input = Some(String::new());
+    }
+
+    /// Edit the command, returning it once Enter is pressed
+    pub fn key(&mut self, key: KeyCode) -> Option<String> {
+        let input = self.input.as_mut()?;
+        match key {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Enter => return self.input.take(),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        let line = match &self.input {
+            Some(input) => format!(":{}_", input),
+            None if self.playing.is_some() => format!(
+                "Playing at {} cycles a frame, space to pause, +/- for faster or slower",
+                self.speed
+            ),
+            None if self.message.is_empty() => ": for commands".to_string(),
+            None => self.message.clone(),
+        };
+        frame.render_widget(Paragraph::new(line), area);
+    }
+}
+
+#[cfg(test)]
+mod console_tests {
+    use super::*;
+    use crate::cpu::test_cpu;
+
+    #[test]
+    fn registers() {
+        assert_eq!(parse_register("r0"), Some(0));
+        assert_eq!(parse_register("R12"), Some(12));
+        assert_eq!(parse_register("pc"), Some(15));
+        assert_eq!(parse_register("z"), Some(17));
+        assert_eq!(parse_register("r16"), None);
+        assert_eq!(parse_register("x1"), None);
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(Comparison::parse(">=").unwrap().holds(5, 5));
+        assert!(Comparison::parse("!=").unwrap().holds(4, 5));
+        assert!(!Comparison::parse("<").unwrap().holds(5, 4));
+        assert_eq!(Comparison::parse("=>"), None);
+    }
+
+    #[test]
+    fn watch_length() {
+        let cpu = test_cpu(&[]);
+        let watch = |line| match parse(line, &cpu.arch_state().mem) {
+            Ok(Command::Watch(watchpoint)) => Ok(watchpoint.len),
+            Ok(command) => panic!("{:?} is not a watch", command),
+            Err(e) => Err(e),
+        };
+        assert_eq!(watch("watch 0x20000000"), Ok(4));
+        assert_eq!(watch("watch 0x20000000 2"), Ok(2));
+        assert!(watch("watch 0x20000000 1O").is_err());
+        assert!(watch("watch 0x20000000 0").is_err());
+        assert!(watch("watch 0xFFFFFFFF 4").is_err());
+    }
+}
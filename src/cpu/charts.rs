@@ -0,0 +1,156 @@
+//! The charts pane, sparklines of the last cycles' IPC, occupancies and branch
+//! accuracy. Samples are taken once a cycle just after commit, like the stats,
+//! but only while the TUI is up to show them.
+use super::*;
+use ratatui::widgets::Sparkline;
+
+/// Cycles of samples kept, more than the pane is ever wide
+const CHART_CYCLES: usize = 512;
+/// IPC and branch accuracy are averaged over this many cycles, as a cycle of
+/// either on its own only ever shows a few values
+const ROLLING: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct ChartSample {
+    epoch: usize,
+    committed: usize,
+    rob: usize,
+    rs: usize,
+    load_queue: usize,
+    /// The run's counts so far, so a window's branches are the difference
+    mispredicts: u32,
+    correct_predicts: u32,
+}
+
+impl<'a> OoOSpeculative<'a> {
+    pub fn enable_charts(&mut self) {
+        self.charts = Some(VecDeque::with_capacity(CHART_CYCLES));
+    }
+
+    pub(super) fn sample_charts(&mut self, committed: usize) {
+        let Some(charts) = self.charts.as_mut() else {
+            return;
+        };
+        if charts.len() == CHART_CYCLES {
+            charts.pop_front();
+        }
+        charts.push_back(ChartSample {
+            epoch: self.epoch,
+            committed,
+            rob: self.rob.occupancy(),
+            rs: [
+                &self.rs_alu_shift,
+                &self.rs_mul,
+                &self.rs_control,
+                &self.rs_ls,
+            ]
+            .iter()
+            .map(|rs| rs.occupancy())
+            .sum(),
+            load_queue: self.load_queue.len(),
+            mispredicts: self.mispredicts,
+            correct_predicts: self.correct_predicts,
+        });
+    }
+
+    /// Drop the samples from after a cycle gone back to, they'll be taken again
+    pub(super) fn rewind_charts(&mut self) {
+        let epoch = self.epoch;
+        if let Some(charts) = self.charts.as_mut() {
+            while charts.back().is_some_and(|sample| sample.epoch > epoch) {
+                charts.pop_back();
+            }
+        }
+    }
+
+    pub(super) fn render_charts(&self, frame: &mut Frame, area: Rect) {
+        use Constraint::{Fill, Length};
+
+        let Some(charts) = &self.charts else {
+            return;
+        };
+        let [title_area, area] = Layout::vertical([Length(1), Fill(1)]).areas(area);
+        let [label_area, chart_area] = Layout::horizontal([Length(22), Fill(1)]).areas(area);
+        let width = chart_area.width as usize;
+        frame.render_widget(
+            Block::new()
+                .title(format!("Last {} cycles", width.min(charts.len())))
+                .title_alignment(Alignment::Center),
+            title_area,
+        );
+
+        // Each point of IPC and branch accuracy is over the cycles up to it
+        let start = charts.len().saturating_sub(width);
+        let first = |n: usize| (n + 1).saturating_sub(ROLLING);
+        let ipc: Vec<u64> = (start..charts.len())
+            .map(|n| {
+                let first = first(n);
+                let committed: usize = charts.range(first..=n).map(|s| s.committed).sum();
+                (committed * 100 / (n + 1 - first)) as u64
+            })
+            .collect();
+        let accuracy: Vec<Option<u64>> = (start..charts.len())
+            .map(|n| {
+                let last = &charts[n];
+                // Counted from before the window, so its first cycle's branches count
+                let (mispredicts, correct) = match first(n).checked_sub(1) {
+                    Some(before) => (charts[before].mispredicts, charts[before].correct_predicts),
+                    None => (0, 0),
+                };
+                let correct = last.correct_predicts.saturating_sub(correct);
+                let branches = correct + last.mispredicts.saturating_sub(mispredicts);
+                (branches > 0).then(|| (correct * 100 / branches) as u64)
+            })
+            .collect();
+        let recent = charts.range(start..);
+        let rob: Vec<u64> = recent.clone().map(|s| s.rob as u64).collect();
+        let rs: Vec<u64> = recent.clone().map(|s| s.rs as u64).collect();
+        let load_queue: Vec<u64> = recent.map(|s| s.load_queue as u64).collect();
+
+        let latest = charts.back();
+        let now = |value: fn(&ChartSample) -> usize| latest.map_or(0, value);
+        let rs_size = N_ALUSHIFT_RS + N_MUL_RS + N_CNTRL_RS + N_LS_RS;
+        let labels = [
+            (
+                format!("IPC, {} cycles", ROLLING),
+                format!("{:.2}", *ipc.last().unwrap_or(&0) as f64 / 100.0),
+            ),
+            (
+                "ROB".to_string(),
+                format!("{}/{}", now(|s| s.rob), ROB_ENTRIES),
+            ),
+            ("RS".to_string(), format!("{}/{}", now(|s| s.rs), rs_size)),
+            (
+                "Load queue".to_string(),
+                format!("{}/{}", now(|s| s.load_queue), LQ_SIZE),
+            ),
+            (
+                "Branch accuracy".to_string(),
+                match accuracy.last().copied().flatten() {
+                    Some(percent) => format!("{}%", percent),
+                    None => "-".to_string(),
+                },
+            ),
+        ]
+        .map(|(name, value)| format!("{:<15} {:>5}", name, value));
+        let charts = [
+            Sparkline::default().data(&ipc).max((N_ISSUE * 100) as u64),
+            Sparkline::default().data(&rob).max(ROB_ENTRIES as u64),
+            Sparkline::default().data(&rs).max(rs_size as u64),
+            Sparkline::default().data(&load_queue).max(LQ_SIZE as u64),
+            Sparkline::default().data(&accuracy).max(100),
+        ];
+
+        let rows = Layout::vertical([Length(2); 5]);
+        let label_rows: [Rect; 5] = rows.areas(label_area);
+        let chart_rows: [Rect; 5] = rows.areas(chart_area);
+        for ((label, chart), (label_row, chart_row)) in labels
+            .into_iter()
+            .zip(charts)
+            .zip(label_rows.into_iter().zip(chart_rows))
+        {
+            frame.render_widget(Paragraph::new(label), label_row);
+            frame.render_widget(chart, chart_row);
+        }
+    }
+}
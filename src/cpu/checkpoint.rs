@@ -0,0 +1,111 @@
+//! Saving the whole machine to a file and resuming from it, so a long program can
+//! be run up to the interesting part once and the rest simulated many times. A
+//! checkpoint is the same snapshot stepping back uses, then memory, then what the
+//! host side keeps for the guest. Tracers and host files aren't saved, so a
+//! program can't be checkpointed with files open. Input isn't saved either, only
+//! how much of the guest stdin and UART input had been read, and resuming skips
+//! that much of the same input.
+use super::history::Snapshot;
+use super::*;
+use crate::checkpoint::{persist_fields, CheckpointError, Input, Persist, MAGIC, VERSION};
+
+persist_fields!(CDBRecord {
+    is_branch_target,
+    valid,
+    rob_number,
+    result,
+    aspr_update,
+    halt
+});
+persist_fields!(LoadQueueEntry {
+    address,
+    rob_entry,
+    load_type
+});
+persist_fields!(InstructionQueueEntry {
+    i,
+    pc,
+    predicted_taken,
+    ends_instruction,
+    seq
+});
+persist_fields!(FetchQueueEntry {
+    pc,
+    i,
+    predicted_taken,
+    cycle
+});
+
+impl Persist for StallReason {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            StallReason::FullRob => 0u8.save(out),
+            StallReason::IssueRSFull(issue_type) => {
+                1u8.save(out);
+                issue_type.save(out);
+            }
+            StallReason::IStall => 2u8.save(out),
+        }
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(match u8::load(input)? {
+            0 => StallReason::FullRob,
+            1 => StallReason::IssueRSFull(IssueType::load(input)?),
+            2 => StallReason::IStall,
+            _ => return Err(CheckpointError::Invalid("stall reason")),
+        })
+    }
+}
+
+impl<'a> OoOSpeculative<'a> {
+    pub fn save_checkpoint(&self, path: &str) -> Result<(), CheckpointError> {
+        if self.host.has_open_files() {
+            return Err(CheckpointError::FilesOpen);
+        }
+        let mut out = MAGIC.to_vec();
+        VERSION.save(&mut out);
+        self.snapshot().save(&mut out);
+        self.state.mem.save_contents(&mut out);
+        self.host.last_errno.save(&mut out);
+        self.host.stdin_read().save(&mut out);
+        self.uart.consumed().save(&mut out);
+        std::fs::write(path, out)?;
+        Ok(())
+    }
+
+    /// Resume from a checkpoint of the program already loaded
+    pub fn load_checkpoint(&mut self, path: &str) -> Result<(), CheckpointError> {
+        let bytes = std::fs::read(path)?;
+        let mut input = Input::new(&bytes);
+        if input.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(CheckpointError::NotACheckpoint);
+        }
+        let version = u32::load(&mut input)?;
+        if version != VERSION {
+            return Err(CheckpointError::Version(version));
+        }
+        let snapshot = Snapshot::load(&mut input)?;
+        self.state.mem.load_contents(&mut input)?;
+        let last_errno = u32::load(&mut input)?;
+        let stdin_read = u64::load(&mut input)?;
+        let uart_consumed = u64::load(&mut input)?;
+        if !input.is_empty() {
+            return Err(CheckpointError::Invalid("length"));
+        }
+
+        self.restore(snapshot);
+        self.host.last_errno = last_errno;
+        if !self.host.skip_stdin(stdin_read)? || !self.uart.skip_input(uart_consumed) {
+            return Err(CheckpointError::InputTooShort);
+        }
+        // Snapshots and chart samples from before are no use now
+        if self.history.is_some() {
+            self.enable_history();
+        }
+        if self.charts.is_some() {
+            self.enable_charts();
+        }
+        Ok(())
+    }
+}
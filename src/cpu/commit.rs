@@ -1,7 +1,10 @@
 use super::*;
 use crate::binary::unsigned_to_signed_bitcast;
 use crate::components::ROB::ROBStatus::EMPTY;
+use crate::cpu::exception::is_exc_return;
+use crate::cpu::semihosting::SEMIHOSTING_BKPT;
 use crate::decode::IT::*;
+use crate::model::UART_IRQ;
 
 impl<'a> OoOSpeculative<'a> {
     pub(super) fn commit(&mut self) {
@@ -14,10 +17,17 @@ impl<'a> OoOSpeculative<'a> {
             self.halt = Some(unsigned_to_signed_bitcast(self.state.regs.gp[0]))
         }
 
+        // Anything committing means the ROB has refilled since the last flush
+        self.refill = None;
+        let mispredicts_before = self.mispredicts;
+
         let predicted_taken = head.predicted_taken;
 
         let mut string_info = String::new();
 
+        // The architectural pc after this instruction
+        let mut next_pc = head.pc;
+
         match head.i.it {
             // Maybe taken
             B => {
@@ -36,6 +46,7 @@ impl<'a> OoOSpeculative<'a> {
                         self.spec_pc = head.target_address - 1;
                         self.mispredicts += 1;
                         self.flush_on_mispredict();
+                        self.refill = Some(CpiCategory::MispredictRecovery);
                     } else {
                         self.correct_predicts += 1;
                     }
@@ -47,11 +58,16 @@ impl<'a> OoOSpeculative<'a> {
                         self.spec_pc = head.pc;
                         self.mispredicts += 1;
                         self.flush_on_mispredict();
+                        self.refill = Some(CpiCategory::MispredictRecovery);
                     } else {
                         self.correct_predicts += 1;
                     }
                 }
 
+                if taken {
+                    next_pc = head.target_address - 1;
+                }
+
                 string_info += &format!(
                     "pred: {} actual: {} target: {} ",
                     predicted_taken,
@@ -63,10 +79,12 @@ impl<'a> OoOSpeculative<'a> {
 
             // Always Taken, so branch is mispredicted in "not taken"
             BL => {
+                next_pc = head.target_address;
                 if !predicted_taken || PREDICT == PredictionAlgorithms::Stall  {
                     self.spec_pc = head.target_address;
                     self.mispredicts += 1;
                     self.flush_on_mispredict();
+                    self.refill = Some(CpiCategory::MispredictRecovery);
                 } else {
                     self.correct_predicts += 1;
                 }
@@ -74,27 +92,21 @@ impl<'a> OoOSpeculative<'a> {
 
             // Always requires a flush
             BX | BLX => {
-                self.spec_pc = (head.target_address >> 1) << 1;
+                next_pc = (head.target_address >> 1) << 1;
+                self.spec_pc = next_pc;
                 self.flush_on_mispredict();
             }
 
             SVC => {
-                match head.i.immu {
-                    1 => {
-                        let mut addr = head.value;
-                        loop {
-                            let c = self.state.mem.get_byte_nolog(addr);
-                            if c == 0 {
-                                break;
-                            }
-                            self.output += &format!("{}", c as char);
-                            addr += 1;
-                        }
-                    },
-                    3 => {
-                        self.output += &format!("{}", head.value);
-                    }
-                    _ => {},
+                self.supervisor_call(head.i.immu);
+                self.fetch_stall = false;
+            }
+
+            BKPT => {
+                if head.i.immu == SEMIHOSTING_BKPT {
+                    self.semihost();
+                } else {
+                    panic!("Breakpoint #{} hit at {:08X?}", head.i.immu, head.pc - 2)
                 }
                 self.fetch_stall = false;
             }
@@ -103,27 +115,7 @@ impl<'a> OoOSpeculative<'a> {
         }
 
         match head.dest {
-            ROBEntryDest::Address(addr) => match head.i.it {
-                STRImm | STRReg => {
-                    if let Err(e) = self.state.mem.set_word(addr, head.value) {
-                        panic!("{:?}: attempt to set halfword at {:08X?}", e, addr)
-                    }
-                }
-
-                STRHImm | STRHReg => {
-                    if let Err(e) = self.state.mem.set_halfword(addr, head.value as u16) {
-                        panic!("{:?}: attempt to set halfword at {:08X?}", e, addr)
-                    }
-                }
-
-                STRBImm | STRBReg => {
-                    if let Err(e) = self.state.mem.set_byte(addr, head.value as u8) {
-                        panic!("{:?}: attempt to set halfword at {:08X?}", e, addr)
-                    }
-                }
-
-                _ => unreachable!(),
-            },
+            ROBEntryDest::Address(addr) => self.store(head.i.it, addr, head.value),
             ROBEntryDest::AwaitingAddress => unreachable!(),
             ROBEntryDest::Register(rn) => {
                 self.state.regs.set(rn, head.value);
@@ -149,6 +141,17 @@ impl<'a> OoOSpeculative<'a> {
             _ => {}
         }
 
+        if head.i.it == BX && is_exc_return(head.target_address) {
+            self.exception_return();
+        } else {
+            self.state.regs.pc = next_pc;
+        }
+
+        self.profile_commit(self.mispredicts != mispredicts_before);
+        if head.ends_instruction {
+            self.update_call_stack(head.i.it, head.pc, self.state.regs.pc);
+        }
+
         (self.log_fn)(format!(
             "{}: {:08X?} {} => {:08X?} =# {:08X?}  {}",
             self.instructions_committed,
@@ -158,15 +161,60 @@ impl<'a> OoOSpeculative<'a> {
             head.target_address,
             string_info
         ));
+
+        if head.ends_instruction && !head.halt && self.interrupt_pending() {
+            self.take_exception(16 + UART_IRQ, self.state.regs.pc);
+        }
+
+        self.trace(PipelineEvent::Commit {
+            seq: head.seq,
+            store: matches!(head.dest, ROBEntryDest::Address(_)),
+            mispredicted: self.mispredicts != mispredicts_before,
+        });
         self.rob.clear_head_and_increment();
         self.instructions_committed += 1;
+        self.debug_commit(&head);
+    }
+
+    pub(super) fn store(&mut self, it: IT, addr: u32, value: u32) {
+        if self.uart.contains(addr) {
+            self.uart.write(addr, value);
+            return;
+        }
+        let len = match it {
+            STRImm | STRReg => {
+                if let Err(e) = self.state.mem.set_word(addr, value) {
+                    panic!("{:?}: attempt to set halfword at {:08X?}", e, addr)
+                }
+                4
+            }
+
+            STRHImm | STRHReg => {
+                if let Err(e) = self.state.mem.set_halfword(addr, value as u16) {
+                    panic!("{:?}: attempt to set halfword at {:08X?}", e, addr)
+                }
+                2
+            }
+
+            STRBImm | STRBReg => {
+                if let Err(e) = self.state.mem.set_byte(addr, value as u8) {
+                    panic!("{:?}: attempt to set halfword at {:08X?}", e, addr)
+                }
+                1
+            }
+
+            _ => unreachable!(),
+        };
+        self.mem_view.note_store(addr, len, self.epoch);
     }
 
     pub fn flush_on_mispredict(&mut self) {
+        let squashed_iq: Vec<u64> = self.iq.iter().map(|iqe| iqe.seq).collect();
         self.iq.clear();
         self.fb = [None; N_ISSUE];
         self.flushing = true;
         self.flush_delay = FLUSH_DELAY;
+        self.refill = Some(CpiCategory::Serialization);
         self.to_broadcast.clear();
         self.load_queue.clear();
         self.fetch_stall = false;
@@ -178,6 +226,11 @@ impl<'a> OoOSpeculative<'a> {
             self.rs_ls.flush_entries_corresponding_to_rob(flush);
             self.rs_mul.flush_entries_corresponding_to_rob(flush);
             self.rob.set_status(flush, EMPTY);
+            let seq = self.rob.get(flush).seq;
+            self.trace(PipelineEvent::Squash { seq });
+        }
+        for seq in squashed_iq {
+            self.trace(PipelineEvent::Squash { seq });
         }
     }
 }
@@ -0,0 +1,285 @@
+//! Top-down cycle accounting. Every cycle has N_ISSUE commit slots, and each slot
+//! either commits a mop or is charged to whatever kept the ROB head from committing.
+//! The slots add up to N_ISSUE times the cycle count, so dividing each category by
+//! N_ISSUE and the committed count splits the CPI into a stack.
+use super::*;
+use crate::checkpoint::{persist_fields, CheckpointError, Input, Persist};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CpiCategory {
+    Committed,
+    /// From the end of the flush after a mispredicted branch up to the next commit
+    MispredictRecovery,
+    /// The fixed FLUSH_DELAY penalty of any flush
+    FlushDelay,
+    RobFull,
+    RsFull(IssueType),
+    /// The head is a load that was held back by an older store whose address was
+    /// unknown or overlapped it, and hasn't caught up since
+    LoadWaitingOnStore,
+    /// The head is a load or store with its operands, still on its way through
+    /// the memory pipeline
+    MemoryLatency,
+    /// Nothing to commit and nothing holding the frontend back
+    FrontendStarvation,
+    /// Waiting behind SVC, BKPT, BX and BLX, or refilling after their flush
+    Serialization,
+    /// The head is waiting on operands or a functional unit
+    Execution,
+}
+
+impl CpiCategory {
+    pub const ALL: [CpiCategory; 13] = [
+        CpiCategory::Committed,
+        CpiCategory::MispredictRecovery,
+        CpiCategory::FlushDelay,
+        CpiCategory::RobFull,
+        CpiCategory::RsFull(IssueType::ALUSHIFT),
+        CpiCategory::RsFull(IssueType::MUL),
+        CpiCategory::RsFull(IssueType::LoadStore),
+        CpiCategory::RsFull(IssueType::Control),
+        CpiCategory::LoadWaitingOnStore,
+        CpiCategory::MemoryLatency,
+        CpiCategory::FrontendStarvation,
+        CpiCategory::Serialization,
+        CpiCategory::Execution,
+    ];
+}
+
+impl fmt::Display for CpiCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpiCategory::Committed => write!(f, "Committed"),
+            CpiCategory::MispredictRecovery => write!(f, "Mispredict recovery"),
+            CpiCategory::FlushDelay => write!(f, "Flush delay"),
+            CpiCategory::RobFull => write!(f, "ROB full"),
+            CpiCategory::RsFull(IssueType::ALUSHIFT) => write!(f, "RS full (ALU/shift)"),
+            CpiCategory::RsFull(IssueType::MUL) => write!(f, "RS full (mul)"),
+            CpiCategory::RsFull(IssueType::LoadStore) => write!(f, "RS full (load/store)"),
+            CpiCategory::RsFull(IssueType::Control) => write!(f, "RS full (control)"),
+            CpiCategory::LoadWaitingOnStore => write!(f, "Load waiting on store"),
+            CpiCategory::MemoryLatency => write!(f, "Memory latency"),
+            CpiCategory::FrontendStarvation => write!(f, "Frontend starvation"),
+            CpiCategory::Serialization => write!(f, "Serialization"),
+            CpiCategory::Execution => write!(f, "Execution"),
+        }
+    }
+}
+
+/// Commit slots charged to each category over the run
+#[derive(Default, Clone)]
+pub struct CpiStack {
+    slots: HashMap<CpiCategory, u64>,
+}
+
+impl CpiStack {
+    pub fn add(&mut self, category: CpiCategory, slots: u64) {
+        *self.slots.entry(category).or_default() += slots;
+    }
+
+    pub fn get(&self, category: CpiCategory) -> u64 {
+        self.slots.get(&category).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.slots.values().sum()
+    }
+
+    /// A table of slots, share of all slots and CPI contribution per category
+    pub fn report(&self, instructions: usize) -> String {
+        let total = self.total().max(1) as f64;
+        let per_instruction = (N_ISSUE * instructions.max(1)) as f64;
+        let mut report = format!(
+            "CPI stack ({} commit slots per cycle):\n{:<24}{:>12}{:>9}{:>9}\n",
+            N_ISSUE, "", "slots", "%", "CPI"
+        );
+        for category in CpiCategory::ALL {
+            let slots = self.get(category);
+            if slots == 0 && category != CpiCategory::Committed {
+                continue;
+            }
+            report += &format!(
+                "{:<24}{:>12}{:>8.2}%{:>9.3}\n",
+                category.to_string(),
+                slots,
+                100.0 * slots as f64 / total,
+                slots as f64 / per_instruction
+            );
+        }
+        report += &format!(
+            "{:<24}{:>12}{:>8.2}%{:>9.3}",
+            "Total",
+            self.total(),
+            100.0,
+            self.total() as f64 / per_instruction
+        );
+        report
+    }
+}
+
+impl<'a> OoOSpeculative<'a> {
+    /// Charge this cycle's commit slots, called straight after commit. Issue side
+    /// stalls are the ones recorded last cycle, as issue runs after commit
+    pub(super) fn account_cycle(&mut self, committed: usize) {
+        self.cpi.add(CpiCategory::Committed, committed as u64);
+        let lost = N_ISSUE.saturating_sub(committed) as u64;
+        self.profile_cycle(lost);
+        if lost > 0 {
+            let category = self.lost_slot_category();
+            self.cpi.add(category, lost);
+            if !self.flushing && !self.rob.is_empty() {
+                let seq = self.rob.get_head().seq;
+                self.trace(PipelineEvent::HeadStall { seq });
+            }
+        }
+        self.cycle_stalls.clear();
+    }
+
+    fn lost_slot_category(&self) -> CpiCategory {
+        if self.flushing {
+            return CpiCategory::FlushDelay;
+        }
+        if let Some(category) = self.refill {
+            return category;
+        }
+        if self.rob.is_empty() {
+            return if self.fetch_stall {
+                CpiCategory::Serialization
+            } else {
+                CpiCategory::FrontendStarvation
+            };
+        }
+
+        let head = self.rob.get_head();
+        if head.is_serializing() {
+            return CpiCategory::Serialization;
+        }
+        if get_issue_type(head.i.it) == IssueType::LoadStore {
+            // Nothing is older than the head to block it now, but a load held back
+            // by a store before it got here is still catching up
+            if self.blocked_loads.contains(&head.seq) {
+                return CpiCategory::LoadWaitingOnStore;
+            }
+            let waiting_on_operands = self
+                .rs_ls
+                .vec
+                .iter()
+                .any(|rs| rs.busy && rs.rob_dest == self.rob.head && rs.waiting_on_operands());
+            if !waiting_on_operands {
+                return CpiCategory::MemoryLatency;
+            }
+        }
+        if self.rob.is_full() || self.cycle_stalls.contains(&StallReason::FullRob) {
+            return CpiCategory::RobFull;
+        }
+        for stall in self.cycle_stalls.iter() {
+            if let StallReason::IssueRSFull(issue_type) = stall {
+                return CpiCategory::RsFull(*issue_type);
+            }
+        }
+        CpiCategory::Execution
+    }
+}
+
+/// Checkpoints store the position in `CpiCategory::ALL`
+impl Persist for CpiCategory {
+    fn save(&self, out: &mut Vec<u8>) {
+        let n = CpiCategory::ALL.iter().position(|c| c == self).unwrap();
+        (n as u8).save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        CpiCategory::ALL
+            .get(u8::load(input)? as usize)
+            .copied()
+            .ok_or(CheckpointError::Invalid("CPI category"))
+    }
+}
+
+persist_fields!(CpiStack { slots });
+
+#[cfg(test)]
+mod cpi_tests {
+    use super::*;
+    use crate::cpu::test_cpu;
+
+    /// LDR r0, [r1, #0]
+    const LOAD: u32 = 0x6808;
+    /// MULS r0, r1, r0
+    const MUL: u32 = 0x4348;
+
+    /// Put a mop in the ROB, returning its entry
+    fn issue(cpu: &mut OoOSpeculative, halfword: u32, seq: u64) -> usize {
+        let entry = cpu.rob.issue_receive(&InstructionQueueEntry {
+            i: decode(halfword),
+            pc: 0,
+            predicted_taken: false,
+            ends_instruction: true,
+            seq,
+        });
+        cpu.rob.issue_commit();
+        entry
+    }
+
+    fn queue_load(cpu: &mut OoOSpeculative, rob_entry: usize) {
+        cpu.load_queue.push_back(LoadQueueEntry {
+            address: 0x2000_0000,
+            rob_entry,
+            load_type: IT::LDRImm,
+        });
+    }
+
+    #[test]
+    fn load_waiting_on_store() {
+        // A blocked load behind the head isn't what the head is waiting for
+        let mut cpu = test_cpu(&[]);
+        issue(&mut cpu, MUL, 1);
+        let load = issue(&mut cpu, LOAD, 2);
+        queue_load(&mut cpu, load);
+        cpu.blocked_loads = vec![2];
+        assert_eq!(cpu.lost_slot_category(), CpiCategory::Execution);
+
+        let mut cpu = test_cpu(&[]);
+        let load = issue(&mut cpu, LOAD, 1);
+        queue_load(&mut cpu, load);
+        cpu.blocked_loads = vec![1];
+        assert_eq!(cpu.lost_slot_category(), CpiCategory::LoadWaitingOnStore);
+    }
+
+    #[test]
+    fn memory_latency() {
+        let mut cpu = test_cpu(&[]);
+        let load = issue(&mut cpu, LOAD, 1);
+        queue_load(&mut cpu, load);
+        assert_eq!(cpu.lost_slot_category(), CpiCategory::MemoryLatency);
+
+        // Its address still to work out, but with everything it needs for that
+        let mut cpu = test_cpu(&[]);
+        let load = issue(&mut cpu, LOAD, 1);
+        cpu.rs_ls.vec[0] = RS {
+            busy: true,
+            j: RSData::Data(0x2000_0000),
+            k: RSData::Data(0),
+            l: RSData::None,
+            i: decode(LOAD),
+            rob_dest: load,
+        };
+        assert_eq!(cpu.lost_slot_category(), CpiCategory::MemoryLatency);
+    }
+
+    #[test]
+    fn execution() {
+        let mut cpu = test_cpu(&[]);
+        let load = issue(&mut cpu, LOAD, 1);
+        cpu.rs_ls.vec[0] = RS {
+            busy: true,
+            j: RSData::ROB(5, 1),
+            k: RSData::Data(0),
+            l: RSData::None,
+            i: decode(LOAD),
+            rob_dest: load,
+        };
+        assert_eq!(cpu.lost_slot_category(), CpiCategory::Execution);
+    }
+}
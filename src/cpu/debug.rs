@@ -0,0 +1,257 @@
+//! Stopping the simulation for a debugger. Stops happen at commit, once the last
+//! mop of an instruction has committed, so the architectural state a debugger sees
+//! is exact. The rest of that cycle's commit slots are given up when a stop hits.
+use super::*;
+use crate::components::ROB::ROBEntry;
+use crate::decode::IT::*;
+use crate::model::{MemError, ASPR};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn hit_by(&self, access: &MemAccess) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Read => !access.write,
+            WatchKind::Write => access.write,
+            WatchKind::Access => true,
+        };
+        // In u64 so ranges at the top of memory don't overflow
+        let end = |addr: u32, len: u32| addr as u64 + len as u64;
+        kind_matches
+            && (access.addr as u64) < end(self.addr, self.len)
+            && (self.addr as u64) < end(access.addr, access.len)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: u32,
+    pub len: u32,
+    pub write: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The instruction asked for by a single step committed
+    Step,
+    /// The next instruction to commit is at a breakpoint
+    Breakpoint(u32),
+    Watchpoint(Watchpoint, MemAccess),
+    /// The program ended with this exit code
+    Halted(i32),
+}
+
+#[derive(Default)]
+pub struct DebugState {
+    pub breakpoints: HashSet<u32>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Stop after the next instruction commits
+    pub step: bool,
+    pub(super) stop: Option<StopReason>,
+    /// Accesses by the mops of the instruction committing so far
+    accesses: Vec<MemAccess>,
+}
+
+/// Bytes and direction of a load or store mop
+fn access_of(it: IT) -> Option<(u32, bool)> {
+    match it {
+        LDRImm | LDRReg => Some((4, false)),
+        LDRHImm | LDRHReg => Some((2, false)),
+        LDRBImm | LDRBReg => Some((1, false)),
+        LDRSH => Some((2, false)),
+        LDRSB => Some((1, false)),
+        STRImm | STRReg => Some((4, true)),
+        STRHImm | STRHReg => Some((2, true)),
+        STRBImm | STRBReg => Some((1, true)),
+        _ => None,
+    }
+}
+
+impl<'a> OoOSpeculative<'a> {
+    /// Check for a stop after a mop commits. Loads have their address in
+    /// target_address by now and stores in their destination
+    pub(super) fn debug_commit(&mut self, head: &ROBEntry) {
+        if !self.debug.watchpoints.is_empty() {
+            if let Some((len, write)) = access_of(head.i.it) {
+                let addr = match head.dest {
+                    ROBEntryDest::Address(addr) => addr,
+                    _ => head.target_address,
+                };
+                self.debug.accesses.push(MemAccess { addr, len, write });
+            }
+        }
+        if !head.ends_instruction {
+            return;
+        }
+
+        let accesses = std::mem::take(&mut self.debug.accesses);
+        let watch_hit = accesses.iter().find_map(|access| {
+            self.debug
+                .watchpoints
+                .iter()
+                .find(|watchpoint| watchpoint.hit_by(access))
+                .map(|watchpoint| (*watchpoint, *access))
+        });
+        let pc = self.state.regs.pc;
+        self.debug.stop = if let Some((watchpoint, access)) = watch_hit {
+            Some(StopReason::Watchpoint(watchpoint, access))
+        } else if self.debug.breakpoints.contains(&pc) {
+            Some(StopReason::Breakpoint(pc))
+        } else if self.debug.step {
+            Some(StopReason::Step)
+        } else {
+            None
+        };
+    }
+
+    /// Run until a stop, the program halting, or keep_going saying no, which
+    /// returns None. Stops are checked against the pc after each instruction, so
+    /// a breakpoint at the pc the run starts from doesn't stop it straight away
+    pub fn run_until_stop(
+        &mut self,
+        mut keep_going: impl FnMut(&Self) -> bool,
+    ) -> Option<StopReason> {
+        loop {
+            if let Some(code) = self.halt {
+                return Some(StopReason::Halted(code));
+            }
+            self.tick();
+            if let Some(stop) = self.debug.stop.take() {
+                return Some(stop);
+            }
+            if !keep_going(self) {
+                return None;
+            }
+        }
+    }
+
+    /// Commit exactly one more instruction
+    pub fn step_instruction(&mut self) -> StopReason {
+        self.debug.step = true;
+        let stop = self.run_until_stop(|_| true);
+        self.debug.step = false;
+        stop.unwrap_or(StopReason::Step)
+    }
+
+    /// The architectural state, exact while stopped
+    pub fn arch_state(&self) -> &ProcessorState {
+        &self.state
+    }
+
+    pub fn set_register(&mut self, index: u8, value: u32) {
+        self.state.regs.set(index, value);
+        self.flush_pipeline();
+    }
+
+    pub fn set_flags(&mut self, apsr: ASPR) {
+        self.state.regs.apsr = apsr;
+        self.flush_pipeline();
+    }
+
+    /// Unlike guest stores, a debugger may write flash too, to patch code
+    pub fn write_memory(&mut self, addr: u32, bytes: &[u8]) -> Result<(), MemError> {
+        for (n, byte) in bytes.iter().enumerate() {
+            let addr = addr.wrapping_add(n as u32);
+            self.state.mem.get_byte(addr)?;
+            self.state.mem.set_byte_nolog(addr, *byte);
+        }
+        self.flush_pipeline();
+        Ok(())
+    }
+
+    /// Throw away everything in flight and restart fetch at the architectural pc,
+    /// needed after a debugger changes registers or memory under the pipeline
+    pub(super) fn flush_pipeline(&mut self) {
+        let mut squashed: Vec<u64> = self.iq.iter().map(|iqe| iqe.seq).collect();
+        if !self.rob.is_empty() {
+            let mut i = self.rob.head;
+            loop {
+                let entry = self.rob.get(i);
+                if entry.status == ROBStatus::EMPTY {
+                    break;
+                }
+                squashed.push(entry.seq);
+                i = (i + 1) % ROB_ENTRIES;
+                if i == self.rob.head {
+                    break;
+                }
+            }
+        }
+        for seq in squashed {
+            self.trace(PipelineEvent::Squash { seq });
+        }
+
+        self.rob = ROB::new();
+        self.rs_alu_shift.empty();
+        self.rs_mul.empty();
+        self.rs_control.empty();
+        self.rs_ls.empty();
+        self.iq.clear();
+        self.fb = [None; N_ISSUE];
+        self.to_broadcast.clear();
+        self.load_queue.clear();
+        self.cdb.clear();
+        self.fetch_stall = false;
+        self.flushing = false;
+        self.flush_delay = 0;
+        self.spec_pc = self.state.regs.pc;
+    }
+}
+
+#[cfg(test)]
+mod debug_tests {
+    use super::*;
+    use crate::cpu::test_cpu;
+
+    #[test]
+    fn watch_top_of_memory() {
+        let watchpoint = Watchpoint {
+            addr: 0xFFFF_FFF0,
+            len: 8,
+            kind: WatchKind::Access,
+        };
+        let access = |addr| MemAccess {
+            addr,
+            len: 4,
+            write: false,
+        };
+        assert!(watchpoint.hit_by(&access(0xFFFF_FFF4)));
+        assert!(!watchpoint.hit_by(&access(0xFFFF_FFF8)));
+        // Ends past the top of memory
+        assert!(!watchpoint.hit_by(&access(0xFFFF_FFFC)));
+    }
+
+    #[test]
+    fn watch_signed_load() {
+        // movs r1, #0x20; lsls r1, r1, #24; movs r2, #1; ldrsb r0, [r1, r2]; svc 0
+        let mut cpu = test_cpu(&[0x2120, 0x0609, 0x2201, 0x5688, 0xDF00]);
+        let watchpoint = Watchpoint {
+            addr: 0x2000_0000,
+            len: 4,
+            kind: WatchKind::Read,
+        };
+        cpu.debug.watchpoints.push(watchpoint);
+
+        let access = MemAccess {
+            addr: 0x2000_0001,
+            len: 1,
+            write: false,
+        };
+        assert_eq!(
+            cpu.run_until_stop(|_| true),
+            Some(StopReason::Watchpoint(watchpoint, access))
+        );
+    }
+}
@@ -3,12 +3,30 @@ use super::*;
 impl<'a> OoOSpeculative<'a> {
     pub(super) fn decode(&mut self) {
         for j in 0..N_ISSUE {
-            if let Some(FetchQueueEntry { pc, i, predicted_taken }) = self.fb[j] {
+            if let Some(FetchQueueEntry { pc, i, predicted_taken, cycle }) = self.fb[j] {
+                // pc is already past the instruction
+                let address = pc - if is_32_bit(i) { 4 } else { 2 };
                 let i = decode(i);
                 let i_as_mops = decode2(i);
 
-                for mop in i_as_mops {
-                    self.iq.push_back(InstructionQueueEntry { i: mop, pc, predicted_taken });
+                let n_mops = i_as_mops.len();
+                for (k, mop) in i_as_mops.into_iter().enumerate() {
+                    let seq = self.next_seq;
+                    self.next_seq += 1;
+                    self.iq.push_back(InstructionQueueEntry {
+                        i: mop,
+                        pc,
+                        predicted_taken,
+                        ends_instruction: k == n_mops - 1,
+                        seq,
+                    });
+                    self.trace(PipelineEvent::Decode {
+                        seq,
+                        pc: address,
+                        upc: k,
+                        i: mop,
+                        fetched: cycle,
+                    });
                 }
 
                 // Consume from buffer
@@ -0,0 +1,154 @@
+//! The code pane, memory disassembled an instruction a line with the functions'
+//! names above their first instructions. It follows the commit pc until it's
+//! scrolled, and marks breakpoints, the commit and fetch pcs and the instructions
+//! in flight.
+//!
+//! Thumb code can only be decoded forwards, so the pane starts from the function
+//! the commit pc is in, or a little before it, and walks up to it to find where
+//! the instructions before it start.
+use super::*;
+use std::collections::HashSet;
+
+/// How far back to start walking to the commit pc outside any function
+const WALK_BACK: u32 = 64;
+
+impl<'a> OoOSpeculative<'a> {
+    /// An address in the instruction being committed, or the next one to commit.
+    /// The ROB has the pc after each instruction, which is in the instruction
+    /// one back
+    pub(super) fn commit_address(&self) -> u32 {
+        if self.rob.is_empty() {
+            self.state.regs.pc
+        } else {
+            self.rob.get_head().pc - 1
+        }
+    }
+
+    /// The instruction at addr, if it's all in memory
+    fn code_at(&self, addr: u32) -> Option<u32> {
+        let mem = &self.state.mem;
+        let first = mem.get_halfword(addr).ok()?;
+        if is_32_bit(first as u32) {
+            mem.get_halfword(addr + 2).ok()?;
+        }
+        Some(mem.get_instruction(addr))
+    }
+
+    fn code_size(&self, addr: u32) -> u32 {
+        match self.code_at(addr) {
+            Some(i) if is_32_bit(i) => 4,
+            _ => 2,
+        }
+    }
+
+    /// A branch target as a function name and offset
+    fn code_label(&self, target: u32) -> String {
+        match self.state.mem.function_containing(target) {
+            Some((start, name)) if start == target => name.clone(),
+            Some((start, name)) => format!("{}+{:#X}", name, target - start),
+            None => format!("{:08X}", target),
+        }
+    }
+
+    /// Where the pane starts when following the commit pc, rows lines above it
+    fn code_follow_top(&self, rows: usize) -> u32 {
+        let target = self.commit_address() & !1;
+        let start = match self.state.mem.function_containing(target) {
+            Some((start, _)) if target - start <= 4096 => start,
+            _ => target.saturating_sub(WALK_BACK),
+        };
+        let mut starts = Vec::new();
+        let mut addr = start;
+        while addr <= target {
+            starts.push(addr);
+            addr += self.code_size(addr);
+        }
+        starts[starts.len().saturating_sub(rows + 1)]
+    }
+
+    /// Move the pane by lines instructions, down for positive. Up goes back a
+    /// halfword at a time, as instructions can't be walked backwards
+    pub fn code_scroll(&mut self, lines: i32) {
+        let mut top = self.code_top.unwrap_or_else(|| self.code_follow_top(0));
+        for _ in 0..lines.unsigned_abs() {
+            top = if lines > 0 {
+                top.wrapping_add(self.code_size(top))
+            } else {
+                top.wrapping_sub(2)
+            };
+        }
+        self.code_top = Some(top);
+    }
+
+    /// Go back to following the commit pc
+    pub fn code_follow(&mut self) {
+        self.code_top = None;
+    }
+
+    /// The pane's text, height lines of it
+    pub(super) fn disassembly(&self, height: usize) -> String {
+        let commit = self.commit_address();
+        // In flight instructions by the pc after them
+        let mut in_flight: HashSet<u32> = (0..ROB_ENTRIES)
+            .map(|n| self.rob.get(n))
+            .filter(|entry| entry.status != ROBStatus::EMPTY)
+            .map(|entry| entry.pc)
+            .collect();
+        in_flight.extend(self.iq.iter().map(|iqe| iqe.pc));
+        in_flight.extend(self.fb.iter().flatten().map(|fqe| fqe.pc));
+
+        let mut addr = self
+            .code_top
+            .unwrap_or_else(|| self.code_follow_top(height / 3));
+        let mut lines = Vec::with_capacity(height);
+        while lines.len() < height {
+            if let Some(name) = self.state.mem.get_function_at(addr) {
+                lines.push(format!("{}:", name));
+            }
+            let Some(fetched) = self.code_at(addr) else {
+                lines.push(format!("     {:08X}  --", addr));
+                addr = addr.wrapping_add(2);
+                continue;
+            };
+            let size = if is_32_bit(fetched) { 4 } else { 2 };
+            let here = addr..addr + size;
+            let breakpoint = if self.debug.breakpoints.contains(&addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let position = if here.contains(&commit) {
+                '>'
+            } else if here.contains(&self.spec_pc) {
+                'F'
+            } else {
+                ' '
+            };
+            let flight = if in_flight.contains(&here.end) {
+                '~'
+            } else {
+                ' '
+            };
+            let hex = if size == 4 {
+                format!("{:08X}", fetched)
+            } else {
+                format!("{:04X}", fetched)
+            };
+            let i = decode(fetched);
+            let mut line = format!(
+                "{}{}{}  {:08X}  {:<8}  {}",
+                breakpoint, position, flight, addr, hex, i
+            );
+            if matches!(i.it, IT::B | IT::BL) {
+                line += &format!(
+                    " <{}>",
+                    self.code_label(addr.wrapping_add(4).wrapping_add(i.imms as u32))
+                );
+            }
+            lines.push(line);
+            addr = here.end;
+        }
+        lines.truncate(height);
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,104 @@
+use super::*;
+
+/// LR value on exception entry: return to Thread mode using the main stack
+pub const EXC_RETURN_THREAD_MSP: u32 = 0xFFFF_FFF9;
+
+/// The vector table is fixed at address 0 on ARMv6-M without the VTOR extension
+const VECTOR_TABLE: u32 = 0;
+
+// The 8 word exception frame: r0-r3, r12, lr, return address, xPSR
+const FRAME_SIZE: u32 = 32;
+// Set in the stacked xPSR when an extra word was pushed to 8 byte align the frame
+const XPSR_STACK_ALIGN: u32 = 1 << 9;
+
+pub fn is_exc_return(addr: u32) -> bool {
+    (addr & 0xFFFF_FFF0) == 0xFFFF_FFF0
+}
+
+impl<'a> OoOSpeculative<'a> {
+    /// Interrupts are only taken at the commit of the last mop of an instruction,
+    /// once the architectural state is exact
+    pub(super) fn interrupt_pending(&self) -> bool {
+        self.active_exception.is_none() && self.uart.irq_pending()
+    }
+
+    /// Exception entry. Everything younger than the ROB head is flushed, the frame is
+    /// pushed onto the stack and fetch restarts at the handler from the vector table
+    pub(super) fn take_exception(&mut self, exc_num: u32, return_address: u32) {
+        let regs = self.state.regs;
+        let mut xpsr = ((regs.apsr.n as u32) << 31)
+            | ((regs.apsr.z as u32) << 30)
+            | ((regs.apsr.c as u32) << 29)
+            | ((regs.apsr.v as u32) << 28);
+
+        let mut sp = regs.sp;
+        if sp & 4 != 0 {
+            sp -= 4;
+            xpsr |= XPSR_STACK_ALIGN;
+        }
+        sp -= FRAME_SIZE;
+
+        let frame = [
+            regs.gp[0],
+            regs.gp[1],
+            regs.gp[2],
+            regs.gp[3],
+            regs.gp[12],
+            regs.lr,
+            return_address,
+            xpsr,
+        ];
+        for (n, word) in frame.iter().enumerate() {
+            let addr = sp + 4 * n as u32;
+            if let Err(e) = self.state.mem.set_word(addr, *word) {
+                panic!("{:?}: attempt to push exception frame at {:08X?}", e, addr)
+            }
+        }
+
+        let handler = match self.state.mem.get_word(VECTOR_TABLE + 4 * exc_num) {
+            Ok(handler) => handler & !1,
+            Err(e) => panic!("{:?}: could not read vector for exception {}", e, exc_num),
+        };
+
+        self.flush_on_mispredict();
+        self.state.regs.sp = sp;
+        self.state.regs.lr = EXC_RETURN_THREAD_MSP;
+        self.state.regs.pc = handler;
+        self.spec_pc = handler;
+        self.active_exception = Some(exc_num);
+    }
+
+    /// Exception return, on committing a BX to an EXC_RETURN value. The pipeline has
+    /// already been flushed by the BX
+    pub(super) fn exception_return(&mut self) {
+        let sp = self.state.regs.sp;
+        let mut frame = [0; 8];
+        for (n, word) in frame.iter_mut().enumerate() {
+            let addr = sp + 4 * n as u32;
+            *word = match self.state.mem.get_word(addr) {
+                Ok(word) => word,
+                Err(e) => panic!("{:?}: attempt to pop exception frame at {:08X?}", e, addr),
+            };
+        }
+
+        let regs = &mut self.state.regs;
+        regs.gp[0] = frame[0];
+        regs.gp[1] = frame[1];
+        regs.gp[2] = frame[2];
+        regs.gp[3] = frame[3];
+        regs.gp[12] = frame[4];
+        regs.lr = frame[5];
+        regs.pc = frame[6];
+
+        let xpsr = frame[7];
+        regs.apsr.n = (xpsr >> 31) & 1 == 1;
+        regs.apsr.z = (xpsr >> 30) & 1 == 1;
+        regs.apsr.c = (xpsr >> 29) & 1 == 1;
+        regs.apsr.v = (xpsr >> 28) & 1 == 1;
+
+        regs.sp = sp + FRAME_SIZE + if xpsr & XPSR_STACK_ALIGN != 0 { 4 } else { 0 };
+
+        self.spec_pc = frame[6];
+        self.active_exception = None;
+    }
+}
@@ -2,6 +2,9 @@ use super::*;
 use crate::binary::{bit_as_bool, briz, signed_to_unsigned_bitcast, unsigned_to_signed_bitcast};
 use crate::components::shift::{shift_with_carry, ShiftType};
 use crate::components::ALU::{ALUOperation, CalcResult, ALU};
+use crate::cpu::syscalls::SVC_EXIT;
+use crate::model::MemError;
+use crate::trace::Unit;
 use crate::IT::*;
 use std::cmp::Ordering;
 use std::collections::HashSet;
@@ -9,10 +12,28 @@ use std::collections::HashSet;
 impl<'a> OoOSpeculative<'a> {
     pub(super) fn execute(&mut self) {
         let mut can_go: Vec<(usize, (LoadQueueEntry, Option<u32>))> = Vec::with_capacity(N_LS_EXECS);
+        let mut blocked = Vec::new();
         for (i, entry) in self.load_queue.iter().enumerate() {
+            // Device reads have side effects so can't be done speculatively
+            if self.uart.contains(entry.address) && entry.rob_entry != self.rob.head {
+                continue;
+            }
             let (this_can_go, forwarded) = self.rob.load_can_go(entry);
             if this_can_go {
                 can_go.push((i, (entry.clone(), forwarded)));
+            } else {
+                blocked.push(self.rob.get(entry.rob_entry).seq);
+            }
+        }
+        // Loads older than the head have committed. Flushed ones can linger until
+        // the head passes them, as their seqs are never reused
+        let oldest = (!self.rob.is_empty()).then(|| self.rob.get_head().seq);
+        self.blocked_loads
+            .retain(|seq| oldest.is_some_and(|oldest| *seq >= oldest));
+        for seq in blocked {
+            self.trace(PipelineEvent::LoadBlocked { seq });
+            if !self.blocked_loads.contains(&seq) {
+                self.blocked_loads.push(seq);
             }
         }
 
@@ -34,6 +55,8 @@ impl<'a> OoOSpeculative<'a> {
                 .set_target_address(lqe_head.rob_entry, lqe_head.address);
             
             if let Some(forwarded) = forwarded {
+                let seq = self.rob.get(lqe_head.rob_entry).seq;
+                self.trace(PipelineEvent::LoadForwarded { seq });
                 // Load store has a delay of 1 cycles on top of the 1 cycle for addr calc
                 self.to_broadcast.push((
                     1,
@@ -49,35 +72,10 @@ impl<'a> OoOSpeculative<'a> {
                 continue;
             }
 
-            let result = match lqe_head.load_type {
-                LDRBImm | LDRBReg => match self.state.mem.get_byte(load_address) {
-                    Ok(byte) => Ok(byte as u32),
-                    Err(e) => Err(e),
-                },
-                LDRHReg | LDRHImm => match self.state.mem.get_halfword(load_address) {
-                    Ok(byte) => Ok(byte as u32),
-                    Err(e) => Err(e),
-                },
-                LDRImm | LDRReg => self.state.mem.get_word(load_address),
-                LDRSB => match self.state.mem.get_byte(load_address) {
-                    Ok(byte) => Ok(briz(byte as u32, 0, 6)
-                        + (if bit_as_bool(byte as u32, 7) {
-                            0x80000000
-                        } else {
-                            0
-                        })),
-                    Err(e) => Err(e),
-                },
-                LDRSH => match self.state.mem.get_halfword(load_address) {
-                    Ok(byte) => Ok(briz(byte as u32, 0, 14)
-                        + (if bit_as_bool(byte as u32, 15) {
-                            0x80000000
-                        } else {
-                            0
-                        })),
-                    Err(e) => Err(e),
-                },
-                _ => unreachable!(),
+            let result = if self.uart.contains(load_address) {
+                Ok(self.uart.read(load_address))
+            } else {
+                self.read_memory(lqe_head.load_type, load_address)
             };
 
             let result = match result {
@@ -107,80 +105,136 @@ impl<'a> OoOSpeculative<'a> {
         }
         self.load_queue = new_load_queue;
 
-        for _ in 0..N_ALUSHIFTERS {
+        for n in 0..N_ALUSHIFTERS {
             if let Some(rs_index) = self.rs_alu_shift.get_oldest_ready(&self.rob, false) {
+                self.trace_execute(self.rs_alu_shift.vec[rs_index].rob_dest, Unit::AluShift(n));
                 self.execute_alu_shift(&self.rs_alu_shift.vec[rs_index].clone());
                 self.rs_alu_shift.vec[rs_index].busy = false;
             }
         }
 
-        for _ in 0..N_MULS {
+        for n in 0..N_MULS {
             if let Some(rs_index) = self.rs_mul.get_oldest_ready(&self.rob, false) {
+                self.trace_execute(self.rs_mul.vec[rs_index].rob_dest, Unit::Mul(n));
                 self.execute_mul(&self.rs_mul.vec[rs_index].clone());
                 self.rs_mul.vec[rs_index].busy = false;
             }
         }
 
-        for _ in 0..N_CONTROL {
+        for n in 0..N_CONTROL {
             if let Some(rs_index) = self.rs_control.get_oldest_ready(&self.rob, false) {
+                self.trace_execute(self.rs_control.vec[rs_index].rob_dest, Unit::Control(n));
                 self.execute_control(&self.rs_control.vec[rs_index].clone());
                 self.rs_control.vec[rs_index].busy = false;
             }
         }
 
-        for _ in 0..N_LS_EXECS {
+        for n in 0..N_LS_EXECS {
             // No loads if the queue is full
             let no_loads = self.load_queue.len() >= LQ_SIZE;
             if let Some(rs_index) = self.rs_ls.get_oldest_ready(&self.rob, no_loads) {
+                self.trace_execute(self.rs_ls.vec[rs_index].rob_dest, Unit::LoadStore(n));
                 self.execute_load_store(&self.rs_ls.vec[rs_index].clone());
                 self.rs_ls.vec[rs_index].busy = false;
             }
         }
     }
 
+    fn trace_execute(&mut self, rob: usize, unit: Unit) {
+        self.count_unit_busy(unit);
+        let seq = self.rob.get(rob).seq;
+        self.trace(PipelineEvent::Execute { seq, unit });
+    }
+
+    pub(super) fn read_memory(&self, load_type: IT, load_address: u32) -> Result<u32, MemError> {
+        match load_type {
+            LDRBImm | LDRBReg => match self.state.mem.get_byte(load_address) {
+                Ok(byte) => Ok(byte as u32),
+                Err(e) => Err(e),
+            },
+            LDRHReg | LDRHImm => match self.state.mem.get_halfword(load_address) {
+                Ok(byte) => Ok(byte as u32),
+                Err(e) => Err(e),
+            },
+            LDRImm | LDRReg => self.state.mem.get_word(load_address),
+            LDRSB => match self.state.mem.get_byte(load_address) {
+                Ok(byte) => Ok(briz(byte as u32, 0, 6)
+                    + (if bit_as_bool(byte as u32, 7) {
+                        0x80000000
+                    } else {
+                        0
+                    })),
+                Err(e) => Err(e),
+            },
+            LDRSH => match self.state.mem.get_halfword(load_address) {
+                Ok(byte) => Ok(briz(byte as u32, 0, 14)
+                    + (if bit_as_bool(byte as u32, 15) {
+                        0x80000000
+                    } else {
+                        0
+                    })),
+                Err(e) => Err(e),
+            },
+            _ => unreachable!(),
+        }
+    }
+
     fn execute_control(&mut self, rs: &RS) {
+        // The semihosting call itself happens at commit
+        if rs.i.it == BKPT {
+            self.to_broadcast.push((
+                1,
+                CDBRecord {
+                    is_branch_target: false,
+                    valid: false,
+                    result: 0,
+                    aspr_update: ASPRUpdate::no_update(),
+                    rob_number: rs.rob_dest,
+                    halt: false,
+                },
+            ));
+            return;
+        }
+        // The call itself happens at commit, only exit needs flagging here
         if rs.i.it == SVC {
             let svc_num = Self::get_data(rs.j).unwrap();
             let r0 = Self::get_data(rs.k).unwrap();
-            match svc_num {
-                0 => {
-                    self.to_broadcast.push((
-                        1,
-                        CDBRecord {
-                            is_branch_target: false,
-                            valid: false,
-                            result: r0,
-                            aspr_update: ASPRUpdate::no_update(),
-                            rob_number: rs.rob_dest,
-                            halt: true,
-                        },
-                    ));
-                    return;
-                }
-                1 | 3 => {
-                    self.to_broadcast.push((
-                        1,
-                        CDBRecord {
-                            is_branch_target: false,
-                            valid: false,
-                            result: r0,
-                            aspr_update: ASPRUpdate::no_update(),
-                            rob_number: rs.rob_dest,
-                            halt: false,
-                        },
-                    ));
-                    return;
-                }
-                _ => panic!("Invalid svc"),
-            }
+            self.to_broadcast.push((
+                1,
+                CDBRecord {
+                    is_branch_target: false,
+                    valid: false,
+                    result: r0,
+                    aspr_update: ASPRUpdate::no_update(),
+                    rob_number: rs.rob_dest,
+                    halt: svc_num == SVC_EXIT,
+                },
+            ));
+            return;
         }
+        let target = Self::branch_target(rs, self.rob.get(rs.rob_dest).pc);
+        self.to_broadcast.push((
+            1,
+            CDBRecord {
+                is_branch_target: true,
+                valid: false,
+                result: target,
+                aspr_update: ASPRUpdate::no_update(),
+                rob_number: rs.rob_dest,
+                halt: false,
+            },
+        ));
+    }
+
+    /// Where a branch goes, given the pc after it. The bottom bit of a B's target
+    /// says whether it's taken
+    pub(super) fn branch_target(rs: &RS, pc: u32) -> u32 {
         // BX, BLX and SetPc require RM
         // SetPC, BX and BLX are absolute
         // B and BL are relative, and require an immediate
         let mut target = match rs.i.it {
             SetPC | BX | BLX => Self::get_data(rs.j).unwrap(),
             BL | B => {
-                let pc = self.rob.get(rs.rob_dest).pc;
                 let offset = rs.i.imms as u32;
                 pc.wrapping_add(offset)
             }
@@ -233,18 +287,7 @@ impl<'a> OoOSpeculative<'a> {
             assert_eq!(target % 2, 0);
             target += taken as u32;
         }
-
-        self.to_broadcast.push((
-            1,
-            CDBRecord {
-                is_branch_target: true,
-                valid: false,
-                result: target,
-                aspr_update: ASPRUpdate::no_update(),
-                rob_number: rs.rob_dest,
-                halt: false,
-            },
-        ));
+        target
     }
 
     fn execute_load_store(&mut self, rs: &RS) {
@@ -283,6 +326,23 @@ impl<'a> OoOSpeculative<'a> {
     }
 
     fn execute_mul(&mut self, rs: &RS) {
+        let (result, aspr_update) = Self::mul(rs);
+
+        // Multiplier has a delay of 2 cycles
+        self.to_broadcast.push((
+            2,
+            CDBRecord {
+                is_branch_target: false,
+                valid: false,
+                result,
+                aspr_update,
+                rob_number: rs.rob_dest,
+                halt: false,
+            },
+        ));
+    }
+
+    pub(super) fn mul(rs: &RS) -> (u32, ASPRUpdate) {
         let j = unsigned_to_signed_bitcast(Self::get_data(rs.j).unwrap());
         let k = unsigned_to_signed_bitcast(Self::get_data(rs.k).unwrap());
 
@@ -295,11 +355,20 @@ impl<'a> OoOSpeculative<'a> {
             c: None,
             v: None,
         };
-        let result = signed_to_unsigned_bitcast(result);
+        (signed_to_unsigned_bitcast(result), aspr_update)
+    }
+
+    fn execute_alu_shift(&mut self, rs: &RS) {
+        let CalcResult {
+            delay,
+            result,
+            aspr_update,
+        } = Self::alu_shift(rs);
+        // The delay should always be 1 for this bit
+        assert_eq!(delay, 1);
 
-        // Multiplier has a delay of 2 cycles
         self.to_broadcast.push((
-            2,
+            delay,
             CDBRecord {
                 is_branch_target: false,
                 valid: false,
@@ -311,7 +380,7 @@ impl<'a> OoOSpeculative<'a> {
         ));
     }
 
-    fn execute_alu_shift(&mut self, rs: &RS) {
+    pub(super) fn alu_shift(rs: &RS) -> CalcResult {
         let j = Self::get_data(rs.j);
         let k = Self::get_data(rs.k);
         let l = Self::get_data(rs.l);
@@ -435,31 +504,13 @@ impl<'a> OoOSpeculative<'a> {
             _ => unreachable!("{:?}", rs.i.it),
         };
 
-        let CalcResult {
-            delay,
-            result,
-            aspr_update,
-        } = match op {
+        match op {
             ALU_Shift::ALU_OP(op) => ALU(op, n, m, c != 0),
             ALU_Shift::SHIFT_OP(op) => shift_with_carry(op, n, m as u8, c as u8),
-        };
-        // The delay should always be 1 for this bit
-        assert_eq!(delay, 1);
-
-        self.to_broadcast.push((
-            delay,
-            CDBRecord {
-                is_branch_target: false,
-                valid: false,
-                result,
-                aspr_update,
-                rob_number: rs.rob_dest,
-                halt: false,
-            },
-        ));
+        }
     }
 
-    fn get_data(x: RSData) -> Option<u32> {
+    pub(super) fn get_data(x: RSData) -> Option<u32> {
         if let RSData::Data(n) = x {
             Some(n)
         } else {
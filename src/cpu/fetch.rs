@@ -11,6 +11,7 @@ impl<'a> OoOSpeculative<'a> {
                 let fetched = self.state.mem.get_instruction(self.spec_pc);
                 let pc_increment = if is_32_bit(fetched) { 4 } else { 2 };
                 hwords_fetched += pc_increment / 2;
+                self.trace(PipelineEvent::Fetch { pc: self.spec_pc, slot: i });
 
                 /* The use of 0b1111 as a register specifier is not normally permitted in Thumb instructions. When a value of 0b1111 is
                    permitted, a variety of meanings is possible. For register reads, these meanings are:
@@ -28,7 +29,7 @@ impl<'a> OoOSpeculative<'a> {
                     i += 1;
                     
                     if control_instruction.is_serializing() {
-                        self.fb[i - 1] = Some(FetchQueueEntry { pc: self.spec_pc + pc_increment, i: fetched, predicted_taken: false});
+                        self.fb[i - 1] = Some(FetchQueueEntry { pc: self.spec_pc + pc_increment, i: fetched, predicted_taken: false, cycle: self.epoch });
                         self.fetch_stall = true;
                         self.spec_pc += pc_increment;
                         continue;
@@ -47,9 +48,9 @@ impl<'a> OoOSpeculative<'a> {
                             if PREDICT == PredictionAlgorithms::AlwaysTaken {
                                 self.spec_pc = self.spec_pc.wrapping_add(control_offset).wrapping_add(4);
                             } else {
-                                self.spec_pc += 4;
+                                self.spec_pc += pc_increment;
                             };
-                            self.fb[i - 1] = Some(FetchQueueEntry { pc: pc_if_untaken, i: fetched, predicted_taken: PREDICT == PredictionAlgorithms::AlwaysTaken });
+                            self.fb[i - 1] = Some(FetchQueueEntry { pc: pc_if_untaken, i: fetched, predicted_taken: PREDICT == PredictionAlgorithms::AlwaysTaken, cycle: self.epoch });
                         }
 
                         PredictionAlgorithms::Bits(_) => {
@@ -62,13 +63,13 @@ impl<'a> OoOSpeculative<'a> {
                             if pred {
                                 self.spec_pc = self.spec_pc.wrapping_add(control_offset).wrapping_add(4);
                             } else {
-                                self.spec_pc += 4;
+                                self.spec_pc += pc_increment;
                             };
-                            self.fb[i - 1] = Some(FetchQueueEntry { pc: pc_if_untaken, i: fetched, predicted_taken: pred });
+                            self.fb[i - 1] = Some(FetchQueueEntry { pc: pc_if_untaken, i: fetched, predicted_taken: pred, cycle: self.epoch });
                         }
                     }
                 } else {
-                    self.fb[i] = Some(FetchQueueEntry {pc: self.spec_pc + pc_increment, i: fetched, predicted_taken: false });
+                    self.fb[i] = Some(FetchQueueEntry {pc: self.spec_pc + pc_increment, i: fetched, predicted_taken: false, cycle: self.epoch });
                     i += 1;
                     
                     self.spec_pc += pc_increment;
@@ -115,6 +116,11 @@ impl<'a> OoOSpeculative<'a> {
         if (i & 0b1111_1111_1000_0000) == 0b0100_0111_1000_0000 {
             return Some((IT::BLX, 0));
         }
+
+        // BKPT
+        if (i & 0b1111_1111_0000_0000) == 0b1011_1110_0000_0000 {
+            return Some((IT::BKPT, 0));
+        }
         //
         // // Pop (15)
         // if (i & 0b1111_1111_0000_0000) == 0b1011_1101_0000_0000 {
@@ -124,3 +130,23 @@ impl<'a> OoOSpeculative<'a> {
         None
     }
 }
+
+#[cfg(test)]
+mod fetch_tests {
+    use crate::cpu::test_cpu;
+
+    #[test]
+    fn untaken_branch_falls_through() {
+        // The bne is never taken, and predicted so the second time round
+        // movs r0, #0; movs r1, #0; movs r3, #2
+        // loop: cmp r0, #0; bne over; adds r1, #1; over: subs r3, #1; bne loop; svc 0
+        let mut cpu = test_cpu(&[
+            0x2000, 0x2100, 0x2302, 0x2800, 0xD100, 0x3101, 0x3B01, 0xD1FA, 0xDF00,
+        ]);
+        while cpu.halt.is_none() {
+            cpu.tick();
+        }
+        // Fetch steps on by 2 past a 16-bit branch predicted not taken
+        assert_eq!(cpu.state.regs.get(1), 2);
+    }
+}
@@ -0,0 +1,286 @@
+//! Fast-forwarding. A functional model runs the program an instruction at a time
+//! straight on the architectural state, with none of the pipeline, to get past
+//! the start of a long workload quickly. It shares the mop breakdown, operand
+//! reads and ALU with the pipeline so both get the same answers.
+//!
+//! The branch predictor is trained on the way, there are no caches to warm.
+//! The guest's clock moves on a cycle an instruction while fast-forwarding, but
+//! the cycle count reported only moves in the detailed model, and nothing is
+//! traced, counted or profiled.
+use super::*;
+use crate::binary::unsigned_to_signed_bitcast;
+use crate::cpu::exception::is_exc_return;
+use crate::cpu::semihosting::SEMIHOSTING_BKPT;
+use crate::cpu::syscalls::{SVC_EXIT, SVC_MARKER};
+use crate::decode::IT::*;
+use crate::model::{Memory, UART_IRQ};
+
+/// Where fast-forwarding stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FastForward {
+    /// Once this many instructions have committed, counted in mops like the
+    /// detailed model counts them
+    Instructions(usize),
+    /// When the pc gets here, before running what's there
+    Address(u32),
+    /// Just after the first `svc_marker()`
+    Marker,
+}
+
+impl FastForward {
+    /// A count, marker, a hex address with 0x, or a symbol from the ELF
+    pub fn parse(text: &str, mem: &Memory) -> Result<Self, String> {
+        if text == "marker" {
+            return Ok(FastForward::Marker);
+        }
+        if let Some(hex) = text.strip_prefix("0x") {
+            return u32::from_str_radix(hex, 16)
+                .map(FastForward::Address)
+                .map_err(|_| format!("{} is not an address", text));
+        }
+        if let Ok(n) = text.parse() {
+            return Ok(FastForward::Instructions(n));
+        }
+        // Thumb function symbols have the low bit set
+        match mem.get_symbol(text) {
+            Some(addr) => Ok(FastForward::Address(addr & !1)),
+            None => Err(format!(
+                "{} is not a count, address, symbol or marker",
+                text
+            )),
+        }
+    }
+}
+
+/// Nothing renamed, so operands come from the registers
+const NO_RENAMES: [Option<usize>; 20] = [None; 20];
+
+impl<'a> OoOSpeculative<'a> {
+    /// Run functionally until to says, or the program ends, then restart the
+    /// pipeline from wherever that is. Returns the instructions run
+    pub fn fast_forward(&mut self, to: FastForward) -> usize {
+        self.finish_instruction();
+        let start = self.instructions_committed;
+        while self.halt.is_none() {
+            let done = match to {
+                FastForward::Instructions(n) => self.instructions_committed - start >= n,
+                FastForward::Address(addr) => self.state.regs.pc == addr,
+                FastForward::Marker => false,
+            };
+            if done {
+                break;
+            }
+            let i = self.functional_step();
+            if to == FastForward::Marker && i.it == SVC && i.immu == SVC_MARKER {
+                break;
+            }
+        }
+        self.flush_pipeline();
+        self.instructions_committed - start
+    }
+
+    /// Run functionally to the end, counting the instructions run in each basic
+    /// block, by its first address, for every interval instructions
+    pub fn basic_block_vectors(&mut self, interval: usize) -> Vec<HashMap<u32, u64>> {
+        self.finish_instruction();
+        let mut vectors = vec![HashMap::new()];
+        let mut block = self.state.regs.pc;
+        let mut in_interval = 0;
+        while self.halt.is_none() {
+            if in_interval >= interval {
+                vectors.push(HashMap::new());
+                in_interval = 0;
+            }
+            let address = self.state.regs.pc;
+            let fallthrough = address
+                + if is_32_bit(self.state.mem.get_instruction(address)) {
+                    4
+                } else {
+                    2
+                };
+            let before = self.instructions_committed;
+            let i = self.functional_step();
+            let ran = self.instructions_committed - before;
+            *vectors.last_mut().unwrap().entry(block).or_default() += ran as u64;
+            in_interval += ran;
+
+            // A block ends at any branch, taken or not, and wherever the pc jumps
+            if self.state.regs.pc != fallthrough || matches!(i.it, B | BL | BX | BLX | SVC | BKPT) {
+                block = self.state.regs.pc;
+            }
+        }
+        self.flush_pipeline();
+        vectors
+    }
+
+    /// Commit the rest of the instruction in flight, if any, as the pipeline can
+    /// only be thrown away between instructions
+    fn finish_instruction(&mut self) {
+        let empty =
+            self.rob.is_empty() && self.iq.is_empty() && self.fb.iter().all(Option::is_none);
+        if !empty {
+            let step = std::mem::replace(&mut self.debug.step, true);
+            self.run_until_stop(|_| true);
+            self.debug.step = step;
+        }
+        self.flush_pipeline();
+    }
+
+    /// Throw away the counts so far, so everything reported is from here on.
+    /// The predictor, call stack, machine state and the guest's clock are kept
+    pub fn start_measuring(&mut self) {
+        self.epoch = 0;
+        self.instructions_committed = 0;
+        self.mispredicts = 0;
+        self.correct_predicts = 0;
+        self.cpi = CpiStack::default();
+        self.refill = None;
+        self.cycle_stalls.clear();
+        if self.stats.is_some() {
+            self.enable_stats();
+        }
+        if self.profiler.is_some() {
+            self.enable_profiling();
+        }
+        if self.charts.is_some() {
+            self.enable_charts();
+        }
+    }
+
+    /// Run the instruction at the pc, returning it
+    fn functional_step(&mut self) -> I {
+        self.guest_cycles += 1;
+        self.uart.tick();
+        let address = self.state.regs.pc;
+        let fetched = self.state.mem.get_instruction(address);
+        // Like the ROB, mops have the pc after the instruction
+        let pc = address + if is_32_bit(fetched) { 4 } else { 2 };
+        let i = decode(fetched);
+        let mops = decode2(i);
+        let n_mops = mops.len();
+        for (k, mop) in mops.into_iter().enumerate() {
+            self.functional_mop(mop, pc, k == n_mops - 1);
+            if self.halt.is_some() {
+                break;
+            }
+        }
+        i
+    }
+
+    /// What issue, execute and commit do to one mop, all at once
+    fn functional_mop(&mut self, i: I, pc: u32, ends_instruction: bool) {
+        let issue_type = get_issue_type(i.it);
+        let rs_set = match issue_type {
+            IssueType::ALUSHIFT => &self.rs_alu_shift,
+            IssueType::MUL => &self.rs_mul,
+            IssueType::LoadStore => &self.rs_ls,
+            IssueType::Control => &self.rs_control,
+        };
+        let (j, k, l) = rs_set.get_dependencies(&i, pc, &self.state.regs, &NO_RENAMES, &self.rob);
+        let rs = RS {
+            busy: true,
+            j,
+            k,
+            l,
+            i,
+            rob_dest: 0,
+        };
+
+        let mut next_pc = pc;
+        let mut exception_return = false;
+        match issue_type {
+            IssueType::ALUSHIFT => {
+                let result = Self::alu_shift(&rs);
+                self.functional_write(&i, result.result, &result.aspr_update);
+            }
+            IssueType::MUL => {
+                let (result, aspr_update) = Self::mul(&rs);
+                self.functional_write(&i, result, &aspr_update);
+            }
+            IssueType::LoadStore => {
+                let address = Self::get_data(j)
+                    .unwrap()
+                    .wrapping_add(Self::get_data(k).unwrap());
+                match i.it {
+                    STRBImm | STRBReg | STRHImm | STRHReg | STRImm | STRReg => {
+                        self.store(i.it, address, Self::get_data(l).unwrap())
+                    }
+                    _ => {
+                        let result = if self.uart.contains(address) {
+                            Ok(self.uart.read(address))
+                        } else {
+                            self.read_memory(i.it, address)
+                        };
+                        match result {
+                            Ok(result) => self.state.regs.set(i.rt, result),
+                            Err(e) => panic!("Memory error {:?}", e),
+                        }
+                    }
+                }
+            }
+            IssueType::Control => match i.it {
+                SVC => {
+                    if i.immu == SVC_EXIT {
+                        let r0 = self.state.regs.gp[0];
+                        self.halt = Some(unsigned_to_signed_bitcast(r0));
+                    }
+                    self.supervisor_call(i.immu);
+                }
+                BKPT => {
+                    if i.immu == SEMIHOSTING_BKPT {
+                        self.semihost();
+                    } else {
+                        panic!("Breakpoint #{} hit at {:08X?}", i.immu, pc - 2)
+                    }
+                }
+                B => {
+                    let target = Self::branch_target(&rs, pc);
+                    let taken = (target & 1) == 1;
+                    if let PredictionAlgorithms::Bits(_) = PREDICT {
+                        self.btb.update(pc, taken);
+                    }
+                    if taken {
+                        next_pc = target - 1;
+                    }
+                }
+                BL => {
+                    next_pc = Self::branch_target(&rs, pc);
+                    self.state.regs.set(14, pc);
+                }
+                BX | BLX => {
+                    let target = Self::branch_target(&rs, pc);
+                    next_pc = (target >> 1) << 1;
+                    if i.it == BLX {
+                        self.state.regs.set(14, pc);
+                    }
+                    exception_return = i.it == BX && is_exc_return(target);
+                }
+                _ => unreachable!("{:?}", i),
+            },
+        }
+
+        if exception_return {
+            self.exception_return();
+        } else {
+            self.state.regs.pc = next_pc;
+        }
+        self.instructions_committed += 1;
+        if ends_instruction {
+            self.update_call_stack(i.it, pc, self.state.regs.pc);
+            if self.halt.is_none() && self.interrupt_pending() {
+                self.take_exception(16 + UART_IRQ, self.state.regs.pc);
+            }
+        }
+    }
+
+    /// An ALU or multiplier result, to rd and the flags
+    fn functional_write(&mut self, i: &I, result: u32, aspr_update: &ASPRUpdate) {
+        // Compares and tests only set flags
+        if !matches!(i.it, CMPImm | CMPReg | CMN | TST | NOP) {
+            self.state.regs.set(i.rd, result);
+        }
+        if i.setsflags {
+            self.state.regs.apply_aspr_update(aspr_update);
+        }
+    }
+}
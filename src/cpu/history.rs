@@ -0,0 +1,294 @@
+//! Stepping backwards. The machine is snapshotted every so many cycles, and going
+//! back restores the newest snapshot before the target and replays forward to it.
+//! Replay is deterministic: UART input comes from what was received the first
+//! time and nothing is sent twice, see `UartState`, and tracers are left out.
+//!
+//! Memory isn't copied. Instead each snapshot keeps the pages written after it,
+//! as they were when it was taken, so undoing the newer snapshots' pages in turn
+//! gets back to it. Host file I/O can't be undone, so history stops there.
+use super::*;
+use crate::checkpoint::persist_fields;
+use crate::model::{PageLog, UartState};
+
+/// Cycles between snapshots, the most a step back has to replay
+const SNAPSHOT_INTERVAL: usize = 1000;
+/// Snapshots kept, older ones are dropped
+const MAX_SNAPSHOTS: usize = 1000;
+
+/// Everything that decides what the machine does next, apart from memory and
+/// host files. Checkpoints are one of these too, see checkpoint.rs
+#[derive(Clone)]
+pub(super) struct Snapshot {
+    regs: Registers,
+    /// Pages written after this snapshot, as they were when it was taken. Filled
+    /// in when the next snapshot is taken, the newest's are still in memory's log
+    pages: PageLog,
+    fb: [Option<FetchQueueEntry>; N_ISSUE],
+    iq: VecDeque<InstructionQueueEntry>,
+    rob: ROB,
+    btb: BTB,
+    load_queue: VecDeque<LoadQueueEntry>,
+    rs_mul: RSSet,
+    rs_alu_shift: RSSet,
+    rs_ls: RSSet,
+    rs_control: RSSet,
+    output: String,
+    uart: UartState,
+    io_calls: u64,
+    active_exception: Option<u32>,
+    heap_break: Option<u32>,
+    next_seq: u64,
+    flush_delay: u32,
+    flushing: bool,
+    spec_pc: u32,
+    fetch_stall: bool,
+    mispredicts: u32,
+    correct_predicts: u32,
+    cdb: VecDeque<CDBRecord>,
+    to_broadcast: Vec<(u8, CDBRecord)>,
+    cpi: CpiStack,
+    refill: Option<CpiCategory>,
+    cycle_stalls: Vec<StallReason>,
+    blocked_loads: Vec<u64>,
+    stalls: Vec<StallReason>,
+    epoch: usize,
+    guest_cycles: u64,
+    instructions_committed: usize,
+    call_stack: Vec<CallFrame>,
+    profiler: Option<Profiler>,
+    stats: Option<Stats>,
+    halt: Option<i32>,
+}
+
+persist_fields!(Snapshot {
+    regs,
+    pages,
+    fb,
+    iq,
+    rob,
+    btb,
+    load_queue,
+    rs_mul,
+    rs_alu_shift,
+    rs_ls,
+    rs_control,
+    output,
+    uart,
+    io_calls,
+    active_exception,
+    heap_break,
+    next_seq,
+    flush_delay,
+    flushing,
+    spec_pc,
+    fetch_stall,
+    mispredicts,
+    correct_predicts,
+    cdb,
+    to_broadcast,
+    cpi,
+    refill,
+    cycle_stalls,
+    blocked_loads,
+    stalls,
+    epoch,
+    guest_cycles,
+    instructions_committed,
+    call_stack,
+    profiler,
+    stats,
+    halt
+});
+
+#[derive(Default)]
+pub struct History {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl<'a> OoOSpeculative<'a> {
+    pub fn enable_history(&mut self) {
+        self.history = Some(History::default());
+        self.state.mem.start_page_log();
+        self.take_snapshot();
+    }
+
+    /// Called at the start of each cycle
+    pub(super) fn record_history(&mut self) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        let due = self.epoch.is_multiple_of(SNAPSHOT_INTERVAL);
+        // After stepping back, the snapshots ahead are already gone
+        let newest = history
+            .snapshots
+            .back()
+            .map_or(0, |snapshot| snapshot.epoch);
+        if due && self.epoch > newest {
+            self.take_snapshot();
+        }
+    }
+
+    pub(super) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            regs: self.state.regs,
+            pages: PageLog::new(),
+            fb: self.fb,
+            iq: self.iq.clone(),
+            rob: self.rob.clone(),
+            btb: self.btb.clone(),
+            load_queue: self.load_queue.clone(),
+            rs_mul: self.rs_mul.clone(),
+            rs_alu_shift: self.rs_alu_shift.clone(),
+            rs_ls: self.rs_ls.clone(),
+            rs_control: self.rs_control.clone(),
+            output: self.output.clone(),
+            uart: self.uart.save(),
+            io_calls: self.host.io_calls,
+            active_exception: self.active_exception,
+            heap_break: self.heap_break,
+            next_seq: self.next_seq,
+            flush_delay: self.flush_delay,
+            flushing: self.flushing,
+            spec_pc: self.spec_pc,
+            fetch_stall: self.fetch_stall,
+            mispredicts: self.mispredicts,
+            correct_predicts: self.correct_predicts,
+            cdb: self.cdb.clone(),
+            to_broadcast: self.to_broadcast.clone(),
+            cpi: self.cpi.clone(),
+            refill: self.refill,
+            cycle_stalls: self.cycle_stalls.clone(),
+            blocked_loads: self.blocked_loads.clone(),
+            stalls: self.stalls.clone(),
+            epoch: self.epoch,
+            guest_cycles: self.guest_cycles,
+            instructions_committed: self.instructions_committed,
+            call_stack: self.call_stack.clone(),
+            profiler: self.profiler.clone(),
+            stats: self.stats.clone(),
+            halt: self.halt,
+        }
+    }
+
+    fn take_snapshot(&mut self) {
+        let pages = self.state.mem.start_page_log();
+        let snapshot = self.snapshot();
+        let history = self.history.as_mut().unwrap();
+        if let Some(newest) = history.snapshots.back_mut() {
+            newest.pages = pages;
+        }
+        history.snapshots.push_back(snapshot);
+        if history.snapshots.len() > MAX_SNAPSHOTS {
+            history.snapshots.pop_front();
+        }
+    }
+
+    /// Go back to the newest snapshot usable says yes to, dropping the ones after it
+    fn rewind(&mut self, usable: impl Fn(&Snapshot) -> bool) -> Result<(), String> {
+        let history = self.history.as_mut().ok_or("Stepping back isn't enabled")?;
+        let Some(index) = history.snapshots.iter().rposition(usable) else {
+            let oldest = history.snapshots.front().map_or(0, |s| s.epoch);
+            return Err(format!(
+                "Can't step back that far, history starts at cycle {}",
+                oldest
+            ));
+        };
+        if history.snapshots[index].io_calls != self.host.io_calls {
+            return Err("Can't step back past host file I/O".to_string());
+        }
+
+        let newest_pages = self.state.mem.start_page_log();
+        self.state.mem.undo_pages(&newest_pages);
+        while history.snapshots.len() > index + 1 {
+            let dropped = history.snapshots.pop_back().unwrap();
+            self.state.mem.undo_pages(&dropped.pages);
+        }
+        let snapshot = history.snapshots.back_mut().unwrap();
+        self.state
+            .mem
+            .undo_pages(&std::mem::take(&mut snapshot.pages));
+        let snapshot = snapshot.clone();
+        self.restore(snapshot);
+        Ok(())
+    }
+
+    /// Put everything but memory back as it was in the snapshot
+    pub(super) fn restore(&mut self, snapshot: Snapshot) {
+        self.state.regs = snapshot.regs;
+        self.fb = snapshot.fb;
+        self.iq = snapshot.iq;
+        self.rob = snapshot.rob;
+        self.btb = snapshot.btb;
+        self.load_queue = snapshot.load_queue;
+        self.rs_mul = snapshot.rs_mul;
+        self.rs_alu_shift = snapshot.rs_alu_shift;
+        self.rs_ls = snapshot.rs_ls;
+        self.rs_control = snapshot.rs_control;
+        self.output = snapshot.output;
+        self.uart.restore(snapshot.uart);
+        self.active_exception = snapshot.active_exception;
+        self.heap_break = snapshot.heap_break;
+        self.next_seq = snapshot.next_seq;
+        self.flush_delay = snapshot.flush_delay;
+        self.flushing = snapshot.flushing;
+        self.spec_pc = snapshot.spec_pc;
+        self.fetch_stall = snapshot.fetch_stall;
+        self.mispredicts = snapshot.mispredicts;
+        self.correct_predicts = snapshot.correct_predicts;
+        self.cdb = snapshot.cdb;
+        self.to_broadcast = snapshot.to_broadcast;
+        self.cpi = snapshot.cpi;
+        self.refill = snapshot.refill;
+        self.cycle_stalls = snapshot.cycle_stalls;
+        self.blocked_loads = snapshot.blocked_loads;
+        self.stalls = snapshot.stalls;
+        self.epoch = snapshot.epoch;
+        self.guest_cycles = snapshot.guest_cycles;
+        self.instructions_committed = snapshot.instructions_committed;
+        self.call_stack = snapshot.call_stack;
+        self.profiler = snapshot.profiler;
+        self.stats = snapshot.stats;
+        self.halt = snapshot.halt;
+        self.rewind_charts();
+        self.mem_view.rewind(self.epoch);
+        // Not kept, so there's nothing to show until the next cycle
+        self.broadcasts.clear();
+    }
+
+    /// Tick without tracing while going says so
+    fn replay(&mut self, mut going: impl FnMut(&Self) -> bool) {
+        let tracers = std::mem::take(&mut self.tracers);
+        while going(self) && self.halt.is_none() {
+            self.tick();
+        }
+        self.tracers = tracers;
+    }
+
+    pub fn step_back_cycles(&mut self, n: usize) -> Result<(), String> {
+        let target = self.epoch.saturating_sub(n);
+        self.rewind(|snapshot| snapshot.epoch <= target)?;
+        self.replay(|cpu| cpu.epoch < target);
+        Ok(())
+    }
+
+    /// Back to the last cycle with n fewer instructions committed. Commit retires
+    /// several a cycle, so there may not be a cycle with exactly that many, and
+    /// then this goes back further
+    pub fn step_back_instructions(&mut self, n: usize) -> Result<(), String> {
+        if n == 0 || self.instructions_committed == 0 {
+            return Ok(());
+        }
+        let target = self.instructions_committed.saturating_sub(n);
+        self.rewind(|snapshot| snapshot.instructions_committed <= target)?;
+        // Replay past it to find the cycle, then go back to that
+        let mut last = self.epoch;
+        self.replay(|cpu| {
+            let going = cpu.instructions_committed <= target;
+            if going {
+                last = cpu.epoch;
+            }
+            going
+        });
+        self.step_back_cycles(self.epoch - last)
+    }
+}
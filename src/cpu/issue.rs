@@ -47,10 +47,11 @@ impl<'a> OoOSpeculative<'a> {
         };
 
         if rs_insert.is_some() {
-            self.iq.pop_front();
+            let seq = self.iq.pop_front().unwrap().seq;
             self.rob.issue_commit();
+            self.trace(PipelineEvent::Issue { seq, rob: dest });
         } else {
-            self.stall(StallReason::IssueRSFull);
+            self.stall(StallReason::IssueRSFull(issue_dest));
         }
     }
 }
@@ -0,0 +1,200 @@
+//! The memory pane, sixteen bytes a row with the ASCII alongside. It follows a
+//! register, the SP to start with, until it's scrolled or sent to an address,
+//! and shows memory as bytes, halfwords or words either way round. Bytes written
+//! by the last few stores are highlighted.
+use super::*;
+
+/// Stores whose bytes are highlighted
+const RECENT_STORES: usize = 16;
+const ROW_BYTES: u32 = 16;
+/// Rows above the followed register's
+const FOLLOW_CONTEXT: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MemUnit {
+    Byte,
+    Halfword,
+    Word,
+}
+
+impl MemUnit {
+    fn bytes(self) -> u32 {
+        match self {
+            MemUnit::Byte => 1,
+            MemUnit::Halfword => 2,
+            MemUnit::Word => 4,
+        }
+    }
+}
+
+pub struct MemoryView {
+    /// The first row shown, none to follow the register
+    top: Option<u32>,
+    /// By `Registers::get` index
+    follow: u8,
+    unit: MemUnit,
+    little_endian: bool,
+    ascii: bool,
+    /// Address, length and cycle of each of the last stores
+    recent_stores: VecDeque<(u32, u32, usize)>,
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        Self {
+            top: None,
+            follow: 13,
+            unit: MemUnit::Word,
+            little_endian: true,
+            ascii: true,
+            recent_stores: VecDeque::with_capacity(RECENT_STORES),
+        }
+    }
+}
+
+impl MemoryView {
+    /// Bytes, halfwords, words and round again
+    pub fn next_unit(&mut self) {
+        self.unit = match self.unit {
+            MemUnit::Byte => MemUnit::Halfword,
+            MemUnit::Halfword => MemUnit::Word,
+            MemUnit::Word => MemUnit::Byte,
+        };
+    }
+
+    pub fn toggle_endian(&mut self) {
+        self.little_endian = !self.little_endian;
+    }
+
+    pub fn toggle_ascii(&mut self) {
+        self.ascii = !self.ascii;
+    }
+
+    pub(super) fn note_store(&mut self, addr: u32, len: u32, epoch: usize) {
+        if self.recent_stores.len() == RECENT_STORES {
+            self.recent_stores.pop_front();
+        }
+        self.recent_stores.push_back((addr, len, epoch));
+    }
+
+    /// Forget the stores from after a cycle gone back to
+    pub(super) fn rewind(&mut self, epoch: usize) {
+        self.recent_stores.retain(|store| store.2 <= epoch);
+    }
+
+    fn recently_stored(&self, addr: u32) -> bool {
+        self.recent_stores
+            .iter()
+            .any(|(start, len, _)| (*start..start + len).contains(&addr))
+    }
+}
+
+impl<'a> OoOSpeculative<'a> {
+    fn mem_follow_top(&self) -> u32 {
+        let addr = self.state.regs.get(self.mem_view.follow) & !(ROW_BYTES - 1);
+        addr.wrapping_sub(FOLLOW_CONTEXT * ROW_BYTES)
+    }
+
+    /// Move the pane by rows, down to higher addresses for positive
+    pub fn mem_scroll(&mut self, rows: i32) {
+        let top = self.mem_view.top.unwrap_or_else(|| self.mem_follow_top());
+        self.mem_view.top = Some(top.wrapping_add_signed(rows * ROW_BYTES as i32));
+    }
+
+    /// Show from the row addr is in
+    pub fn mem_goto(&mut self, addr: u32) {
+        self.mem_view.top = Some(addr & !(ROW_BYTES - 1));
+    }
+
+    /// Follow a register, by `Registers::get` index
+    pub fn mem_follow(&mut self, reg: u8) {
+        self.mem_view.follow = reg;
+        self.mem_view.top = None;
+    }
+
+    /// Go back to following the register
+    pub fn mem_follow_again(&mut self) {
+        self.mem_view.top = None;
+    }
+
+    /// Where the pane is and how it shows memory, for its title
+    pub(super) fn memory_title(&self) -> String {
+        let view = &self.mem_view;
+        let at = match view.top {
+            Some(top) => format!("{:08X}", top),
+            None => format!("following {}", Registers::reg_id_to_str(view.follow)),
+        };
+        let unit = match view.unit {
+            MemUnit::Byte => "bytes",
+            MemUnit::Halfword => "halfwords",
+            MemUnit::Word => "words",
+        };
+        let order = match (view.unit, view.little_endian) {
+            (MemUnit::Byte, _) => "",
+            (_, true) => ", little endian",
+            (_, false) => ", big endian",
+        };
+        format!("{}, {}{}", at, unit, order)
+    }
+
+    /// The pane's rows, height of them, fitting in width if the ASCII can
+    pub(super) fn memory_lines(&self, width: usize, height: usize) -> Vec<Line<'static>> {
+        let view = &self.mem_view;
+        let mem = &self.state.mem;
+        let unit = view.unit.bytes();
+        let followed = self.state.regs.get(view.follow);
+        // Marker, address, the units with a space before each, the ASCII
+        let hex_width = 11 + (ROW_BYTES / unit * (2 * unit + 1)) as usize;
+        let ascii = view.ascii && hex_width + 2 + ROW_BYTES as usize <= width;
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+        let mut addr = view.top.unwrap_or_else(|| self.mem_follow_top());
+        let mut lines = Vec::with_capacity(height);
+        for _ in 0..height {
+            let row = addr..addr.wrapping_add(ROW_BYTES);
+            let marker = if row.contains(&followed) { '>' } else { ' ' };
+            let mut spans = vec![Span::raw(format!("{}{:08X}:", marker, addr))];
+            for start in (0..ROW_BYTES)
+                .step_by(unit as usize)
+                .map(|n| addr.wrapping_add(n))
+            {
+                let bytes: Option<Vec<u8>> = (0..unit)
+                    .map(|n| mem.get_byte(start.wrapping_add(n)).ok())
+                    .collect();
+                let text = match bytes {
+                    Some(mut bytes) => {
+                        if view.little_endian {
+                            bytes.reverse();
+                        }
+                        bytes.iter().map(|b| format!("{:02X}", b)).collect()
+                    }
+                    None => "__".repeat(unit as usize),
+                };
+                spans.push(Span::raw(" "));
+                if (0..unit).any(|n| view.recently_stored(start.wrapping_add(n))) {
+                    spans.push(Span::styled(text, highlight));
+                } else {
+                    spans.push(Span::raw(text));
+                }
+            }
+            if ascii {
+                spans.push(Span::raw("  "));
+                for byte_addr in (0..ROW_BYTES).map(|n| addr.wrapping_add(n)) {
+                    let c = match mem.get_byte(byte_addr) {
+                        Ok(b @ 0x20..=0x7E) => b as char,
+                        Ok(_) => '.',
+                        Err(_) => ' ',
+                    };
+                    if view.recently_stored(byte_addr) {
+                        spans.push(Span::styled(c.to_string(), highlight));
+                    } else {
+                        spans.push(Span::raw(c.to_string()));
+                    }
+                }
+            }
+            lines.push(Line::from(spans));
+            addr = row.end;
+        }
+        lines
+    }
+}
@@ -1,10 +1,24 @@
 use itertools::Itertools;
+mod charts;
 mod commit;
+mod cpi;
+mod checkpoint;
+mod debug;
 mod decode;
+mod disassembly;
+mod exception;
 mod execute;
+mod history;
 mod fetch;
+mod functional;
 mod issue;
+mod memory_view;
 mod parameters;
+mod profile;
+mod queues;
+mod sampling;
+mod semihosting;
+mod syscalls;
 mod wb;
 
 use crate::binary::is_32_bit;
@@ -13,19 +27,30 @@ use crate::components::ROB::{ROBEntryDest, ROBStatus};
 use crate::components::RS::*;
 use crate::decode::IT;
 use crate::decode::{decode, decode2, get_issue_type, IssueType, I};
-use crate::model::{ASPRUpdate, ProcessorState};
-use crate::model::Registers;
+use crate::model::{ASPRUpdate, ProcessorState, SourceLine};
+use crate::model::{HostIO, Registers, Uart, UartSink, UartSource, UART_DEFAULT_BASE};
+use crate::layout::{Pane, TuiLayout};
+use crate::stats::Stats;
+use crate::trace::{PipelineEvent, Tracer};
+pub use cpi::{CpiCategory, CpiStack};
+pub use debug::{DebugState, StopReason, WatchKind, Watchpoint};
+pub use functional::FastForward;
+pub use history::History;
+pub use memory_view::MemoryView;
 pub use parameters::*;
+pub use profile::{CallFrame, Profiler};
 
-use ratatui::layout::Margin;
 use ratatui::prelude::Alignment;
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
 use ratatui::widgets::{Borders, Padding, Paragraph};
 use ratatui::{
-    layout::{Constraint, Layout},
+    layout::{Constraint, Layout, Rect},
     widgets::Block,
     Frame,
 };
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 use crate::components::branch_predict::BTB;
 
 #[derive(PartialEq, Eq)]
@@ -57,15 +82,20 @@ pub struct LoadQueueEntry {
 #[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
 enum StallReason {
     FullRob,
-    IssueRSFull,
+    IssueRSFull(IssueType),
     IStall,
 }
 
+#[derive(Clone)]
 pub struct InstructionQueueEntry {
     pub i: I,
     /// the pc value fetched from
     pub pc: u32,
     pub predicted_taken: bool,
+    /// Whether this is the last mop of its instruction, so interrupts can be taken after it
+    pub ends_instruction: bool,
+    /// Numbers mops in decode order, for tracing
+    pub seq: u64,
 }
 
 #[derive(Copy, Clone)]
@@ -73,6 +103,8 @@ pub struct FetchQueueEntry {
     pub pc: u32,
     pub i: u32,
     pub predicted_taken: bool,
+    /// The cycle it was fetched in
+    pub cycle: usize,
 }
 
 pub struct OoOSpeculative<'a> {
@@ -97,6 +129,16 @@ pub struct OoOSpeculative<'a> {
     rs_control: RSSet,
 
     pub output: String,
+    pub uart: Uart,
+    pub host: HostIO,
+    // The exception number of the handler currently running, if any
+    active_exception: Option<u32>,
+    // The guest heap break for svc sbrk, the start of the heap until first moved
+    heap_break: Option<u32>,
+
+    // Anything following the pipeline, like the trace exporters
+    tracers: Vec<Box<dyn Tracer + 'a>>,
+    next_seq: u64,
 
     flush_delay: u32,
     flushing: bool,
@@ -111,18 +153,46 @@ pub struct OoOSpeculative<'a> {
     // Holds all the simulated delays of simulated operations, and
     // when they should be broadcast onto CDB
     to_broadcast: Vec<(u8, CDBRecord)>,
+    // What went out on the CDB this cycle and where each was going, for the CDB pane
+    broadcasts: Vec<(CDBRecord, ROBEntryDest)>,
+
+    // Commit slots lost or used each cycle, see cpi.rs
+    pub cpi: CpiStack,
+    // What to charge an empty ROB to after a flush, until the next commit
+    refill: Option<CpiCategory>,
+    // Issue stalls since the last accounting
+    cycle_stalls: Vec<StallReason>,
+    // Seqs of the loads in flight that execute has held back for an older store
+    blocked_loads: Vec<u64>,
 
     // Render Info
     stalls: Vec<StallReason>,
     pub epoch: usize,
+    // Cycles by the guest's clock, which unlike epoch keeps going through
+    // fast-forwarding and isn't reset to measure from somewhere
+    guest_cycles: u64,
     pub instructions_committed: usize,
     pub rs_current_display: IssueType,
     pub rob_focus: usize,
-    pub mem_bottom_offset: usize,
-    pub display_focus: usize,
-
-    // The pc of the fn and its name
-    pub call_stack: Vec<(u32, String)>,
+    pub mem_view: MemoryView,
+    /// The pane the scrolling keys go to
+    pub focus: Pane,
+    /// Where the code pane starts, none to follow the commit pc
+    pub code_top: Option<u32>,
+    /// The registers at the start of the cycle, to show what commit changed
+    regs_before: Registers,
+
+    // Followed at commit, see profile.rs
+    pub call_stack: Vec<CallFrame>,
+    pub profiler: Option<Profiler>,
+    // Breakpoints and watchpoints, see debug.rs
+    pub debug: DebugState,
+    // Filled in when statistics are asked for, see sampling.rs
+    pub stats: Option<Stats>,
+    // Snapshots for stepping back, see history.rs
+    history: Option<History>,
+    // The last cycles for the charts pane, see charts.rs
+    charts: Option<VecDeque<charts::ChartSample>>,
 
     pub halt: Option<i32>,
 }
@@ -135,6 +205,12 @@ impl<'a> OoOSpeculative<'a> {
         let rob = ROB::new();
         Self {
             output: String::new(),
+            uart: Uart::new(UART_DEFAULT_BASE, UartSource::None, UartSink::Capture(String::new())),
+            host: HostIO::new(PathBuf::from("."), Vec::new()),
+            active_exception: None,
+            heap_break: None,
+            tracers: Vec::new(),
+            next_seq: 0,
             log_fn: Box::new(log_fn),
 
             spec_pc: state.regs.pc,
@@ -155,26 +231,44 @@ impl<'a> OoOSpeculative<'a> {
             fetch_stall: false,
             load_queue: VecDeque::with_capacity(LQ_SIZE),
 
+            cpi: CpiStack::default(),
+            refill: None,
+            cycle_stalls: Vec::new(),
+            blocked_loads: Vec::new(),
             stalls: Vec::new(),
             mispredicts: 0,
             correct_predicts: 0,
             epoch: 0,
+            guest_cycles: 0,
             instructions_committed: 0,
             rs_current_display: IssueType::ALUSHIFT,
             rob_focus: 0,
             to_broadcast: Vec::new(),
             cdb: VecDeque::new(),
-            mem_bottom_offset: 0,
-            display_focus: 0,
+            broadcasts: Vec::new(),
+            mem_view: MemoryView::default(),
+            focus: Pane::Rob,
+            code_top: None,
+            regs_before: state.regs,
             halt: None,
             call_stack: Vec::new(),
+            profiler: None,
+            debug: DebugState::default(),
+            stats: None,
+            history: None,
+            charts: None,
         }
     }
 
     pub fn tick(&mut self) {
         // 6 stage pipeline
         // The pipeline stages are simulated backwards to avoid instantaneous updates
+        self.record_history();
+        self.regs_before = self.state.regs;
+        self.broadcasts.clear();
         self.epoch += 1;
+        self.guest_cycles += 1;
+        self.uart.tick();
 
         if self.flushing {
             self.flush_delay -= 1;
@@ -183,9 +277,17 @@ impl<'a> OoOSpeculative<'a> {
             }
         }
 
+        let committed_before = self.instructions_committed;
+        self.debug.stop = None;
         for _ in 0..N_ISSUE {
             self.commit();
+            if self.debug.stop.is_some() {
+                break;
+            }
         }
+        self.account_cycle(self.instructions_committed - committed_before);
+        self.sample_stats();
+        self.sample_charts(self.instructions_committed - committed_before);
         if self.flushing {
             return;
         }
@@ -216,183 +318,166 @@ impl<'a> OoOSpeculative<'a> {
 
     // -----------------------------------------------------------------
     // Rendering stuff
-    pub fn render(&self, frame: &mut Frame) {
-        use Constraint::{Fill, Length, Min};
-
-        let vertical = Layout::vertical([Min(0)]);
-        let [main_area] = vertical.areas(frame.area());
-        let horizontal = Layout::horizontal([Length(26), Length(40), Fill(20)]);
-        let [left_area, rob_area, right_area] = horizontal.areas(main_area);
-
-        let (rs_to_display, rs_to_display_n, rs_to_display_name) = match self.rs_current_display {
-            IssueType::ALUSHIFT => (&self.rs_alu_shift, 1, "ALU/Shift"),
-            IssueType::MUL => (&self.rs_mul, 2, "MUL"),
-            IssueType::LoadStore => (&self.rs_ls, 3, "Load/Store"),
-            IssueType::Control => (&self.rs_control, 4, "Control"),
-        };
-
-        frame.render_widget(Block::bordered().title("Stats"), left_area);
-        frame.render_widget(Block::bordered().title("Pipeline"), right_area);
-
-        // Get the area in the boxes
-        let left_area = left_area.inner(Margin {
-            horizontal: 1,
-            vertical: 1,
-        });
-        let right_area = right_area.inner(Margin {
-            horizontal: 1,
-            vertical: 1,
-        });
-
-        let [fb_area, iq_area, rs_area, mem_top_border, mem_area] = Layout::vertical([
-            Length((2 + N_ISSUE) as u16),
-            Length(5),
-            Length(10),
-            Length(1),
-            Fill(1),
-        ])
-        .areas(right_area);
-        let [epoch_area, rst_area, stall_area] =
-            Layout::vertical([Length(4), Length(22), Fill(1)]).areas(left_area);
-
-        let bottom_border = |name| {
-            Block::bordered()
-                .borders(Borders::BOTTOM)
-                .title(name)
-                .title_alignment(Alignment::Center)
-                .padding(Padding::left(2))
-        };
-
-        // Render epoch num
-        frame.render_widget(
-            Paragraph::new(format!(
-                "Epoch: {}\nCommitted: {}",
-                self.epoch, self.instructions_committed
-            ))
-            .block(bottom_border("")),
-            epoch_area,
-        );
-
-        let rs_str = self
-            .rob
-            .register_status
-            .iter()
-            .enumerate()
-            .map(|(i, rob_entry)| match rob_entry {
-                Some(entry) => format!("{:03} : {:08X} : #{}", Registers::reg_id_to_str(i as u8), self.state.regs.get(i as u8), entry),
-                None => format!(
-                    "{:03} : {:08X}",
-                    Registers::reg_id_to_str(i as u8),
-                    self.state.regs.get(i as u8)
-                ),
-            })
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        frame.render_widget(
-            Paragraph::new(rs_str).block(bottom_border("Register Status")),
-            rst_area,
-        );
+    pub fn render(&self, frame: &mut Frame, area: Rect, layout: &TuiLayout) {
+        for (column, column_area, panes) in layout.areas(area, |pane| self.pane_shown(pane)) {
+            frame.render_widget(Block::bordered().title(column.title.as_str()), column_area);
+            let last = panes.last().map(|(pane, _)| *pane);
+            for (pane, pane_area) in panes {
+                // Panes are separated by a line under each
+                let mut block = Block::new();
+                if Some(pane) != last {
+                    block = block.borders(Borders::BOTTOM);
+                }
+                if let Some(title) = self.pane_title(pane) {
+                    block = block.title(title).title_alignment(Alignment::Center);
+                }
+                let inner = block.inner(pane_area);
+                frame.render_widget(block, pane_area);
+                self.render_pane(pane, frame, inner);
+            }
+        }
+    }
 
-        let rob_str = self.rob.render(self.rob_focus);
+    /// Whether a pane has anything to show. Only programs with debug info get the
+    /// source pane, and the charts only have samples while the TUI is up
+    pub fn pane_shown(&self, pane: Pane) -> bool {
+        match pane {
+            Pane::Source => !self.state.mem.line_table().is_empty(),
+            Pane::Charts => self.charts.is_some(),
+            _ => true,
+        }
+    }
 
-        frame.render_widget(
-            Paragraph::new(rob_str).block(Block::bordered().title(if self.display_focus == 0 {
-                "#ROB#"
-            } else {
-                "ROB"
-            })),
-            rob_area,
-        );
+    fn pane_title(&self, pane: Pane) -> Option<String> {
+        let title = match pane {
+            Pane::Stats | Pane::Source | Pane::Charts | Pane::Queues => return None,
+            Pane::Registers => "Registers".to_string(),
+            Pane::Stalls => "Stall Status".to_string(),
+            Pane::Rob => "ROB".to_string(),
+            Pane::Fetch => "Fetch Buffer".to_string(),
+            Pane::Iq => "Instruction Queue".to_string(),
+            Pane::Rs => {
+                let (n, name) = match self.rs_current_display {
+                    IssueType::ALUSHIFT => (1, "ALU/Shift"),
+                    IssueType::MUL => (2, "MUL"),
+                    IssueType::LoadStore => (3, "Load/Store"),
+                    IssueType::Control => (4, "Control"),
+                };
+                format!("RS {}/4: {}", n, name)
+            }
+            Pane::Mem => format!("Mem: {}", self.memory_title()),
+            Pane::Code => "Code".to_string(),
+        };
+        if self.focus == pane {
+            Some(format!("#{}#", title))
+        } else {
+            Some(title)
+        }
+    }
 
-        let mem_string = self.state.mem.dump(
-            mem_area.width.into(),
-            (mem_area.height - 2).into(),
-            0x22000000,
-            self.mem_bottom_offset,
-        );
-        frame.render_widget(Block::new().borders(Borders::BOTTOM), mem_top_border);
-        frame.render_widget(
-            Paragraph::new(mem_string).block(
-                Block::new()
-                    .title(if self.display_focus == 1 {
-                        "#Mem#"
-                    } else {
-                        "Mem"
-                    })
-                    .title_alignment(Alignment::Center),
+    fn render_pane(&self, pane: Pane, frame: &mut Frame, area: Rect) {
+        use Constraint::Fill;
+
+        let padded = Block::new().padding(Padding::left(2));
+        match pane {
+            Pane::Stats => frame.render_widget(
+                Paragraph::new(format!(
+                    "Epoch: {}\nCommitted: {}",
+                    self.epoch, self.instructions_committed
+                ))
+                .block(padded),
+                area,
             ),
-            mem_area,
-        );
-
-        frame.render_widget(
-            Paragraph::new(format!(
-                "{}",
-                (0..N_ISSUE)
-                    .map(|j| {
-                        match &self.fb[j] {
+            Pane::Registers => {
+                frame.render_widget(Paragraph::new(self.register_lines()).block(padded), area)
+            }
+            Pane::Stalls => {
+                let mut stall_count: HashMap<String, usize> = HashMap::new();
+                let mut stall_reasons: HashSet<String> = HashSet::new();
+                for x in self.stalls.iter() {
+                    if *x == StallReason::IStall {
+                        continue;
+                    }
+                    let reason_string = format!("{:?}", x);
+                    *stall_count.entry(reason_string.clone()).or_default() += 1;
+                    if !stall_reasons.contains(&reason_string) {
+                        stall_reasons.insert(reason_string);
+                    }
+                }
+
+                let mut stall_string = String::new();
+                for entry in stall_reasons.into_iter().sorted() {
+                    stall_string += &format!("{}: {}\n", entry, stall_count[&entry]);
+                }
+
+                frame.render_widget(Paragraph::new(stall_string).block(padded), area);
+            }
+            Pane::Rob => frame.render_widget(Paragraph::new(self.rob.render(self.rob_focus)), area),
+            Pane::Fetch => frame.render_widget(
+                Paragraph::new(
+                    (0..N_ISSUE)
+                        .map(|j| match &self.fb[j] {
                             Some(fqe) => format!("{:08X}   Spec PC: {:08X?}", fqe.i, fqe.pc),
                             None => "-".to_string(),
-                        }
-                    })
-                    .join("\n"),
-            ))
-            .block(bottom_border("Fetch Buffer")),
-            fb_area,
-        );
-
-        let i_strs = self
-            .iq
-            .iter()
-            .enumerate()
-            .map(|(i, iqe)| format!("{}: {:<14}    {:08X?}", i, iqe.i.to_string(), iqe.pc))
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        frame.render_widget(
-            Paragraph::new(format!("{}", i_strs)).block(bottom_border("Instruction Queue")),
-            iq_area,
-        );
-
-        let mut stall_count: HashMap<String, usize> = HashMap::new();
-        let mut stall_reasons: HashSet<String> = HashSet::new();
-        for x in self.stalls.iter() {
-            if *x == StallReason::IStall {
-                continue;
-            }
-            let reason_string = format!("{:?}", x);
-            *stall_count.entry(reason_string.clone()).or_default() += 1;
-            if !stall_reasons.contains(&reason_string) {
-                stall_reasons.insert(reason_string);
+                        })
+                        .join("\n"),
+                )
+                .block(padded),
+                area,
+            ),
+            Pane::Iq => frame.render_widget(
+                Paragraph::new(
+                    self.iq
+                        .iter()
+                        .enumerate()
+                        .map(|(i, iqe)| {
+                            format!("{}: {:<14}    {:08X?}", i, iqe.i.to_string(), iqe.pc)
+                        })
+                        .join("\n"),
+                )
+                .block(padded),
+                area,
+            ),
+            Pane::Rs => self.render_rs(frame, area),
+            Pane::Source => {
+                let height = area.height as usize;
+                let [commit_area, fetch_area] = Layout::horizontal([Fill(1), Fill(1)]).areas(area);
+                frame.render_widget(
+                    Paragraph::new(self.source_view("Commit", self.commit_address(), height))
+                        .block(padded.clone()),
+                    commit_area,
+                );
+                frame.render_widget(
+                    Paragraph::new(self.source_view("Fetch", self.spec_pc, height)).block(padded),
+                    fetch_area,
+                );
             }
+            Pane::Charts => self.render_charts(frame, area),
+            Pane::Queues => self.render_queues(frame, area),
+            Pane::Mem => frame.render_widget(
+                Paragraph::new(self.memory_lines(area.width.into(), area.height.into())),
+                area,
+            ),
+            Pane::Code => frame.render_widget(
+                Paragraph::new(self.disassembly(area.height.into())),
+                area,
+            ),
         }
+    }
 
-        let mut stall_string = String::new();
-        for entry in stall_reasons.into_iter().sorted() {
-            stall_string += &format!("{}: {}\n", entry, stall_count[&entry]);
-        }
-
-        frame.render_widget(
-            Paragraph::new(stall_string).block(bottom_border("Stall Status").borders(Borders::NONE)),
-            stall_area,
-        );
-
-        // frame.render_widget(
-        //     Paragraph::new(
-        //         self.call_stack
-        //             .iter()
-        //             .fold(String::new(), |acc, x| acc + "\n" + &x.1),
-        //     ),
-        //     call_stack_area,
-        // );
-
-        let rs_title = format!("RS {}/4: {}", rs_to_display_n, rs_to_display_name);
+    fn render_rs(&self, frame: &mut Frame, area: Rect) {
+        use Constraint::{Fill, Length};
 
-        let [rs_area, rs_area_inner] = Layout::vertical([Length(1), Fill(1)]).areas(rs_area);
+        let rs_to_display = match self.rs_current_display {
+            IssueType::ALUSHIFT => &self.rs_alu_shift,
+            IssueType::MUL => &self.rs_mul,
+            IssueType::LoadStore => &self.rs_ls,
+            IssueType::Control => &self.rs_control,
+        };
 
         let [index_area, j_area, k_area, l_area, inst_area] =
             Layout::horizontal([Length(3), Length(11), Length(11), Length(11), Fill(1)])
-                .areas(rs_area_inner);
+                .areas(area);
 
         fn make_block_from_property<'a>(
             rs_to_display: &RSSet,
@@ -444,12 +529,6 @@ impl<'a> OoOSpeculative<'a> {
                 .borders(Borders::RIGHT),
         );
 
-        frame.render_widget(
-            Block::new()
-                .title(rs_title)
-                .title_alignment(Alignment::Center),
-            rs_area,
-        );
         frame.render_widget(index_block, index_area);
         frame.render_widget(j_para, j_area);
         frame.render_widget(k_para, k_area);
@@ -457,8 +536,102 @@ impl<'a> OoOSpeculative<'a> {
         frame.render_widget(inst_para, inst_area);
     }
 
+    /// A line per register with its committed value, then the ROB entry it's
+    /// renamed to and that entry's value once it's ready. Registers committed
+    /// to this cycle are highlighted
+    fn register_lines(&self) -> Vec<Line<'static>> {
+        (0..20u8)
+            .map(|n| {
+                let value = self.state.regs.get(n);
+                let mut text = format!("{:<3} {:08X}", Registers::reg_id_to_str(n), value);
+                if let Some(entry) = self.rob.register_status[n as usize] {
+                    let producer = self.rob.get(entry);
+                    let flag = match n {
+                        16 => producer.asprupdate.n,
+                        17 => producer.asprupdate.z,
+                        18 => producer.asprupdate.c,
+                        19 => producer.asprupdate.v,
+                        _ => None,
+                    };
+                    let renamed = if !producer.ready {
+                        "waiting".to_string()
+                    } else if n >= 16 {
+                        flag.map_or("-".to_string(), |flag| format!("{:08X}", flag as u32))
+                    } else {
+                        format!("{:08X}", producer.value)
+                    };
+                    text += &format!(" #{:02} {}", entry, renamed);
+                }
+                if value != self.regs_before.get(n) {
+                    Line::styled(text, Style::new().add_modifier(Modifier::REVERSED))
+                } else {
+                    Line::raw(text)
+                }
+            })
+            .collect()
+    }
+
+    /// Where the code at addr came from, with the lines around it up to height
+    /// lines in all
+    fn source_view(&self, name: &str, addr: u32, height: usize) -> String {
+        let lines = self.state.mem.line_table();
+        let function = match self.state.mem.function_containing(addr) {
+            Some((_, function)) => function.as_str(),
+            None => "?",
+        };
+        let Some(line) = lines.line_at(addr) else {
+            return format!("{}: in {}, no source line", name, function);
+        };
+        let mut view = format!("{}: in {}, {}", name, function, lines.describe(line));
+        let first = line.line.saturating_sub((height as u32 - 1) / 2).max(1);
+        for n in first..first + height as u32 - 1 {
+            let Some(text) = lines.text(SourceLine { line: n, ..line }) else {
+                break;
+            };
+            let marker = if n == line.line { '>' } else { ' ' };
+            view += &format!("\n{}{:>4} {}", marker, n, text.replace('\t', "    "));
+        }
+        view
+    }
+
+    pub fn add_tracer(&mut self, tracer: Box<dyn Tracer + 'a>) {
+        self.tracers.push(tracer);
+    }
+
+    /// Flush the tracers at the end of the run
+    pub fn finish_tracing(&mut self) -> std::io::Result<()> {
+        for tracer in self.tracers.iter_mut() {
+            tracer.finish()?;
+        }
+        Ok(())
+    }
+
+    fn trace(&mut self, event: PipelineEvent) {
+        for tracer in self.tracers.iter_mut() {
+            tracer.event(self.epoch, &event);
+        }
+    }
+
     fn stall(&mut self, reason: StallReason) {
         self.stalls.push(reason);
+        self.cycle_stalls.push(reason);
+    }
+
+    /// Scroll a pane by lines, down for positive
+    pub fn scroll_pane(&mut self, pane: Pane, lines: i32) {
+        for _ in 0..lines.unsigned_abs() {
+            match pane {
+                // Up the screen is back through the ROB
+                Pane::Rob if lines < 0 => self.rob_focus_down(),
+                Pane::Rob => self.rob_focus_up(),
+                _ => {}
+            }
+        }
+        match pane {
+            Pane::Mem => self.mem_scroll(lines),
+            Pane::Code => self.code_scroll(lines),
+            _ => {}
+        }
     }
 
     pub fn rob_focus_up(&mut self) {
@@ -483,5 +656,26 @@ impl<'a> OoOSpeculative<'a> {
         self.rs_control.empty();
         self.rs_ls.empty();
         self.epoch = 0;
+        // The snapshots are from before the reset
+        if self.history.is_some() {
+            self.enable_history();
+        }
     }
 }
+
+/// A core on the fib benchmark's memory with code, hand-assembled Thumb
+/// halfwords, written over its entry point
+#[cfg(test)]
+pub(crate) fn test_cpu(code: &[u16]) -> OoOSpeculative<'static> {
+    use crate::model::{LoadOptions, Memory};
+    let mut regs = Registers::new();
+    let path = "programs/benchmarks/fib.out";
+    let mem = Memory::load(path, &LoadOptions::default(), &mut regs).unwrap();
+    let mut cpu = OoOSpeculative::new(ProcessorState { regs, mem }, |_| {});
+    let bytes: Vec<u8> = code
+        .iter()
+        .flat_map(|halfword| halfword.to_le_bytes())
+        .collect();
+    cpu.write_memory(cpu.state.regs.pc, &bytes).unwrap();
+    cpu
+}
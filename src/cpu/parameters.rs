@@ -15,4 +15,6 @@ pub const N_ALUSHIFT_RS: usize = 12;
 pub const N_MUL_RS: usize = 12;
 pub const N_CNTRL_RS: usize = 12;
 pub const N_LS_RS: usize = 12;
-pub const STORE_LOAD_FORWARDING : bool = true;
\ No newline at end of file
+pub const STORE_LOAD_FORWARDING : bool = true;
+/// Simulated clock, for turning cycles into time for guest programs
+pub const CLOCK_HZ: u64 = 48_000_000;
@@ -0,0 +1,199 @@
+//! Per function profiling from the ELF symbol table. The call stack is followed at
+//! commit, so it is architectural: BL and BLX push a frame, reaching a frame's
+//! return address pops it, and landing in a function further down the stack
+//! unwinds to it. Landing in a function not on the stack, like an exception
+//! handler or a tail call, pushes it without a return address.
+//!
+//! Each cycle and lost commit slot is charged to the stack as it stands at the end
+//! of commit, and each committed mop and mispredict to the stack it committed on.
+//! Exclusive counts go to the innermost function, inclusive counts to every
+//! function on the stack, once each however deep the recursion.
+use super::*;
+use crate::checkpoint::persist_fields;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+#[derive(Clone)]
+pub struct CallFrame {
+    /// Start address of the function
+    pub function: u32,
+    pub name: String,
+    /// Where the call returns to, none if it wasn't entered by a call
+    pub return_addr: Option<u32>,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Counts {
+    pub cycles: u64,
+    pub instructions: u64,
+    pub mispredicts: u64,
+    /// Commit slots lost, as counted by the CPI stack
+    pub stall_slots: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct FunctionProfile {
+    pub exclusive: Counts,
+    pub inclusive: Counts,
+}
+
+#[derive(Default, Clone)]
+pub struct Profiler {
+    pub functions: HashMap<String, FunctionProfile>,
+    /// Cycles by call stack, outermost function first and joined with ;
+    folded: HashMap<String, u64>,
+}
+
+impl Profiler {
+    fn charge(&mut self, stack: &[CallFrame], add: impl Fn(&mut Counts)) {
+        let Some(top) = stack.last() else {
+            return;
+        };
+        add(&mut self
+            .functions
+            .entry(top.name.clone())
+            .or_default()
+            .exclusive);
+        let mut seen = HashSet::new();
+        for frame in stack {
+            if seen.insert(&frame.name) {
+                add(&mut self
+                    .functions
+                    .entry(frame.name.clone())
+                    .or_default()
+                    .inclusive);
+            }
+        }
+    }
+
+    /// Functions sorted by inclusive cycles, each count inclusive then exclusive
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{:<24}{:>21}{:>21}{:>15}{:>17}\n",
+            "function", "cycles", "instructions", "mispredicts", "stall slots"
+        );
+        report += &format!(
+            "{:<24}{:>11}{:>10}{:>11}{:>10}{:>8}{:>7}{:>9}{:>8}\n",
+            "", "incl", "excl", "incl", "excl", "incl", "excl", "incl", "excl"
+        );
+        let by_cycles = self
+            .functions
+            .iter()
+            .sorted_by_key(|(name, f)| (std::cmp::Reverse(f.inclusive.cycles), *name));
+        for (name, f) in by_cycles {
+            let (incl, excl) = (f.inclusive, f.exclusive);
+            report += &format!(
+                "{:<24}{:>11}{:>10}{:>11}{:>10}{:>8}{:>7}{:>9}{:>8}\n",
+                name,
+                incl.cycles,
+                excl.cycles,
+                incl.instructions,
+                excl.instructions,
+                incl.mispredicts,
+                excl.mispredicts,
+                incl.stall_slots,
+                excl.stall_slots
+            );
+        }
+        report.pop();
+        report
+    }
+
+    /// Folded stacks weighted by cycles, the input flamegraph.pl and inferno take
+    pub fn write_folded(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for (stack, cycles) in self.folded.iter().sorted() {
+            writeln!(out, "{} {}", stack, cycles)?;
+        }
+        out.flush()
+    }
+}
+
+impl<'a> OoOSpeculative<'a> {
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::default());
+    }
+
+    /// Follow the call stack across a committed instruction, given the pc after it
+    /// as the ROB has it and where it went. Called for the last mop of an
+    /// instruction, only that one knows where the instruction goes next
+    pub(super) fn update_call_stack(&mut self, it: IT, pc: u32, next_pc: u32) {
+        if matches!(it, IT::BL | IT::BLX) {
+            self.push_frame(next_pc, Some(pc));
+        } else if self
+            .call_stack
+            .last()
+            .is_some_and(|frame| frame.return_addr == Some(next_pc))
+        {
+            self.call_stack.pop();
+        }
+
+        let Some((function, _)) = self.state.mem.function_containing(next_pc) else {
+            return;
+        };
+        if self
+            .call_stack
+            .last()
+            .is_some_and(|frame| frame.function == function)
+        {
+            return;
+        }
+        match self
+            .call_stack
+            .iter()
+            .rposition(|frame| frame.function == function)
+        {
+            Some(depth) => self.call_stack.truncate(depth + 1),
+            None => self.push_frame(next_pc, None),
+        }
+    }
+
+    fn push_frame(&mut self, pc: u32, return_addr: Option<u32>) {
+        if let Some((function, name)) = self.state.mem.function_containing(pc) {
+            self.call_stack.push(CallFrame {
+                function,
+                name: name.clone(),
+                return_addr,
+            });
+        }
+    }
+
+    pub(super) fn profile_commit(&mut self, mispredicted: bool) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.charge(&self.call_stack, |counts| {
+                counts.instructions += 1;
+                counts.mispredicts += mispredicted as u64;
+            });
+        }
+    }
+
+    pub(super) fn profile_cycle(&mut self, lost_slots: u64) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.charge(&self.call_stack, |counts| {
+                counts.cycles += 1;
+                counts.stall_slots += lost_slots;
+            });
+            if !self.call_stack.is_empty() {
+                let stack = self.call_stack.iter().map(|frame| &frame.name).join(";");
+                *profiler.folded.entry(stack).or_default() += 1;
+            }
+        }
+    }
+}
+
+persist_fields!(CallFrame {
+    function,
+    name,
+    return_addr
+});
+persist_fields!(Counts {
+    cycles,
+    instructions,
+    mispredicts,
+    stall_slots
+});
+persist_fields!(FunctionProfile {
+    exclusive,
+    inclusive
+});
+persist_fields!(Profiler { functions, folded });
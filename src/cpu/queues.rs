@@ -0,0 +1,119 @@
+//! The load queue, pending broadcast and CDB panes. Loads show what execute
+//! would do with them next cycle, the results waiting to broadcast how many
+//! cycles are left, and the CDB what went out on it this cycle, as the queue
+//! itself is always empty by the end of writeback.
+use super::*;
+
+impl<'a> OoOSpeculative<'a> {
+    fn load_queue_lines(&self) -> Vec<String> {
+        self.load_queue
+            .iter()
+            .map(|entry| {
+                let load_type = format!("{:?}", entry.load_type);
+                let status =
+                    if self.uart.contains(entry.address) && entry.rob_entry != self.rob.head {
+                        "waits for head".to_string()
+                    } else {
+                        match self.rob.load_can_go(entry) {
+                            (false, _) => "blocked by a store".to_string(),
+                            (true, Some(value)) => format!("forwarded {:08X}", value),
+                            (true, None) => "ready".to_string(),
+                        }
+                    };
+                format!(
+                    "{:02}  {:08X}  {:<8} {}",
+                    entry.rob_entry, entry.address, load_type, status
+                )
+            })
+            .collect()
+    }
+
+    /// What a broadcast is for, from where its ROB entry's result goes
+    fn broadcast_kind(record: &CDBRecord, dest: ROBEntryDest) -> String {
+        if record.is_branch_target {
+            return "target".to_string();
+        }
+        match dest {
+            ROBEntryDest::AwaitingAddress => "address".to_string(),
+            ROBEntryDest::Register(n) => Registers::reg_id_to_str(n),
+            ROBEntryDest::Address(_) | ROBEntryDest::None => "-".to_string(),
+        }
+    }
+
+    fn to_broadcast_lines(&self) -> Vec<String> {
+        let mut pending: Vec<&(u8, CDBRecord)> = self.to_broadcast.iter().collect();
+        pending.sort_by_key(|(delay, _)| *delay);
+        pending
+            .into_iter()
+            .map(|(delay, record)| {
+                let dest = self.rob.get(record.rob_number).dest;
+                format!(
+                    "{:02}  {}  {:<7} {:08X}",
+                    record.rob_number,
+                    delay,
+                    Self::broadcast_kind(record, dest),
+                    record.result
+                )
+            })
+            .collect()
+    }
+
+    fn cdb_lines(&self) -> Vec<String> {
+        self.broadcasts
+            .iter()
+            .map(|(record, dest)| {
+                format!(
+                    "{:02}  {:<7} {:08X}",
+                    record.rob_number,
+                    Self::broadcast_kind(record, *dest),
+                    record.result
+                )
+            })
+            .collect()
+    }
+
+    pub(super) fn render_queues(&self, frame: &mut Frame, area: Rect) {
+        use Constraint::{Fill, Length};
+
+        let [lq_area, broadcast_area, cdb_area] =
+            Layout::horizontal([Fill(3), Fill(2), Fill(2)]).areas(area);
+        let panes = [
+            (
+                format!("Load queue {}/{}", self.load_queue.len(), LQ_SIZE),
+                "ROB address   type     next",
+                self.load_queue_lines(),
+                lq_area,
+            ),
+            (
+                "To broadcast".to_string(),
+                "ROB in result  value",
+                self.to_broadcast_lines(),
+                broadcast_area,
+            ),
+            (
+                "CDB this cycle".to_string(),
+                "ROB result  value",
+                self.cdb_lines(),
+                cdb_area,
+            ),
+        ];
+        for (title, header, mut lines, area) in panes {
+            let [title_area, area] = Layout::vertical([Length(1), Fill(1)]).areas(area);
+            frame.render_widget(
+                Block::new()
+                    .title(title)
+                    .title_alignment(Alignment::Center),
+                title_area,
+            );
+            // Room for the header, and to say how many more there are
+            let rows = (area.height as usize).saturating_sub(1);
+            if lines.len() > rows {
+                let more = lines.len() + 1 - rows;
+                lines.truncate(rows.saturating_sub(1));
+                lines.push(format!("{} more", more));
+            }
+            lines.insert(0, header.to_string());
+            frame.render_widget(Paragraph::new(lines.join("\n")), area);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Feeding the statistics registry. Structures are sampled once a cycle just after
+//! commit, and run totals are filled in when the stats are written out.
+use super::*;
+use crate::stats::{stat_key, Sampled, Stats};
+use crate::trace::Unit;
+
+impl<'a> OoOSpeculative<'a> {
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(Stats::default());
+    }
+
+    pub(super) fn sample_stats(&mut self) {
+        let Some(stats) = self.stats.as_mut() else {
+            return;
+        };
+        self.rob.sample(stats, "rob");
+        self.rs_alu_shift.sample(stats, "rs.alu_shift");
+        self.rs_mul.sample(stats, "rs.mul");
+        self.rs_control.sample(stats, "rs.control");
+        self.rs_ls.sample(stats, "rs.load_store");
+        self.btb.sample(stats, "btb");
+        self.load_queue.sample(stats, "load_queue");
+        self.iq.sample(stats, "iq");
+        self.to_broadcast.sample(stats, "to_broadcast");
+        let fetch_buffer = self.fb.iter().flatten().count();
+        stats.record("fetch_buffer.occupancy", fetch_buffer);
+    }
+
+    pub(super) fn count_unit_busy(&mut self, unit: Unit) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.add(&format!("unit.{}.busy", stat_key(&unit.to_string())), 1);
+        }
+    }
+
+    pub(super) fn count_cdb_broadcasts(&mut self, broadcasts: usize) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record("cdb.broadcasts", broadcasts);
+        }
+    }
+
+    /// The stats with the run totals and utilisations filled in
+    pub fn final_stats(&mut self) -> Option<&Stats> {
+        let cycles = self.epoch as u64;
+        let stats = self.stats.as_mut()?;
+        stats.set("cycles", cycles);
+        stats.set("instructions", self.instructions_committed as u64);
+        stats.set("branch.mispredicts", self.mispredicts as u64);
+        stats.set("branch.correct_predicts", self.correct_predicts as u64);
+        stats.set_ratio(
+            "ipc",
+            self.instructions_committed as f64 / cycles.max(1) as f64,
+        );
+        for category in CpiCategory::ALL {
+            let name = format!("cpi_stack.{}", stat_key(&category.to_string()));
+            stats.set(&name, self.cpi.get(category));
+        }
+
+        let units = (0..N_ALUSHIFTERS)
+            .map(Unit::AluShift)
+            .chain((0..N_MULS).map(Unit::Mul))
+            .chain((0..N_CONTROL).map(Unit::Control))
+            .chain((0..N_LS_EXECS).map(Unit::LoadStore));
+        for unit in units {
+            let key = stat_key(&unit.to_string());
+            let busy = stats.counter(&format!("unit.{}.busy", key));
+            stats.set_ratio(
+                &format!("unit.{}.utilisation", key),
+                busy as f64 / cycles.max(1) as f64,
+            );
+        }
+        // wb doesn't run while flushing, so this is over all cycles rather than samples
+        let broadcasts = stats
+            .histogram("cdb.broadcasts")
+            .map_or(0.0, |h| h.mean() * h.samples() as f64);
+        stats.set_ratio(
+            "cdb.utilisation",
+            broadcasts / (CDB_WIDTH as u64 * cycles.max(1)) as f64,
+        );
+        Some(stats)
+    }
+}
@@ -0,0 +1,282 @@
+//! ARM semihosting, trapped with BKPT 0xAB. The operation is in r0, r1 is either the
+//! argument or a pointer to a block of word arguments, and the result goes in r0.
+//! Calls happen at commit, where BKPT is serializing, so memory and registers are
+//! architecturally exact.
+use super::*;
+use crate::binary::unsigned_to_signed_bitcast;
+use crate::model::{errno, HostFile, OpenMode};
+use std::io::{self, SeekFrom};
+
+pub const SEMIHOSTING_BKPT: u32 = 0xAB;
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_READC: u32 = 0x07;
+const SYS_ISERROR: u32 = 0x08;
+const SYS_ISTTY: u32 = 0x09;
+const SYS_SEEK: u32 = 0x0A;
+const SYS_FLEN: u32 = 0x0C;
+const SYS_REMOVE: u32 = 0x0E;
+const SYS_RENAME: u32 = 0x0F;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_TIME: u32 = 0x11;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_GET_CMDLINE: u32 = 0x15;
+const SYS_HEAPINFO: u32 = 0x16;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+const SYS_ELAPSED: u32 = 0x30;
+const SYS_TICKFREQ: u32 = 0x31;
+
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// Magic file names recognised by SYS_OPEN
+const CONSOLE: &str = ":tt";
+const FEATURES: &str = ":semihosting-features";
+/// "SHFB" then one byte of feature bits, we support SYS_EXIT_EXTENDED only
+const FEATURE_BYTES: [u8; 5] = [0x53, 0x48, 0x46, 0x42, 0b01];
+
+const ERROR: u32 = u32::MAX;
+
+impl<'a> OoOSpeculative<'a> {
+    pub(super) fn semihost(&mut self) {
+        let op = self.state.regs.gp[0];
+        let arg = self.state.regs.gp[1];
+
+        let result = match op {
+            SYS_OPEN => {
+                let path = self.sh_string(self.sh_arg(arg, 0), self.sh_arg(arg, 2));
+                let mode = self.sh_arg(arg, 1);
+                self.sh_open(&path, mode)
+            }
+            SYS_CLOSE => {
+                let result = self.host.close(self.sh_arg(arg, 0));
+                self.sh_result(result.map(|_| 0))
+            }
+            SYS_WRITEC => {
+                let c = self.state.mem.get_byte_nolog(arg);
+                self.output.push(c as char);
+                return;
+            }
+            SYS_WRITE0 => {
+                let s = match self.state.mem.read_cstring(arg) {
+                    Ok(s) => s,
+                    Err(e) => panic!("{:?}: SYS_WRITE0 string at {:08X?}", e, arg),
+                };
+                self.output += &s;
+                return;
+            }
+            // Returns the number of bytes not written
+            SYS_WRITE => {
+                let (fd, buf, len) = (self.sh_arg(arg, 0), self.sh_arg(arg, 1), self.sh_arg(arg, 2));
+                let bytes = match self.state.mem.read_bytes(buf, len) {
+                    Ok(bytes) => bytes,
+                    Err(e) => panic!("{:?}: SYS_WRITE buffer at {:08X?}", e, buf),
+                };
+                match self.guest_write(fd, &bytes) {
+                    Ok(n) => len - n as u32,
+                    Err(e) => {
+                        self.host.last_errno = errno(&e);
+                        len
+                    }
+                }
+            }
+            // Returns the number of bytes not read, so len is end of file
+            SYS_READ => {
+                let (fd, buf, len) = (self.sh_arg(arg, 0), self.sh_arg(arg, 1), self.sh_arg(arg, 2));
+                match self.guest_read(fd, buf, len, "SYS_READ") {
+                    Ok(n) => len - n,
+                    Err(e) => {
+                        self.host.last_errno = errno(&e);
+                        ERROR
+                    }
+                }
+            }
+            SYS_READC => match self.host.read(0, 1) {
+                Ok(bytes) if !bytes.is_empty() => bytes[0] as u32,
+                _ => ERROR,
+            },
+            SYS_ISERROR => (unsigned_to_signed_bitcast(self.sh_arg(arg, 0)) < 0) as u32,
+            SYS_ISTTY => {
+                let result = self.host.is_console(self.sh_arg(arg, 0));
+                self.sh_result(result.map(|tty| tty as u32))
+            }
+            SYS_SEEK => {
+                let (fd, pos) = (self.sh_arg(arg, 0), self.sh_arg(arg, 1));
+                let result = self.host.seek(fd, SeekFrom::Start(pos as u64));
+                self.sh_result(result.map(|_| 0))
+            }
+            SYS_FLEN => {
+                let result = self.host.len(self.sh_arg(arg, 0));
+                self.sh_result(result.map(|len| len as u32))
+            }
+            SYS_REMOVE => {
+                let path = self.sh_string(self.sh_arg(arg, 0), self.sh_arg(arg, 1));
+                let result = self.host.remove(&path);
+                self.sh_result(result.map(|_| 0))
+            }
+            SYS_RENAME => {
+                let from = self.sh_string(self.sh_arg(arg, 0), self.sh_arg(arg, 1));
+                let to = self.sh_string(self.sh_arg(arg, 2), self.sh_arg(arg, 3));
+                let result = self.host.rename(&from, &to);
+                self.sh_result(result.map(|_| 0))
+            }
+            // Centiseconds of simulated time
+            SYS_CLOCK => (self.guest_cycles * 100 / CLOCK_HZ) as u32,
+            SYS_TIME => (self.host.start_time + self.guest_cycles / CLOCK_HZ) as u32,
+            SYS_ERRNO => self.host.last_errno,
+            // The block is a buffer and its length, which is updated to the string length
+            SYS_GET_CMDLINE => {
+                let (buf, len) = (self.sh_arg(arg, 0), self.sh_arg(arg, 1));
+                let mut cmdline = self.host.cmdline.join(" ").into_bytes();
+                if cmdline.len() as u32 >= len {
+                    ERROR
+                } else {
+                    let cmdline_len = cmdline.len() as u32;
+                    cmdline.push(0);
+                    self.sh_write_words(arg + 4, &[cmdline_len]);
+                    if let Err(e) = self.state.mem.write_bytes(buf, &cmdline) {
+                        panic!("{:?}: SYS_GET_CMDLINE buffer at {:08X?}", e, buf)
+                    }
+                    0
+                }
+            }
+            // r1 points to the address of a block of heap base, heap limit, stack base, stack limit
+            SYS_HEAPINFO => {
+                let block = self.sh_arg(arg, 0);
+                let stack_base = self.state.mem.ram_end();
+                let stack_limit =
+                    stack_base - self.state.mem.get_symbol("__stack_size").unwrap_or(0);
+                let heap_base = self
+                    .state
+                    .mem
+                    .get_symbol("__heap_start")
+                    .or(self.state.mem.get_symbol("end"))
+                    .unwrap_or(0);
+                let heap_limit = self.state.mem.get_symbol("__heap_end").unwrap_or(stack_limit);
+                self.sh_write_words(block, &[heap_base, heap_limit, stack_base, stack_limit]);
+                0
+            }
+            SYS_EXIT => {
+                self.halt = Some(if arg == ADP_STOPPED_APPLICATION_EXIT { 0 } else { 1 });
+                return;
+            }
+            SYS_EXIT_EXTENDED => {
+                let (reason, code) = (self.sh_arg(arg, 0), self.sh_arg(arg, 1));
+                self.halt = Some(if reason == ADP_STOPPED_APPLICATION_EXIT {
+                    unsigned_to_signed_bitcast(code)
+                } else {
+                    1
+                });
+                return;
+            }
+            // Cycles since the start as a 64 bit value written to the block
+            SYS_ELAPSED => {
+                let cycles = self.guest_cycles;
+                self.sh_write_words(arg, &[cycles as u32, (cycles >> 32) as u32]);
+                0
+            }
+            SYS_TICKFREQ => CLOCK_HZ as u32,
+            _ => ERROR,
+        };
+
+        self.state.regs.set(0, result);
+    }
+
+    fn sh_open(&mut self, path: &str, mode: u32) -> u32 {
+        if path == CONSOLE {
+            // r, w and a modes pick stdin, stdout and stderr
+            return match mode {
+                0..=3 => 0,
+                4..=7 => 1,
+                _ => 2,
+            };
+        }
+        if path == FEATURES {
+            return self
+                .host
+                .insert(HostFile::Buffer(io::Cursor::new(FEATURE_BYTES.to_vec())));
+        }
+
+        // Binary and text modes are the same here
+        let mode = match mode / 2 {
+            0 => OpenMode::Read,
+            1 => OpenMode::ReadWrite,
+            2 => OpenMode::Write,
+            3 => OpenMode::ReadWriteTruncate,
+            4 => OpenMode::Append,
+            _ => OpenMode::ReadAppend,
+        };
+        let result = self.host.open(path, mode);
+        self.sh_result(result)
+    }
+
+    /// Console writes go to the program output, files to the host
+    pub(super) fn guest_write(&mut self, fd: u32, bytes: &[u8]) -> io::Result<usize> {
+        match self.host.get(fd)? {
+            HostFile::Stdout | HostFile::Stderr => {
+                self.output += &String::from_utf8_lossy(bytes);
+                Ok(bytes.len())
+            }
+            _ => self.host.write_file(fd, bytes),
+        }
+    }
+
+    /// Read up to len bytes into the guest's buf, but no further than writable
+    /// memory goes, so a wild len can't make the host allocate gigabytes
+    pub(super) fn guest_read(
+        &mut self,
+        fd: u32,
+        buf: u32,
+        len: u32,
+        call: &str,
+    ) -> io::Result<u32> {
+        let writable = self.state.mem.writable_from(buf);
+        if len > 0 && writable == 0 {
+            panic!("{} buffer at {:08X?} isn't in ram", call, buf)
+        }
+        let bytes = self.host.read(fd, len.min(writable) as usize)?;
+        if let Err(e) = self.state.mem.write_bytes(buf, &bytes) {
+            panic!("{:?}: {} buffer at {:08X?}", e, call, buf)
+        }
+        Ok(bytes.len() as u32)
+    }
+
+    fn sh_result(&mut self, result: io::Result<u32>) -> u32 {
+        match result {
+            Ok(n) => n,
+            Err(e) => {
+                self.host.last_errno = errno(&e);
+                ERROR
+            }
+        }
+    }
+
+    /// The nth word of the argument block
+    fn sh_arg(&self, block: u32, n: u32) -> u32 {
+        match self.state.mem.get_word(block + 4 * n) {
+            Ok(word) => word,
+            Err(e) => panic!("{:?}: semihosting argument block at {:08X?}", e, block),
+        }
+    }
+
+    fn sh_string(&self, addr: u32, len: u32) -> String {
+        match self.state.mem.read_bytes(addr, len) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(e) => panic!("{:?}: semihosting string at {:08X?}", e, addr),
+        }
+    }
+
+    fn sh_write_words(&mut self, addr: u32, words: &[u32]) {
+        for (n, word) in words.iter().enumerate() {
+            let word_addr = addr + 4 * n as u32;
+            if let Err(e) = self.state.mem.set_word(word_addr, *word) {
+                panic!("{:?}: semihosting result at {:08X?}", e, word_addr)
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! The SVC system call ABI, see programs/syscalls/syscalls.h for the guest side.
+//! The call number is the SVC immediate, arguments are in r0-r2 and the result
+//! goes in r0 (and r1 for 64 bit results). Failed calls return -1 and the error
+//! can be fetched with SVC_ERRNO. Like semihosting, calls happen at commit.
+//!
+//! | svc | call                           | result                          |
+//! |-----|--------------------------------|---------------------------------|
+//! | 0   | exit(code)                     | -                               |
+//! | 1   | puts(str)                      | -                               |
+//! | 2   | gets(buf, len)                 | buf, or 0 at end of input       |
+//! | 3   | putint(n)                      | -                               |
+//! | 4   | read(fd, buf, len)             | bytes read                      |
+//! | 5   | write(fd, buf, len)            | bytes written                   |
+//! | 6   | open(path, flags)              | fd, flags are newlib's O_*      |
+//! | 7   | close(fd)                      | 0                               |
+//! | 8   | lseek(fd, offset, whence)      | new offset                      |
+//! | 9   | fstat(fd, struct svc_stat *)   | 0                               |
+//! | 10  | gettimeofday()                 | r0 seconds, r1 microseconds     |
+//! | 11  | clock()                        | microseconds since the start    |
+//! | 12  | cycles()                       | r0 low word, r1 high word       |
+//! | 13  | sbrk(increment)                | the previous heap break         |
+//! | 14  | errno()                        | errno of the last failed call   |
+//! | 15  | marker()                       | -, where fast-forwarding stops  |
+use super::*;
+use crate::binary::unsigned_to_signed_bitcast;
+use crate::model::errno;
+use std::fs::OpenOptions;
+use std::io::{self, SeekFrom};
+
+pub const SVC_EXIT: u32 = 0;
+pub const SVC_PUTS: u32 = 1;
+pub const SVC_GETS: u32 = 2;
+pub const SVC_PUTINT: u32 = 3;
+pub const SVC_READ: u32 = 4;
+pub const SVC_WRITE: u32 = 5;
+pub const SVC_OPEN: u32 = 6;
+pub const SVC_CLOSE: u32 = 7;
+pub const SVC_LSEEK: u32 = 8;
+pub const SVC_FSTAT: u32 = 9;
+pub const SVC_GETTIMEOFDAY: u32 = 10;
+pub const SVC_CLOCK: u32 = 11;
+pub const SVC_CYCLES: u32 = 12;
+pub const SVC_SBRK: u32 = 13;
+pub const SVC_ERRNO: u32 = 14;
+pub const SVC_MARKER: u32 = 15;
+
+// newlib open flags
+const O_ACCMODE: u32 = 0x3;
+const O_WRONLY: u32 = 0x1;
+const O_RDWR: u32 = 0x2;
+const O_APPEND: u32 = 0x8;
+const O_CREAT: u32 = 0x200;
+const O_TRUNC: u32 = 0x400;
+const O_EXCL: u32 = 0x800;
+
+// st_mode file types
+const S_IFCHR: u32 = 0o020000;
+const S_IFREG: u32 = 0o100000;
+
+const ENOMEM: u32 = 12;
+const EINVAL: u32 = 22;
+
+const ERROR: u32 = u32::MAX;
+
+impl<'a> OoOSpeculative<'a> {
+    pub(super) fn supervisor_call(&mut self, svc_num: u32) {
+        let [r0, r1, r2] = [0, 1, 2].map(|n| self.state.regs.gp[n]);
+
+        let result = match svc_num {
+            // The halt itself is flagged in execute
+            SVC_EXIT => return,
+            SVC_PUTS => {
+                let s = match self.state.mem.read_cstring(r0) {
+                    Ok(s) => s,
+                    Err(e) => panic!("{:?}: svc puts string at {:08X?}", e, r0),
+                };
+                self.output += &s;
+                return;
+            }
+            SVC_GETS => self.svc_gets(r0, r1),
+            SVC_PUTINT => {
+                self.output += &format!("{}", r0);
+                return;
+            }
+            SVC_READ => {
+                let result = self.guest_read(r0, r1, r2, "svc read");
+                self.svc_result(result)
+            }
+            SVC_WRITE => {
+                let bytes = match self.state.mem.read_bytes(r1, r2) {
+                    Ok(bytes) => bytes,
+                    Err(e) => panic!("{:?}: svc write buffer at {:08X?}", e, r1),
+                };
+                let result = self.guest_write(r0, &bytes);
+                self.svc_result(result.map(|n| n as u32))
+            }
+            SVC_OPEN => {
+                let path = match self.state.mem.read_cstring(r0) {
+                    Ok(path) => path,
+                    Err(e) => panic!("{:?}: svc open path at {:08X?}", e, r0),
+                };
+                let result = self.host.open_with(&path, &open_options(r1));
+                self.svc_result(result)
+            }
+            SVC_CLOSE => {
+                let result = self.host.close(r0);
+                self.svc_result(result.map(|_| 0))
+            }
+            SVC_LSEEK => {
+                let offset = unsigned_to_signed_bitcast(r1) as i64;
+                let pos = match r2 {
+                    0 => Ok(SeekFrom::Start(offset as u64)),
+                    1 => Ok(SeekFrom::Current(offset)),
+                    2 => Ok(SeekFrom::End(offset)),
+                    _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "bad whence")),
+                };
+                let result = pos.and_then(|pos| self.host.seek(r0, pos));
+                self.svc_result(result.map(|pos| pos as u32))
+            }
+            // struct svc_stat { st_mode, st_size }
+            SVC_FSTAT => {
+                let stat = match self.host.is_console(r0) {
+                    Ok(true) => Ok([S_IFCHR, 0]),
+                    Ok(false) => self.host.len(r0).map(|len| [S_IFREG, len as u32]),
+                    Err(e) => Err(e),
+                };
+                match stat {
+                    Ok(stat) => {
+                        for (n, word) in stat.iter().enumerate() {
+                            let addr = r1 + 4 * n as u32;
+                            if let Err(e) = self.state.mem.set_word(addr, *word) {
+                                panic!("{:?}: svc fstat buffer at {:08X?}", e, addr)
+                            }
+                        }
+                        0
+                    }
+                    Err(e) => self.svc_error(&e),
+                }
+            }
+            SVC_GETTIMEOFDAY => {
+                let micros = self.simulated_micros();
+                self.state.regs.gp[1] = (micros % 1_000_000) as u32;
+                (self.host.start_time + micros / 1_000_000) as u32
+            }
+            SVC_CLOCK => self.simulated_micros() as u32,
+            SVC_CYCLES => {
+                let cycles = self.guest_cycles;
+                self.state.regs.gp[1] = (cycles >> 32) as u32;
+                cycles as u32
+            }
+            SVC_SBRK => self.svc_sbrk(unsigned_to_signed_bitcast(r0)),
+            SVC_ERRNO => self.host.last_errno,
+            // Only means something to fast-forwarding, see functional.rs
+            SVC_MARKER => return,
+            _ => panic!(
+                "Invalid svc #{} at {:08X?}",
+                svc_num,
+                self.state.regs.pc - 2
+            ),
+        };
+
+        self.state.regs.set(0, result);
+    }
+
+    /// Read a line from the guest stdin into buf, without the newline. Like
+    /// fgets, at most len - 1 bytes are read and the rest of a longer line is
+    /// left for the next call
+    fn svc_gets(&mut self, buf: u32, len: u32) -> u32 {
+        if len == 0 {
+            self.host.last_errno = EINVAL;
+            return ERROR;
+        }
+        let mut line = Vec::new();
+        while line.len() + 1 < len as usize {
+            match self.host.read(0, 1) {
+                Ok(byte) if byte.is_empty() && line.is_empty() => return 0,
+                Ok(byte) if byte.is_empty() || byte[0] == b'\n' => break,
+                Ok(byte) => line.push(byte[0]),
+                Err(e) => return self.svc_error(&e),
+            }
+        }
+        line.push(0);
+        if let Err(e) = self.state.mem.write_bytes(buf, &line) {
+            panic!("{:?}: svc gets buffer at {:08X?}", e, buf)
+        }
+        buf
+    }
+
+    /// The heap runs from __heap_start up to __heap_end from the linker script.
+    /// Images without symbols, like raw binaries, have no heap
+    fn svc_sbrk(&mut self, increment: i32) -> u32 {
+        let Some(heap_start) = self.state.mem.get_symbol("__heap_start") else {
+            self.host.last_errno = ENOMEM;
+            return ERROR;
+        };
+        let heap_end = self
+            .state
+            .mem
+            .get_symbol("__heap_end")
+            .unwrap_or(self.state.mem.ram_end());
+        let brk = self.heap_break.unwrap_or(heap_start);
+        let new_brk = brk as i64 + increment as i64;
+        if new_brk < heap_start as i64 || new_brk > heap_end as i64 {
+            self.host.last_errno = ENOMEM;
+            return ERROR;
+        }
+        self.heap_break = Some(new_brk as u32);
+        brk
+    }
+
+    fn simulated_micros(&self) -> u64 {
+        self.guest_cycles * 1_000_000 / CLOCK_HZ
+    }
+
+    fn svc_result(&mut self, result: io::Result<u32>) -> u32 {
+        match result {
+            Ok(n) => n,
+            Err(e) => self.svc_error(&e),
+        }
+    }
+
+    fn svc_error(&mut self, e: &io::Error) -> u32 {
+        self.host.last_errno = errno(e);
+        ERROR
+    }
+}
+
+fn open_options(flags: u32) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    match flags & O_ACCMODE {
+        O_WRONLY => options.write(true),
+        O_RDWR => options.read(true).write(true),
+        _ => options.read(true),
+    };
+    if flags & O_APPEND != 0 {
+        options.append(true);
+    }
+    if flags & O_EXCL != 0 && flags & O_CREAT != 0 {
+        options.create_new(true);
+    } else if flags & O_CREAT != 0 {
+        options.create(true);
+    }
+    if flags & O_TRUNC != 0 {
+        options.truncate(true);
+    }
+    options
+}
+
+#[cfg(test)]
+mod syscalls_tests {
+    use super::*;
+    use crate::cpu::test_cpu;
+    use crate::model::{ImageFormat, LoadOptions, Memory};
+
+    #[test]
+    fn sbrk_without_symbols() {
+        let mut regs = Registers::new();
+        let options = LoadOptions {
+            format: Some(ImageFormat::Bin),
+            ..LoadOptions::default()
+        };
+        let path = "programs/benchmarks/fib.out";
+        let mem = Memory::load(path, &options, &mut regs).unwrap();
+        let mut cpu = OoOSpeculative::new(ProcessorState { regs, mem }, |_| {});
+        assert_eq!(cpu.svc_sbrk(16), ERROR);
+        assert_eq!(cpu.host.last_errno, ENOMEM);
+    }
+
+    #[test]
+    fn lseek_bad_whence() {
+        let mut cpu = test_cpu(&[]);
+        // lseek(stdin, 0, 3)
+        cpu.state.regs.gp[..3].copy_from_slice(&[0, 0, 3]);
+        cpu.supervisor_call(SVC_LSEEK);
+        assert_eq!(cpu.state.regs.gp[0], ERROR);
+        assert_eq!(cpu.host.last_errno, EINVAL);
+    }
+}
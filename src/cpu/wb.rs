@@ -14,6 +14,7 @@ impl<'a> OoOSpeculative<'a> {
 
         let mut free_slots = CDB_WIDTH;
         let mut new_to_broadcast = Vec::new();
+        let mut writebacks = Vec::new();
         for (delay, record) in self.to_broadcast.iter_mut() {
             if *delay > 1 {
                 *delay -= 1;
@@ -27,15 +28,22 @@ impl<'a> OoOSpeculative<'a> {
                 record.valid = true;
                 self.cdb.push_back(record.clone());
                 self.rob.set_status(record.rob_number, ROBStatus::Write);
+                writebacks.push((record.rob_number, CDB_WIDTH - free_slots));
                 free_slots -= 1;
             }
         }
         self.to_broadcast = new_to_broadcast;
+        self.count_cdb_broadcasts(writebacks.len());
+        for (rob, slot) in writebacks {
+            let seq = self.rob.get(rob).seq;
+            self.trace(PipelineEvent::Writeback { seq, slot });
+        }
 
         // Broadcast the first {CDB_WIDTH} cdb records to everything that needs it
         for _ in 0..CDB_WIDTH {
             if let Some(record) = self.cdb.pop_front() {
                 let rob_entry = self.rob.get(record.rob_number).clone();
+                self.broadcasts.push((record, rob_entry.dest));
                 if record.halt {
                     self.rob.set_halt(record.rob_number);
                 }
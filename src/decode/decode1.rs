@@ -1,7 +1,7 @@
 use crate::binary::{bit_as_bool, briz};
 use crate::cpu::PREDICT;
 use crate::cpu::PredictionAlgorithms::{AlwaysTaken, AlwaysUntaken, Stall};
-use crate::decode::IT::{B, BL, BLX, BX, SVC};
+use crate::decode::IT::{B, BKPT, BL, BLX, BX, SVC};
 
 #[allow(dead_code)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -306,6 +306,9 @@ impl I {
     }
 }
 
+/// Encodings that aren't ARMv6-M instructions, or that this core doesn't run,
+/// decode as UNDEFINED, which issue refuses. Anything in memory can be decoded,
+/// so code can be disassembled
 pub fn decode(i: u32) -> I {
     match briz(i, 16, 31) {
         // Instruction is 16 bit
@@ -437,7 +440,7 @@ pub fn decode(i: u32) -> I {
                                     rl: 0,
                                     setsflags: true,
                                 },
-                                _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                                _ => I::undefined(),
                             };
                         }
                         // MOVImm
@@ -507,7 +510,7 @@ pub fn decode(i: u32) -> I {
                                 rl: 0,
                             }
                         }
-                        _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                        _ => I::undefined(),
                     }
                 }
                 // Data Processing
@@ -675,7 +678,7 @@ pub fn decode(i: u32) -> I {
                                 rl: 0,
                             }
                         }
-                        _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                        _ => I::undefined(),
                     },
                     // Load from literal pool
                     0b0010 | 0b0011 => {
@@ -758,7 +761,7 @@ pub fn decode(i: u32) -> I {
                             setsflags: false,
                         }
                     }
-                    _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                    _ => I::undefined(),
                 },
                 // Load/store single data item pt2
                 // ADR: PC Relative
@@ -916,7 +919,6 @@ pub fn decode(i: u32) -> I {
                                     setsflags: false,
                                 }
                             }
-                            0b0110011 => unimplemented!("CPS"),
                             0b1010000..=0b1010111 => {
                                 let rm = briz(i, 3, 5) as u8;
                                 let rd = briz(i, 0, 2) as u8;
@@ -924,9 +926,8 @@ pub fn decode(i: u32) -> I {
                                 let it = match briz(i, 6, 7) {
                                     0b00 => IT::REV,
                                     0b01 => IT::REV16,
-                                    0b10 => panic!("Invalid Instruction: {i}"),
                                     0b11 => IT::REVSH,
-                                    _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                                    _ => IT::UNDEFINED,
                                 };
 
                                 I {
@@ -989,13 +990,29 @@ pub fn decode(i: u32) -> I {
                                         rn: 0,
                                         setsflags: false,
                                     },
-                                    _ => unimplemented!("Hints other than NOP"),
+                                    (1, 0) => I {
+                                        it: IT::YIELD,
+                                        ..I::undefined()
+                                    },
+                                    (2, 0) => I {
+                                        it: IT::WFE,
+                                        ..I::undefined()
+                                    },
+                                    (3, 0) => I {
+                                        it: IT::WFI,
+                                        ..I::undefined()
+                                    },
+                                    (4, 0) => I {
+                                        it: IT::SEV,
+                                        ..I::undefined()
+                                    },
+                                    _ => I::undefined(),
                                 }
                             }
-                            _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                            _ => I::undefined(),
                         }
                     }
-                    _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                    _ => I::undefined(),
                 },
                 // Store Multiple
                 // Load Multiple
@@ -1057,29 +1074,29 @@ pub fn decode(i: u32) -> I {
                                         imms: 0,
                                         setsflags: false,
                                     },
-                                    _ => unreachable!("BRI issue: Invalid instr: {i}"),
+                                    _ => I::undefined(),
                                 }
                             }
                         }
                     }
                     // B (T2)
                     0b1000 | 0b1001 => decode_b2(i),
-                    _ => panic!("Invalid instr: {i}"),
+                    _ => I::undefined(),
                 },
-                _ => panic!("Invalid instr: {i}"),
+                _ => I::undefined(),
             }
         }
         // Instruction is 32 bit
         _ => {
             if briz(i, 29, 31) != 0b111 {
-                panic!("Invalid instr: {i}")
+                return I::undefined();
             }
 
             let op1 = briz(i, 27, 28);
             let op = briz(i, 15, 15);
 
             match (op1, op) {
-                (0b01, _) | (0b11, _) | (0b10, 0) => panic!("Undefined instr: {i}"),
+                (0b01, _) | (0b11, _) | (0b10, 0) => I::undefined(),
                 (0b10, 1) => {
                     let op1 = briz(i, 20, 26);
                     let op2 = briz(i, 12, 14);
@@ -1089,16 +1106,18 @@ pub fn decode(i: u32) -> I {
                         (0b000, 0b0111000)
                         | (0b000, 0b0111001)
                         | (0b010, 0b0111000)
-                        | (0b010, 0b0111001) => {
-                            unimplemented!()
-                        }
+                        | (0b010, 0b0111001) => I {
+                            it: IT::MSR,
+                            ..I::undefined()
+                        },
                         // MRS
                         (0b000, 0b0111110)
                         | (0b000, 0b0111111)
                         | (0b010, 0b0111110)
-                        | (0b010, 0b0111111) => {
-                            unimplemented!()
-                        }
+                        | (0b010, 0b0111111) => I {
+                            it: IT::MRS,
+                            ..I::undefined()
+                        },
                         // UDF
                         (0b010, 0b1111111) => I::undefined(),
                         // Misc control instructions
@@ -1108,7 +1127,7 @@ pub fn decode(i: u32) -> I {
                                 0b0100 => IT::DSB,
                                 0b0101 => IT::DMB,
                                 0b0110 => IT::ISB,
-                                _ => panic!("Invalid instr: {i}"),
+                                _ => IT::UNDEFINED,
                             };
 
                             I {
@@ -1125,10 +1144,10 @@ pub fn decode(i: u32) -> I {
                         }
                         // BL
                         (0b101, _) | (0b111, _) => decode_bl(i),
-                        _ => panic!("Invalid instr: {i}"),
+                        _ => I::undefined(),
                     }
                 }
-                _ => panic!("Invalid instr: {i}"),
+                _ => I::undefined(),
             }
         }
     }
@@ -1221,7 +1240,7 @@ pub fn decode_b2(i: u32) -> I {
 impl IT {
     pub fn is_serializing(&self) -> bool {
         match self {
-            SVC | BX | BLX => true,
+            SVC | BKPT | BX | BLX => true,
             B | BL => PREDICT == Stall,
             _ => false,
         }
@@ -12,6 +12,17 @@ impl IT {
     }
 }
 
+impl I {
+    /// The registers in rl, which has a bit per register
+    fn register_list(&self) -> String {
+        let registers = (0..16)
+            .filter(|n| self.rl & (1 << n) != 0)
+            .map(|n| Registers::reg_id_to_str(n as u8))
+            .collect::<Vec<String>>();
+        format!("{{{}}}", registers.join(", "))
+    }
+}
+
 impl Display for I {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut str = self.it.to_string_no_type() + if self.setsflags { "s " } else { " " };
@@ -69,11 +80,16 @@ impl Display for I {
             BX | BLX => format!("{}", rm),
 
             // immu
-            SVC => format!("#{}", self.immu),
+            SVC | BKPT => format!("#{}", self.immu),
+
+            // {register list}
+            PUSH | POP => self.register_list(),
 
-            SetPC => "".to_string(),
+            // RN! {register list}
+            LDMIA | STMIA => format!("{}! {}", rn, self.register_list()),
 
-            _ => unimplemented!("tostring for {:?}", self.it),
+            SetPC | NOP | YIELD | WFE | WFI | SEV | DSB | DMB | ISB | MRS | MSR | UNDEFINED
+            | UNPREDICTABLE => "".to_string(),
         };
         str += args.as_str();
         write!(f, "{}", str)
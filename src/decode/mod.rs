@@ -5,7 +5,7 @@ mod i_to_string;
 pub use decode1::*;
 pub use decode2::*;
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
 pub enum IssueType {
     ALUSHIFT,
     MUL,
@@ -14,6 +14,7 @@ pub enum IssueType {
     Control,
 }
 
+use crate::checkpoint::{persist_fields, CheckpointError, Input, Persist};
 use IT::*;
 
 pub fn get_issue_type(it: IT) -> IssueType {
@@ -26,12 +27,73 @@ pub fn get_issue_type(it: IT) -> IssueType {
 
         MUL => IssueType::MUL,
 
-        B | SetPC | BL | BLX | BX | SVC => IssueType::Control,
+        B | SetPC | BL | BLX | BX | SVC | BKPT => IssueType::Control,
 
         STRImm | STRReg | STRBImm | STRBReg | STRHImm | STRHReg | LDRImm | LDRReg | LDRHImm | LDRHReg | LDRBImm | LDRBReg | LDRSB | LDRSH => IssueType::LoadStore,
         
         // fails when hits these instructions, cannot issue
-        DSB | ISB | DMB | BKPT | MRS | MSR | UNDEFINED | UNPREDICTABLE | SEV | WFE | WFI | YIELD  => panic!("Got instruction that shouldn't be in an application level binary, system level instructions not supported"),
+        DSB | ISB | DMB | MRS | MSR | UNDEFINED | UNPREDICTABLE | SEV | WFE | WFI | YIELD  => panic!("Got instruction that shouldn't be in an application level binary, system level instructions not supported"),
         LDMIA | STMIA | POP | PUSH => panic!("Got ciscy instruction {:?} in issue, should have been broken down", it),
     }
 }
+
+impl IT {
+    /// Every instruction type, in declaration order
+    pub const ALL: [IT; 71] = [
+        UNPREDICTABLE, UNDEFINED, ADC, ADDImm, ADDReg, ADDSpImm, AND, ASRImm, ASRReg, B,
+        BIC, BKPT, BL, BLX, BX, CMN, CMPImm, CMPReg, DMB, DSB, EOR, ISB, LDMIA, LDRImm,
+        LDRReg, LDRBImm, LDRBReg, LDRHImm, LDRHReg, LDRSB, LDRSH, LSLImm, LSLReg,
+        LSRImm, LSRReg, MOVImm, MOVReg, MRS, MSR, MUL, MVN, NOP, ORR, POP, PUSH, REV,
+        REV16, REVSH, ROR, RSB, SBC, SEV, STMIA, STRImm, STRReg, STRBImm, STRBReg,
+        STRHImm, STRHReg, SUBImm, SUBReg, SVC, SXTB, SXTH, TST, UXTB, UXTH, WFE, WFI,
+        YIELD, SetPC,
+    ];
+}
+
+/// Checkpoints store the position in `IT::ALL`
+impl Persist for IT {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u8).save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        IT::ALL
+            .get(u8::load(input)? as usize)
+            .copied()
+            .ok_or(CheckpointError::Invalid("instruction type"))
+    }
+}
+
+impl Persist for IssueType {
+    fn save(&self, out: &mut Vec<u8>) {
+        let n: u8 = match self {
+            IssueType::ALUSHIFT => 0,
+            IssueType::MUL => 1,
+            IssueType::LoadStore => 2,
+            IssueType::Control => 3,
+        };
+        n.save(out);
+    }
+
+    fn load(input: &mut Input) -> Result<Self, CheckpointError> {
+        Ok(match u8::load(input)? {
+            0 => IssueType::ALUSHIFT,
+            1 => IssueType::MUL,
+            2 => IssueType::LoadStore,
+            3 => IssueType::Control,
+            _ => return Err(CheckpointError::Invalid("issue type")),
+        })
+    }
+}
+
+persist_fields!(I {
+    it,
+    rd,
+    rn,
+    rm,
+    rt,
+    rl,
+    immu,
+    imms,
+    setsflags
+});
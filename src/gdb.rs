@@ -0,0 +1,420 @@
+//! A GDB remote serial protocol stub, so arm-none-eabi-gdb or LLDB can debug a
+//! guest running on the simulator. The target stops at the commit boundary, see
+//! cpu/debug.rs, so registers and memory are always architectural. Breakpoints
+//! and watchpoints are checked at commit rather than patched into memory.
+use crate::cpu::{OoOSpeculative, StopReason, WatchKind, Watchpoint};
+use crate::model::ASPR;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// Registers in the order of the target description, r0 to pc then xpsr
+const N_REGS: u8 = 17;
+const XPSR: u8 = 16;
+/// Ticks between checks for an interrupt from the debugger while running
+const INTERRUPT_POLL: usize = 4096;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>arm</architecture>
+  <feature name="org.gnu.gdb.arm.m-profile">
+    <reg name="r0" bitsize="32" regnum="0"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="lr" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="xpsr" bitsize="32" regnum="16"/>
+  </feature>
+</target>
+"#;
+
+/// How the debugger left
+pub enum GdbExit {
+    /// Let the program run on without the debugger
+    Detach,
+    Kill,
+}
+
+/// Why the target last stopped, for the ? packet and resumes
+enum Stop {
+    Cpu(StopReason),
+    Interrupted,
+}
+
+struct Connection {
+    stream: TcpStream,
+    /// Bytes read but not yet parsed
+    pending: Vec<u8>,
+    no_ack: bool,
+}
+
+impl Connection {
+    /// The next packet's payload, none if the debugger hung up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Acks and interrupts outside a packet are dropped, there's nothing
+            // to resend or interrupt while stopped
+            let start = self.pending.iter().position(|b| *b == b'$');
+            if let Some(start) = start {
+                let end = self.pending[start..].iter().position(|b| *b == b'#');
+                if let Some(end) = end.map(|end| start + end) {
+                    if self.pending.len() >= end + 3 {
+                        let payload = self.pending[start + 1..end].to_vec();
+                        let checksum = std::str::from_utf8(&self.pending[end + 1..end + 3])
+                            .ok()
+                            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                        self.pending.drain(..end + 3);
+                        let expected = payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+                        if !self.no_ack {
+                            let ack = if checksum == Some(expected) {
+                                b"+"
+                            } else {
+                                b"-"
+                            };
+                            self.stream.write_all(ack)?;
+                            if checksum != Some(expected) {
+                                continue;
+                            }
+                        }
+                        return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+                    }
+                }
+            } else {
+                self.pending.clear();
+            }
+
+            let mut buf = [0; 4096];
+            let n = self.stream.read(&mut buf)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    fn send(&mut self, payload: &str) -> io::Result<()> {
+        // Over the bytes as sent, escapes and all
+        let escaped = escape(payload);
+        let checksum = escaped.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", escaped, checksum);
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+
+    /// Whether the debugger has sent an interrupt, without blocking
+    fn interrupted(&mut self) -> bool {
+        let mut buf = [0; 256];
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let read = self.stream.read(&mut buf);
+        let _ = self.stream.set_nonblocking(false);
+        match read {
+            Ok(n) => {
+                self.pending.extend_from_slice(&buf[..n]);
+                if let Some(at) = self.pending.iter().position(|b| *b == 0x03) {
+                    self.pending.remove(at);
+                    return true;
+                }
+                false
+            }
+            // WouldBlock when nothing was sent
+            Err(_) => false,
+        }
+    }
+}
+
+/// The characters the protocol reserves, escaped as } then the byte xor 0x20
+fn escape(payload: &str) -> String {
+    let mut escaped = String::with_capacity(payload.len());
+    for c in payload.chars() {
+        if matches!(c, '$' | '#' | '}' | '*') {
+            escaped.push('}');
+            escaped.push((c as u8 ^ 0x20) as char);
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn hex_u32(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// A register value in target byte order, as g and p send them
+fn reg_hex(value: u32) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn parse_reg(hex: &str) -> Option<u32> {
+    Some(u32::from_le_bytes(parse_bytes(hex)?.try_into().ok()?))
+}
+
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|n| u8::from_str_radix(hex.get(n..n + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex_encode(text: &str) -> String {
+    text.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_register(cpu: &OoOSpeculative, index: u8) -> u32 {
+    let regs = &cpu.arch_state().regs;
+    if index == XPSR {
+        let apsr = regs.apsr;
+        // Thumb is the only state there is
+        (apsr.n as u32) << 31
+            | (apsr.z as u32) << 30
+            | (apsr.c as u32) << 29
+            | (apsr.v as u32) << 28
+            | 1 << 24
+    } else {
+        regs.get(index)
+    }
+}
+
+fn write_register(cpu: &mut OoOSpeculative, index: u8, value: u32) {
+    if index == XPSR {
+        cpu.set_flags(ASPR {
+            n: value & 1 << 31 != 0,
+            z: value & 1 << 30 != 0,
+            c: value & 1 << 29 != 0,
+            v: value & 1 << 28 != 0,
+        });
+    } else {
+        cpu.set_register(index, value);
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Cpu(StopReason::Step) => "T05thread:1;".to_string(),
+        Stop::Cpu(StopReason::Breakpoint(_)) => "T05thread:1;swbreak:;".to_string(),
+        Stop::Cpu(StopReason::Watchpoint(watchpoint, access)) => {
+            let kind = match watchpoint.kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T05thread:1;{}:{:x};", kind, access.addr)
+        }
+        Stop::Cpu(StopReason::Halted(code)) => format!("W{:02x}", *code as u8),
+        Stop::Interrupted => "T02thread:1;".to_string(),
+    }
+}
+
+/// addr,length or addr,kind, as the m, M and Z packets have them
+fn addr_len(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((hex_u32(addr)?, hex_u32(len)?))
+}
+
+/// Insert or remove a Z packet's breakpoint or watchpoint
+fn set_point(cpu: &mut OoOSpeculative, args: &str, insert: bool) -> Option<&'static str> {
+    let (kind, rest) = args.split_once(',')?;
+    // Conditions and commands after ; are left to the debugger
+    let rest = rest.split(';').next()?;
+    let (addr, len) = addr_len(rest)?;
+    let kind = match kind {
+        // Software and hardware breakpoints are the same thing here
+        "0" | "1" => {
+            if insert {
+                cpu.debug.breakpoints.insert(addr);
+            } else {
+                cpu.debug.breakpoints.remove(&addr);
+            }
+            return Some("OK");
+        }
+        "2" => WatchKind::Write,
+        "3" => WatchKind::Read,
+        "4" => WatchKind::Access,
+        _ => return Some(""),
+    };
+    // A range past the top of memory is an error
+    addr.checked_add(len)?;
+    let watchpoint = Watchpoint { addr, len, kind };
+    if insert {
+        cpu.debug.watchpoints.push(watchpoint);
+    } else {
+        cpu.debug.watchpoints.retain(|w| *w != watchpoint);
+    }
+    Some("OK")
+}
+
+/// Serve one debugger on localhost:port, returning once it detaches or kills
+/// the target. The program starts stopped at its entry point
+pub fn serve(cpu: &mut OoOSpeculative, port: u16) -> io::Result<GdbExit> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for a debugger on localhost:{}", port);
+    let (stream, peer) = listener.accept()?;
+    eprintln!("Debugger connected from {}", peer);
+    stream.set_nodelay(true)?;
+    let mut conn = Connection {
+        stream,
+        pending: Vec::new(),
+        no_ack: false,
+    };
+    let mut last_stop = Stop::Cpu(StopReason::Step);
+
+    while let Some(packet) = conn.read_packet()? {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => stop_reply(&last_stop),
+            "g" => (0..N_REGS)
+                .map(|index| reg_hex(read_register(cpu, index)))
+                .collect(),
+            "G" => {
+                let values: Option<Vec<u32>> = (0..N_REGS as usize)
+                    .map(|n| parse_reg(args.get(n * 8..n * 8 + 8)?))
+                    .collect();
+                match values {
+                    Some(values) => {
+                        for (index, value) in values.into_iter().enumerate() {
+                            write_register(cpu, index as u8, value);
+                        }
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "p" => match hex_u32(args) {
+                Some(index) if index < N_REGS as u32 => reg_hex(read_register(cpu, index as u8)),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args
+                    .split_once('=')
+                    .and_then(|(index, value)| Some((hex_u32(index)?, parse_reg(value)?)));
+                match parsed {
+                    Some((index, value)) if index < N_REGS as u32 => {
+                        write_register(cpu, index as u8, value);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => {
+                let bytes = addr_len(args)
+                    .and_then(|(addr, len)| cpu.arch_state().mem.read_bytes(addr, len).ok());
+                match bytes {
+                    Some(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+                    None => "E01".to_string(),
+                }
+            }
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = addr_len(range)?;
+                    let bytes = parse_bytes(data)?;
+                    (bytes.len() == len as usize).then_some((addr, bytes))
+                });
+                match parsed.map(|(addr, bytes)| cpu.write_memory(addr, &bytes)) {
+                    Some(Ok(())) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            }
+            "c" | "s" => {
+                if let Some(addr) = hex_u32(args) {
+                    cpu.set_register(15, addr);
+                }
+                last_stop = resume(cpu, &mut conn, command == "s");
+                stop_reply(&last_stop)
+            }
+            "v" if args == "Cont?" => "vCont;c;C;s;S".to_string(),
+            "v" if args.starts_with("Cont;") => {
+                // One thread, so the first action is the one that applies
+                let action = args["Cont;".len()..].split([';', ':']).next().unwrap_or("");
+                let step = action.starts_with('s') || action.starts_with('S');
+                last_stop = resume(cpu, &mut conn, step);
+                stop_reply(&last_stop)
+            }
+            "Z" | "z" => set_point(cpu, args, command == "Z")
+                .unwrap_or("E01")
+                .to_string(),
+            "q" => query(args),
+            "Q" if args == "StartNoAckMode" => {
+                conn.send("OK")?;
+                conn.no_ack = true;
+                continue;
+            }
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                conn.send("OK")?;
+                return Ok(GdbExit::Detach);
+            }
+            "k" => return Ok(GdbExit::Kill),
+            _ => String::new(),
+        };
+        conn.send(&reply)?;
+    }
+    // Hanging up without detaching is treated like detaching
+    Ok(GdbExit::Detach)
+}
+
+fn resume(cpu: &mut OoOSpeculative, conn: &mut Connection, step: bool) -> Stop {
+    if step {
+        return Stop::Cpu(cpu.step_instruction());
+    }
+    let mut ticks = 0;
+    let stop = cpu.run_until_stop(|_| {
+        ticks += 1;
+        ticks % INTERRUPT_POLL != 0 || !conn.interrupted()
+    });
+    stop.map_or(Stop::Interrupted, Stop::Cpu)
+}
+
+fn query(args: &str) -> String {
+    let (name, rest) = args.split_once(':').unwrap_or((args, ""));
+    match name {
+        "Supported" => {
+            "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+".to_string()
+        }
+        "Xfer" => {
+            // features:read:target.xml:offset,length
+            let mut parts = rest.splitn(4, ':');
+            let object = (parts.next(), parts.next(), parts.next());
+            let range = parts.next().and_then(addr_len);
+            match (object, range) {
+                ((Some("features"), Some("read"), Some("target.xml")), Some((offset, len))) => {
+                    let offset = (offset as usize).min(TARGET_XML.len());
+                    let end = (offset + len as usize).min(TARGET_XML.len());
+                    let prefix = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{}{}", prefix, &TARGET_XML[offset..end])
+                }
+                _ => "E00".to_string(),
+            }
+        }
+        "Attached" => "1".to_string(),
+        "C" => "QC1".to_string(),
+        "fThreadInfo" => "m1".to_string(),
+        "sThreadInfo" => "l".to_string(),
+        // LLDB asks for these before anything else
+        "HostInfo" => format!(
+            "triple:{};endian:little;ptrsize:4;",
+            hex_encode("thumbv6m-none-eabi")
+        ),
+        "ProcessInfo" => "pid:1;endian:little;ptrsize:4;".to_string(),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,317 @@
+//! Where the TUI's panes go. The screen is split into columns left to right, and
+//! each column into panes top to bottom, as a layout file says or the default.
+//! The help overlay listing the keys is here too.
+use ratatui::layout::{Constraint, Flex, Layout, Position, Rect};
+use ratatui::widgets::{Block, Clear, Padding, Paragraph};
+use ratatui::Frame;
+use std::{fs, io};
+
+/// The default layout, in the layout file format
+const DEFAULT: &str = "\
+column 30 Stats
+stats 3
+registers 22
+stalls *
+column 40
+rob *
+column * Pipeline
+fetch 4
+iq 5
+rs 10
+source 8
+charts 12
+queues 9
+mem *
+column 56
+code *
+";
+
+const HELP: &str = "\
+Enter        step a cycle
+space        play or pause
++ / -        play faster or slower
+b            step back a cycle
+c            run to the end
+l            reset
+:            command line, Enter on an empty one lists the commands
+1-4          show the ALU/shift, MUL, load/store or control RS
+f            focus the next scrollable pane
+Up / Down    scroll the focused pane
+p            follow the commit pc or register again in the focused pane
+u            memory as bytes, halfwords or words
+e            swap the memory byte order
+a            show or hide the memory's ASCII
+?            show or hide this help
+q / Esc      quit
+
+Click a pane to focus it, and scroll the one under the mouse with the wheel.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pane {
+    /// The cycle and instructions committed
+    Stats,
+    Registers,
+    Stalls,
+    Rob,
+    Fetch,
+    Iq,
+    Rs,
+    Source,
+    Charts,
+    /// The load queue, pending broadcasts and CDB
+    Queues,
+    Mem,
+    Code,
+}
+
+impl Pane {
+    const ALL: [Pane; 12] = [
+        Pane::Stats,
+        Pane::Registers,
+        Pane::Stalls,
+        Pane::Rob,
+        Pane::Fetch,
+        Pane::Iq,
+        Pane::Rs,
+        Pane::Source,
+        Pane::Charts,
+        Pane::Queues,
+        Pane::Mem,
+        Pane::Code,
+    ];
+
+    /// As the layout file has it
+    fn name(self) -> &'static str {
+        match self {
+            Pane::Stats => "stats",
+            Pane::Registers => "registers",
+            Pane::Stalls => "stalls",
+            Pane::Rob => "rob",
+            Pane::Fetch => "fetch",
+            Pane::Iq => "iq",
+            Pane::Rs => "rs",
+            Pane::Source => "source",
+            Pane::Charts => "charts",
+            Pane::Queues => "queues",
+            Pane::Mem => "mem",
+            Pane::Code => "code",
+        }
+    }
+
+    /// The panes that scroll, and so can be focused
+    pub fn scrolls(self) -> bool {
+        matches!(self, Pane::Rob | Pane::Mem | Pane::Code)
+    }
+}
+
+/// Cells, or a share of what's left over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Size {
+    Length(u16),
+    Fill(u16),
+}
+
+impl Size {
+    fn parse(text: &str) -> Option<Self> {
+        match text.strip_prefix('*') {
+            Some("") => Some(Size::Fill(1)),
+            Some(share) => share.parse().ok().map(Size::Fill),
+            None => text.parse().ok().map(Size::Length),
+        }
+    }
+
+    fn constraint(self) -> Constraint {
+        match self {
+            Size::Length(n) => Constraint::Length(n),
+            Size::Fill(n) => Constraint::Fill(n),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Column {
+    width: Size,
+    pub title: String,
+    panes: Vec<(Pane, Size)>,
+}
+
+/// A column, where it goes, and each of its panes and where they go
+pub type ColumnAreas<'a> = (&'a Column, Rect, Vec<(Pane, Rect)>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TuiLayout {
+    columns: Vec<Column>,
+}
+
+impl Default for TuiLayout {
+    fn default() -> Self {
+        Self::parse(DEFAULT).unwrap()
+    }
+}
+
+impl TuiLayout {
+    /// A `column <width> [title]` line starts each column, followed by a
+    /// `<pane> <height>` line for each of its panes. Sizes are cells, or * to
+    /// share what's left over, *2 for twice the share. The panes are stats,
+    /// registers, stalls, rob, fetch, iq, rs, source, charts, queues, mem and
+    /// code, each at most once. # starts a comment
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut columns: Vec<Column> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: &str| format!("line {}: {}", n + 1, msg);
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap();
+            let size = words.next().ok_or_else(|| error("expected a size"))?;
+            let size = Size::parse(size).ok_or_else(|| error("bad size"))?;
+            if name == "column" {
+                columns.push(Column {
+                    width: size,
+                    title: words.collect::<Vec<_>>().join(" "),
+                    panes: Vec::new(),
+                });
+                continue;
+            }
+            let pane = Pane::ALL
+                .into_iter()
+                .find(|pane| pane.name() == name)
+                .ok_or_else(|| error(&format!("unknown pane {}", name)))?;
+            if columns
+                .iter()
+                .any(|column| column.panes.iter().any(|p| p.0 == pane))
+            {
+                return Err(error(&format!("{} is already in the layout", name)));
+            }
+            if words.next().is_some() {
+                return Err(error("expected a pane and a height"));
+            }
+            columns
+                .last_mut()
+                .ok_or_else(|| error("panes go in a column"))?
+                .panes
+                .push((pane, size));
+        }
+        if columns.is_empty() {
+            return Err("no columns".to_string());
+        }
+        Ok(Self { columns })
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The panes in order, down each column and then across
+    pub fn panes(&self) -> impl Iterator<Item = Pane> + '_ {
+        self.columns
+            .iter()
+            .flat_map(|column| column.panes.iter().map(|(pane, _)| *pane))
+    }
+
+    /// The scrolling pane after focus, going round
+    pub fn next_focus(&self, focus: Pane) -> Pane {
+        let panes: Vec<Pane> = self.panes().filter(|pane| pane.scrolls()).collect();
+        match panes.iter().position(|pane| *pane == focus) {
+            Some(n) => panes[(n + 1) % panes.len()],
+            None => panes.first().copied().unwrap_or(focus),
+        }
+    }
+
+    /// The pane shown at a position
+    pub fn pane_at(&self, area: Rect, at: Position, shown: impl Fn(Pane) -> bool) -> Option<Pane> {
+        self.areas(area, shown)
+            .into_iter()
+            .flat_map(|(_, _, panes)| panes)
+            .find(|(_, pane_area)| pane_area.contains(at))
+            .map(|(pane, _)| pane)
+    }
+
+    /// Each column and where it goes, then each of its panes shown and where they
+    /// go inside the column's border. Panes not shown take no room
+    pub fn areas(&self, area: Rect, shown: impl Fn(Pane) -> bool) -> Vec<ColumnAreas<'_>> {
+        let widths = self.columns.iter().map(|column| column.width.constraint());
+        let column_areas = Layout::horizontal(widths).split(area);
+        self.columns
+            .iter()
+            .zip(column_areas.iter())
+            .map(|(column, column_area)| {
+                let panes: Vec<(Pane, Size)> = column
+                    .panes
+                    .iter()
+                    .copied()
+                    .filter(|(pane, _)| shown(*pane))
+                    .collect();
+                let inner = Block::bordered().inner(*column_area);
+                let heights = panes.iter().map(|(_, size)| size.constraint());
+                let pane_areas = Layout::vertical(heights).split(inner);
+                let panes = panes
+                    .iter()
+                    .zip(pane_areas.iter())
+                    .map(|((pane, _), pane_area)| (*pane, *pane_area))
+                    .collect();
+                (column, *column_area, panes)
+            })
+            .collect()
+    }
+}
+
+/// The keys and mouse actions, over the middle of the screen
+pub fn render_help(frame: &mut Frame, area: Rect) {
+    let width = HELP.lines().map(str::len).max().unwrap_or(0) as u16 + 4;
+    let height = HELP.lines().count() as u16 + 2;
+    let [area] = Layout::horizontal([Constraint::Length(width)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(HELP).block(
+            Block::bordered()
+                .title("Keys, ? to close")
+                .padding(Padding::horizontal(1)),
+        ),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod layout_tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let layout =
+            TuiLayout::parse("column 20 Left # a comment\nrob *\ncolumn *2\nmem 10\ncode *\n")
+                .unwrap();
+        assert_eq!(layout.columns.len(), 2);
+        assert_eq!(layout.columns[0].title, "Left");
+        assert_eq!(layout.columns[1].width, Size::Fill(2));
+        assert_eq!(
+            layout.panes().collect::<Vec<_>>(),
+            [Pane::Rob, Pane::Mem, Pane::Code]
+        );
+        assert!(TuiLayout::parse("rob *\n").is_err());
+        assert!(TuiLayout::parse("column 20\nrob *\nrob 5\n").is_err());
+        assert!(TuiLayout::parse("column 20\nregs 5\n").is_err());
+        assert_eq!(TuiLayout::default().panes().count(), Pane::ALL.len());
+    }
+
+    #[test]
+    fn areas() {
+        let layout =
+            TuiLayout::parse("column 10\nrob 5\nsource 3\nmem *\ncolumn *\ncode *\n").unwrap();
+        let areas = layout.areas(Rect::new(0, 0, 40, 20), |pane| pane != Pane::Source);
+        let (_, column, panes) = &areas[0];
+        assert_eq!(*column, Rect::new(0, 0, 10, 20));
+        // Inside the border, without the source pane
+        assert_eq!(panes[0], (Pane::Rob, Rect::new(1, 1, 8, 5)));
+        assert_eq!(panes[1], (Pane::Mem, Rect::new(1, 6, 8, 13)));
+        assert_eq!(areas[1].1, Rect::new(10, 0, 30, 20));
+    }
+}
@@ -1,30 +1,46 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
 mod binary;
+mod checkpoint;
 mod components;
+mod console;
 mod cpu;
 mod decode;
+mod gdb;
+mod layout;
 mod model;
+mod simpoint;
+mod stats;
+mod trace;
 #[cfg(test)]
 mod test;
 
 extern crate ratatui;
 
+use console::Console;
 use cpu::*;
 use decode::*;
+use layout::{Pane, TuiLayout};
 use model::*;
+use simpoint::SimPoints;
+use trace::{ChromeTrace, Hotspots, PipeView};
 use ratatui::backend::{Backend, CrosstermBackend};
-use ratatui::crossterm::event::{self, Event, KeyCode};
+use ratatui::crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEventKind,
+};
 use ratatui::crossterm::execute;
 use ratatui::crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
+use ratatui::layout::{Position, Rect};
 use ratatui::Terminal;
 use std::fs::File;
+use std::path::PathBuf;
 use std::{fs, io};
-use std::io::{stdout, Write};
+use std::io::{stdout, Read, Write};
 use std::panic::{set_hook, take_hook};
 use std::process::exit;
+use std::time::{Duration, Instant};
 
 fn main() -> io::Result<()> {
     // let sdl_context = sdl2::init().unwrap();
@@ -34,28 +50,173 @@ fn main() -> io::Result<()> {
     //     .position_centered()
     //     .build()
     //     .unwrap();
-    let mut terminal = ratatui::init();
     let mut registers = Registers::new();
     let app_path = std::env::args().nth(1).unwrap();
 
-    let other_args = std::env::args().skip(2).collect::<Vec<String>>();
+    let mut other_args = std::env::args().skip(2);
     let mut FAST = true;
+    let mut uart_in: Option<String> = None;
+    let mut uart_out: Option<String> = None;
+    let mut uart_base = UART_DEFAULT_BASE;
+    let mut host_dir = String::from(".");
+    let mut guest_stdin: Box<dyn Read> = Box::new(io::empty());
+    // argv[0] is the program itself
+    let mut guest_args = vec![app_path.clone()];
+    let mut load_options = LoadOptions::default();
+    let mut layout = TuiLayout::default();
+    let mut pipeview: Option<String> = None;
+    let mut chrome_trace: Option<String> = None;
+    let mut hotspots: Option<String> = None;
+    let mut stats_path: Option<String> = None;
+    let mut cpi_stack = false;
+    let mut profile = false;
+    let mut folded_stacks: Option<String> = None;
+    let mut gdb_port: Option<u16> = None;
+    let mut save_checkpoint: Option<String> = None;
+    let mut checkpoint_at: Option<usize> = None;
+    let mut restore_checkpoint: Option<String> = None;
+    let mut fast_forward: Option<String> = None;
+    let mut warmup = 0;
+    let mut measure: Option<usize> = None;
+    let mut simpoint_profile: Option<String> = None;
+    let mut simpoint_interval = 10_000;
+    let mut simpoint_k = 10;
+    let mut simpoints: Option<String> = None;
 
-    for arg in other_args.into_iter() {
-        if arg == "--tui" {
-            FAST = false;
+    while let Some(arg) = other_args.next() {
+        match arg.as_str() {
+            "--tui" => FAST = false,
+            // "-" means the host stdin / stdout
+            "--uart-in" => uart_in = other_args.next(),
+            "--uart-out" => uart_out = other_args.next(),
+            // Guest file access is sandboxed to this directory
+            "--host-dir" => host_dir = other_args.next().expect("--host-dir needs a directory"),
+            // Guest stdin for the syscall layer, "-" means the host stdin
+            "--stdin" => {
+                guest_stdin = match other_args.next().as_deref() {
+                    Some("-") => Box::new(io::stdin()),
+                    Some(path) => Box::new(File::open(path)?),
+                    None => panic!("--stdin needs a file or -"),
+                }
+            }
+            "--stdin-text" => {
+                let text = other_args.next().expect("--stdin-text needs a string");
+                guest_stdin = Box::new(io::Cursor::new(text.into_bytes()));
+            }
+            // Everything after -- is passed to the guest main
+            "--" => guest_args.extend(other_args.by_ref()),
+            // Per instruction pipeline trace in gem5's O3PipeView format, for Konata
+            "--pipeview" => pipeview = other_args.next(),
+            // Functional unit timeline as Chrome trace JSON, for Perfetto
+            "--chrome-trace" => chrome_trace = other_args.next(),
+            // Break the CPI down by where commit slots went, printed at the end
+            "--cpi-stack" => cpi_stack = true,
+            // Annotated disassembly of every instruction run, costliest first
+            "--hotspots" => hotspots = other_args.next(),
+            // Occupancy, utilisation and run totals, as CSV if the name ends .csv
+            // and JSON otherwise
+            "--stats" => stats_path = other_args.next(),
+            // Cycles, instructions, mispredicts and stalls per function
+            "--profile" => profile = true,
+            // Cycles per call stack for flame graph tools, implies --profile
+            "--folded-stacks" => folded_stacks = other_args.next(),
+            // Wait for a GDB or LLDB connection on this local port before running
+            "--gdb" => {
+                gdb_port = Some(
+                    other_args
+                        .next()
+                        .and_then(|port| port.parse().ok())
+                        .expect("--gdb needs a port number"),
+                )
+            }
+            // Save the whole machine once this many instructions have committed, and stop
+            "--save-checkpoint" => save_checkpoint = other_args.next(),
+            "--checkpoint-at" => checkpoint_at = Some(count_arg(&mut other_args, "--checkpoint-at")),
+            // Carry on from a checkpoint of the same program
+            "--restore-checkpoint" => restore_checkpoint = other_args.next(),
+            // Run functionally to an instruction count, address, symbol or marker SVC
+            // first, then warm up for some instructions in the detailed model. Only
+            // what comes after counts, for at most --measure instructions
+            "--fast-forward" => fast_forward = other_args.next(),
+            "--warmup" => warmup = count_arg(&mut other_args, "--warmup"),
+            "--measure" => measure = Some(count_arg(&mut other_args, "--measure")),
+            // Profile basic blocks per interval functionally and write the simulation
+            // points chosen to a file, then simulate just those in detail with
+            // --simpoints, warming up for --warmup instructions before each
+            "--simpoint-profile" => simpoint_profile = other_args.next(),
+            "--simpoint-interval" => {
+                simpoint_interval = count_arg(&mut other_args, "--simpoint-interval")
+            }
+            "--simpoint-k" => simpoint_k = count_arg(&mut other_args, "--simpoint-k"),
+            "--simpoints" => simpoints = other_args.next(),
+            "--uart-base" => uart_base = hex_arg(&mut other_args, "--uart-base"),
+            // Loader options, the format is otherwise worked out from the file
+            "--format" => {
+                let name = other_args.next().unwrap_or_default();
+                load_options.format = Some(
+                    ImageFormat::from_name(&name)
+                        .expect("--format must be one of elf, bin, ihex or srec"),
+                );
+            }
+            "--load-base" => load_options.base = Some(hex_arg(&mut other_args, "--load-base")),
+            "--entry" => load_options.entry = Some(hex_arg(&mut other_args, "--entry")),
+            "--memory-map" => {
+                let path = other_args.next().expect("--memory-map needs a file");
+                match MemoryMap::from_config(&path) {
+                    Ok(map) => load_options.memory_map = Some(map),
+                    Err(e) => {
+                        eprintln!("Could not read memory map {}: {}", path, e);
+                        exit(1);
+                    }
+                }
+            }
+            "--layout" => {
+                let path = other_args.next().expect("--layout needs a file");
+                match TuiLayout::read(&path) {
+                    Ok(read) => layout = read,
+                    Err(e) => {
+                        eprintln!("Could not read layout {}: {}", path, e);
+                        exit(1);
+                    }
+                }
+            }
+            _ => {}
         }
     }
+    if save_checkpoint.is_some() != checkpoint_at.is_some() {
+        eprintln!("--save-checkpoint and --checkpoint-at go together");
+        exit(1);
+    }
+    if simpoint_interval == 0 {
+        eprintln!("--simpoint-interval must be at least 1 instruction");
+        exit(1);
+    }
 
-    // Load ELF and initialise register values
-    let memory: Memory = Memory::from_elf(&app_path, &mut registers);
+    // Load the program and initialise register values
+    let memory = match Memory::load(&app_path, &load_options, &mut registers) {
+        Ok(memory) => memory,
+        Err(e) => {
+            eprintln!("Could not load {}: {}", app_path, e);
+            exit(1);
+        }
+    };
+
+    // Only take over the terminal when it's needed, so program output can stream to it
+    let mut terminal = if FAST {
+        None
+    } else {
+        init_panic_hook();
+        Some(init_tui()?)
+    };
 
     let mut state = ProcessorState {
         regs: registers,
         mem: memory,
     };
 
-    state.regs.pc = state.mem.entrypoint as u32;
+    if let Err(e) = state.set_main_args(&guest_args) {
+        panic!("{:?}: could not put the guest arguments on the stack", e)
+    }
     
     fs::create_dir_all("traces").expect("Failed to create trace directory");
     let mut log_file = File::create("traces/log.txt")?;
@@ -70,13 +231,174 @@ fn main() -> io::Result<()> {
         }
     );
 
+    let uart_source = match uart_in.as_deref() {
+        None => UartSource::None,
+        Some("-") => UartSource::stdin(),
+        Some(path) => UartSource::script(path)?,
+    };
+    let uart_sink = match uart_out.as_deref() {
+        Some("-") | None if FAST => UartSink::Stdout,
+        Some("-") | None => UartSink::Capture(String::new()),
+        Some(path) => UartSink::File(File::create(path)?),
+    };
+    cpu.uart = Uart::new(uart_base, uart_source, uart_sink);
+    cpu.host = HostIO::new(PathBuf::from(host_dir), guest_args);
+    cpu.host.set_stdin(guest_stdin);
+    if let Some(path) = pipeview {
+        cpu.add_tracer(Box::new(PipeView::create(&path)?));
+    }
+    if let Some(path) = chrome_trace {
+        cpu.add_tracer(Box::new(ChromeTrace::create(&path)?));
+    }
+    if let Some(path) = hotspots {
+        cpu.add_tracer(Box::new(Hotspots::create(
+            &path,
+            state.mem.line_table().clone(),
+        )?));
+    }
+    if stats_path.is_some() {
+        cpu.enable_stats();
+    }
+    if profile || folded_stacks.is_some() {
+        cpu.enable_profiling();
+    }
+    if let Some(path) = restore_checkpoint {
+        if let Err(e) = cpu.load_checkpoint(&path) {
+            eprintln!("Could not restore checkpoint {}: {}", path, e);
+            exit(1);
+        }
+        // Counting from here if the checkpoint wasn't counting
+        if stats_path.is_some() && cpu.stats.is_none() {
+            cpu.enable_stats();
+        }
+        if (profile || folded_stacks.is_some()) && cpu.profiler.is_none() {
+            cpu.enable_profiling();
+        }
+    }
+    if let Some(path) = &simpoint_profile {
+        let vectors = cpu.basic_block_vectors(simpoint_interval);
+        let points = SimPoints {
+            interval_size: simpoint_interval,
+            points: simpoint::choose(&vectors, simpoint_k),
+        };
+        if !FAST {
+            restore_tui()?;
+        }
+        if let Err(e) = fs::write(path, points.to_text()) {
+            eprintln!("Could not write simulation points {}: {}", path, e);
+            exit(1);
+        }
+        println!(
+            "{} intervals of {} instructions, {} simulation points written to {}",
+            vectors.len(),
+            simpoint_interval,
+            points.points.len(),
+            path
+        );
+        exit(0);
+    }
+    if let Some(path) = &simpoints {
+        let points = match SimPoints::read(path) {
+            Ok(points) => points,
+            Err(e) => {
+                if !FAST {
+                    restore_tui()?;
+                }
+                eprintln!("Could not read simulation points {}: {}", path, e);
+                exit(1);
+            }
+        };
+        let measurements = simpoint::simulate(&mut cpu, &points, warmup);
+        if !FAST {
+            restore_tui()?;
+        }
+        println!("{}", simpoint::report(&measurements));
+        exit(0);
+    }
+    let mut fast_forward_message = String::new();
+    if let Some(to) = &fast_forward {
+        let to = match FastForward::parse(to, &cpu.arch_state().mem) {
+            Ok(to) => to,
+            Err(e) => {
+                if !FAST {
+                    restore_tui()?;
+                }
+                eprintln!("--fast-forward: {}", e);
+                exit(1);
+            }
+        };
+        let n = cpu.fast_forward(to);
+        fast_forward_message = format!(
+            "Fast-forwarded {} instructions to {:08X}",
+            n,
+            cpu.arch_state().regs.pc
+        );
+    }
+    if fast_forward.is_some() || warmup > 0 {
+        let warmup_end = cpu.instructions_committed + warmup;
+        while cpu.halt.is_none() && cpu.instructions_committed < warmup_end {
+            cpu.tick();
+        }
+        cpu.start_measuring();
+    }
+    // Stepping back and the charts are only for looking around in the TUI
+    if !FAST {
+        cpu.enable_history();
+        cpu.enable_charts();
+    }
+
+    if let Some(port) = gdb_port {
+        match gdb::serve(&mut cpu, port) {
+            Ok(gdb::GdbExit::Detach) => {}
+            Ok(gdb::GdbExit::Kill) => {
+                if !FAST {
+                    restore_tui()?;
+                }
+                exit(0);
+            }
+            Err(e) => eprintln!("Debugger connection failed: {}", e),
+        }
+    }
+
     let mut complete = false;
+    let mut console = Console::default();
+    if FAST && !fast_forward_message.is_empty() {
+        println!("{}", fast_forward_message);
+    }
+    console.message = fast_forward_message;
 
     loop {
-        cpu.tick();
+        // The debugger may have run the program to the end already
+        if cpu.halt.is_none() {
+            cpu.tick();
+        }
 
-        let quit = |cpu: &OoOSpeculative| {
-            restore_tui().unwrap();
+        if let (Some(path), Some(at)) = (&save_checkpoint, checkpoint_at) {
+            if cpu.instructions_committed >= at {
+                if !FAST {
+                    restore_tui()?;
+                }
+                match cpu.save_checkpoint(path) {
+                    Ok(()) => println!(
+                        "Checkpoint saved at cycle {}, {} instructions",
+                        cpu.epoch, cpu.instructions_committed
+                    ),
+                    Err(e) => {
+                        eprintln!("Could not save checkpoint {}: {}", path, e);
+                        exit(1);
+                    }
+                }
+                exit(0);
+            }
+        }
+
+        let quit = |cpu: &mut OoOSpeculative| {
+            if !FAST {
+                restore_tui().unwrap();
+            }
+            if let Err(e) = cpu.finish_tracing() {
+                eprintln!("Could not write traces: {}", e);
+            }
 
             let ipc = (cpu.instructions_committed as f64) / (cpu.epoch as f64);
             println!(
@@ -85,54 +407,95 @@ fn main() -> io::Result<()> {
                 ((cpu.correct_predicts as f64) / ((cpu.correct_predicts as f64) + (cpu.mispredicts as f64))
                 )
             );
+            if cpi_stack {
+                println!("{}", cpu.cpi.report(cpu.instructions_committed));
+            }
+            if let (Some(path), Some(stats)) = (&stats_path, cpu.final_stats()) {
+                let text = if path.ends_with(".csv") {
+                    stats.to_csv()
+                } else {
+                    stats.to_json()
+                };
+                if let Err(e) = std::fs::write(path, text) {
+                    eprintln!("Could not write stats: {}", e);
+                }
+            }
+            if let Some(profiler) = &cpu.profiler {
+                if profile {
+                    println!("{}", profiler.report());
+                }
+                if let Some(path) = &folded_stacks {
+                    if let Err(e) = profiler.write_folded(path) {
+                        eprintln!("Could not write folded stacks: {}", e);
+                    }
+                }
+            }
             println!("output: \n{}", cpu.output);
+            if !cpu.uart.captured().is_empty() {
+                println!("uart: \n{}", cpu.uart.captured());
+            }
         };
 
         if let Some(exit_code) = cpu.halt {
             println!("Program terminated with code {}", exit_code);
-            quit(&cpu);
+            quit(&mut cpu);
             exit(exit_code);
         }
+        if measure.is_some_and(|n| cpu.instructions_committed >= n) {
+            println!("Measured {} instructions", cpu.instructions_committed);
+            quit(&mut cpu);
+            exit(0);
+        }
 
         if FAST {
             continue;
         }
+        let terminal = terminal.as_mut().unwrap();
 
-        terminal.draw(|f| cpu.render(f))?;
+        terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
 
         if complete {
             continue;
         }
 
-        // Wait for enter
+        // Wait for enter, or play
+        let mut next_frame = Instant::now() + console::FRAME;
         loop {
-            if event::poll(std::time::Duration::from_millis(500))? {
+            let timeout = if console.is_playing() {
+                next_frame.saturating_duration_since(Instant::now())
+            } else {
+                Duration::from_millis(500)
+            };
+            if event::poll(timeout)? {
                 match event::read()? {
+                    Event::Key(key_event) if console.input.is_some() => {
+                        if let Some(line) = console.key(key_event.code) {
+                            console.run(&mut cpu, &line, esc_pressed);
+                            if cpu.halt.is_some() {
+                                break;
+                            }
+                        }
+                        terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
+                    }
                     Event::Key(key_event) => match key_event.code {
+                        KeyCode::Esc if console.help => {
+                            console.help = false;
+                            terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
+                        }
                         KeyCode::Char('q') | KeyCode::Esc => {
-                            quit(&cpu);
+                            quit(&mut cpu);
                             exit(0);
                         }
                         KeyCode::Enter => {
                             break;
                         }
                         KeyCode::Up => {
-                            if cpu.display_focus == 0 {
-                                cpu.rob_focus_down();
-                            } else if cpu.display_focus == 1 {
-                                cpu.mem_bottom_offset += 1;
-                            }
-                            terminal.draw(|f| cpu.render(f))?;
+                            cpu.scroll_pane(cpu.focus, -1);
+                            terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
                         }
                         KeyCode::Down => {
-                            if cpu.display_focus == 0 {
-                                cpu.rob_focus_up();
-                            } else if cpu.display_focus == 1 {
-                                if cpu.mem_bottom_offset > 0 {
-                                    cpu.mem_bottom_offset -= 1;
-                                }
-                            }
-                            terminal.draw(|f| cpu.render(f))?;
+                            cpu.scroll_pane(cpu.focus, 1);
+                            terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
                         }
                         KeyCode::Char(c) => {
                             match c {
@@ -140,32 +503,98 @@ fn main() -> io::Result<()> {
                                 '2' => cpu.rs_current_display = IssueType::MUL,
                                 '3' => cpu.rs_current_display = IssueType::LoadStore,
                                 '4' => cpu.rs_current_display = IssueType::Control,
+                                ':' => console.open(),
+                                'b' => {
+                                    if let Err(e) = cpu.step_back_cycles(1) {
+                                        console.message = e;
+                                    }
+                                }
                                 'r' => {}
                                 'l' => cpu.reset(),
                                 'c' => {
                                     complete = true;
                                     break;
                                 }
-                                'f' => {
-                                    let new_focus = if cpu.display_focus == 0 { 1 } else { 0 };
-                                    cpu.display_focus = new_focus
-                                }
+                                'f' => cpu.focus = layout.next_focus(cpu.focus),
+                                // Back to following the commit pc or register
+                                'p' if cpu.focus == Pane::Mem => cpu.mem_follow_again(),
+                                'p' => cpu.code_follow(),
+                                'u' => cpu.mem_view.next_unit(),
+                                'e' => cpu.mem_view.toggle_endian(),
+                                'a' => cpu.mem_view.toggle_ascii(),
+                                '?' => console.help = !console.help,
+                                ' ' => console.play_pause(&cpu),
+                                '+' | '=' => console.faster(),
+                                '-' => console.slower(),
                                 _ => continue,
                             }
-                            terminal.draw(|f| cpu.render(f))?;
+                            terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
                         }
                         _ => {}
                     },
+                    Event::Mouse(mouse) => {
+                        let size = terminal.size()?;
+                        let [main_area, _] = screen_areas(Rect::new(0, 0, size.width, size.height));
+                        let at = Position::new(mouse.column, mouse.row);
+                        let Some(pane) = layout.pane_at(main_area, at, |pane| cpu.pane_shown(pane))
+                        else {
+                            continue;
+                        };
+                        match mouse.kind {
+                            MouseEventKind::Down(MouseButton::Left) if pane.scrolls() => {
+                                cpu.focus = pane
+                            }
+                            MouseEventKind::ScrollUp => cpu.scroll_pane(pane, -1),
+                            MouseEventKind::ScrollDown => cpu.scroll_pane(pane, 1),
+                            _ => continue,
+                        }
+                        terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
+                    }
                     Event::Resize(_, _) => {
-                        terminal.draw(|f| cpu.render(f))?;
+                        terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
                     }
                     _ => {}
                 }
+            } else if console.is_playing() {
+                console.play_frame(&mut cpu);
+                next_frame = Instant::now() + console::FRAME;
+                if cpu.halt.is_some() {
+                    break;
+                }
+                terminal.draw(|f| draw(f, &cpu, &console, &layout))?;
             }
         }
     }
 }
 
+/// The simulator with the command line under it
+fn draw(frame: &mut ratatui::Frame, cpu: &OoOSpeculative, console: &Console, layout: &TuiLayout) {
+    let [main_area, console_area] = screen_areas(frame.area());
+    cpu.render(frame, main_area, layout);
+    console.render(frame, console_area);
+    if console.help {
+        layout::render_help(frame, main_area);
+    }
+}
+
+/// The panes, and the command line under them
+fn screen_areas(area: Rect) -> [Rect; 2] {
+    use ratatui::layout::{Constraint, Layout};
+    Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area)
+}
+
+/// Whether Esc was pressed, to interrupt a run from the command line
+fn esc_pressed() -> bool {
+    while let Ok(true) = event::poll(std::time::Duration::ZERO) {
+        if let Ok(Event::Key(key_event)) = event::read() {
+            if key_event.code == KeyCode::Esc {
+                return true;
+            }
+        }
+    }
+    false
+}
+
 pub fn init_panic_hook() {
     let original_hook = take_hook();
     set_hook(Box::new(move |panic_info| {
@@ -177,12 +606,26 @@ pub fn init_panic_hook() {
 
 pub fn init_tui() -> io::Result<Terminal<impl Backend>> {
     enable_raw_mode()?;
-    execute!(stdout(), EnterAlternateScreen)?;
+    execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
     Terminal::new(CrosstermBackend::new(stdout()))
 }
 
 pub fn restore_tui() -> io::Result<()> {
     disable_raw_mode()?;
-    execute!(stdout(), LeaveAlternateScreen)?;
+    execute!(stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
     Ok(())
 }
+
+fn count_arg(args: &mut impl Iterator<Item = String>, flag: &str) -> usize {
+    args.next()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_else(|| panic!("{} needs an instruction count", flag))
+}
+
+fn hex_arg(args: &mut impl Iterator<Item = String>, flag: &str) -> u32 {
+    let value = args
+        .next()
+        .unwrap_or_else(|| panic!("{} needs a hex address", flag));
+    u32::from_str_radix(value.trim_start_matches("0x"), 16)
+        .unwrap_or_else(|_| panic!("{} must be a hex address", flag))
+}
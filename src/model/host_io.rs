@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub enum HostFile {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+    /// Contents made up by the simulator, like the semihosting feature file
+    Buffer(Cursor<Vec<u8>>),
+}
+
+/// How a guest wants a file opened, shared by the semihosting and svc layers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    Write,
+    Append,
+    ReadWrite,
+    /// Read and write, creating and truncating
+    ReadWriteTruncate,
+    /// Read and append, creating
+    ReadAppend,
+}
+
+/// Host side files for guest programs. Guest paths are resolved inside a sandbox
+/// directory and can't escape it. Descriptors 0, 1 and 2 are the console
+pub struct HostIO {
+    root: PathBuf,
+    files: HashMap<u32, HostFile>,
+    next_fd: u32,
+    /// Where the guest stdin comes from, empty unless set
+    stdin: Box<dyn Read>,
+    /// Bytes of it read so far, so a checkpoint can carry on after them
+    stdin_read: u64,
+    /// Command line given to the guest, program name first
+    pub cmdline: Vec<String>,
+    /// Errno of the last failed call, for SYS_ERRNO
+    pub last_errno: u32,
+    /// Host time at the start of the run in seconds since the epoch. Guest time is
+    /// this plus the simulated time, so runs stay repeatable within a session
+    pub start_time: u64,
+    /// Calls that changed host or file state, which stepping back can't undo
+    pub io_calls: u64,
+}
+
+impl HostIO {
+    pub fn new(root: PathBuf, cmdline: Vec<String>) -> Self {
+        let mut files = HashMap::new();
+        files.insert(0, HostFile::Stdin);
+        files.insert(1, HostFile::Stdout);
+        files.insert(2, HostFile::Stderr);
+        Self {
+            root,
+            files,
+            next_fd: 3,
+            stdin: Box::new(io::empty()),
+            stdin_read: 0,
+            cmdline,
+            last_errno: 0,
+            start_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            io_calls: 0,
+        }
+    }
+
+    pub fn set_stdin(&mut self, stdin: Box<dyn Read>) {
+        self.stdin = stdin;
+    }
+
+    pub fn stdin_read(&self) -> u64 {
+        self.stdin_read
+    }
+
+    /// Throw away the first n bytes of the guest stdin, as read before a
+    /// checkpoint. False if there aren't that many
+    pub fn skip_stdin(&mut self, n: u64) -> io::Result<bool> {
+        let skipped = io::copy(&mut self.stdin.as_mut().take(n), &mut io::sink())?;
+        self.stdin_read += skipped;
+        Ok(skipped == n)
+    }
+
+    /// Map a guest path into the sandbox, refusing anything that would leave it
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let mut resolved = self.root.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir | Component::RootDir => {}
+                Component::ParentDir | Component::Prefix(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("{} is outside the host directory", path),
+                    ))
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    pub fn open(&mut self, path: &str, mode: OpenMode) -> io::Result<u32> {
+        let mut options = OpenOptions::new();
+        match mode {
+            OpenMode::Read => options.read(true),
+            OpenMode::Write => options.write(true).create(true).truncate(true),
+            OpenMode::Append => options.append(true).create(true),
+            OpenMode::ReadWrite => options.read(true).write(true),
+            OpenMode::ReadWriteTruncate => {
+                options.read(true).write(true).create(true).truncate(true)
+            }
+            OpenMode::ReadAppend => options.read(true).append(true).create(true),
+        };
+        self.open_with(path, &options)
+    }
+
+    /// Open with options built by the caller, for flag combinations OpenMode doesn't cover
+    pub fn open_with(&mut self, path: &str, options: &OpenOptions) -> io::Result<u32> {
+        let file = options.open(self.resolve(path)?)?;
+        Ok(self.insert(HostFile::File(file)))
+    }
+
+    /// Add an already open file, returning its descriptor
+    pub fn insert(&mut self, file: HostFile) -> u32 {
+        self.io_calls += 1;
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.files.insert(fd, file);
+        fd
+    }
+
+    /// Whether anything besides the console is open
+    pub fn has_open_files(&self) -> bool {
+        self.files
+            .values()
+            .any(|file| !matches!(file, HostFile::Stdin | HostFile::Stdout | HostFile::Stderr))
+    }
+
+    pub fn close(&mut self, fd: u32) -> io::Result<()> {
+        self.io_calls += 1;
+        match self.files.remove(&fd) {
+            Some(_) => Ok(()),
+            None => Err(bad_fd()),
+        }
+    }
+
+    pub fn get(&mut self, fd: u32) -> io::Result<&mut HostFile> {
+        self.files.get_mut(&fd).ok_or_else(bad_fd)
+    }
+
+    /// Read up to len bytes, an empty result is end of file
+    pub fn read(&mut self, fd: u32, len: usize) -> io::Result<Vec<u8>> {
+        self.io_calls += 1;
+        let mut buf = vec![0; len];
+        let n = match self.files.get_mut(&fd).ok_or_else(bad_fd)? {
+            HostFile::File(file) => file.read(&mut buf)?,
+            HostFile::Buffer(buffer) => buffer.read(&mut buf)?,
+            HostFile::Stdin => {
+                let n = self.stdin.read(&mut buf)?;
+                self.stdin_read += n as u64;
+                n
+            }
+            HostFile::Stdout | HostFile::Stderr => return Err(bad_fd()),
+        };
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Write to a file, the console is left to the caller
+    pub fn write_file(&mut self, fd: u32, bytes: &[u8]) -> io::Result<usize> {
+        self.io_calls += 1;
+        match self.get(fd)? {
+            HostFile::File(file) => {
+                file.write_all(bytes)?;
+                Ok(bytes.len())
+            }
+            _ => Err(bad_fd()),
+        }
+    }
+
+    pub fn seek(&mut self, fd: u32, pos: SeekFrom) -> io::Result<u64> {
+        self.io_calls += 1;
+        match self.get(fd)? {
+            HostFile::File(file) => file.seek(pos),
+            HostFile::Buffer(buffer) => buffer.seek(pos),
+            _ => Err(io::Error::new(io::ErrorKind::Unsupported, "console can't seek")),
+        }
+    }
+
+    pub fn len(&mut self, fd: u32) -> io::Result<u64> {
+        match self.get(fd)? {
+            HostFile::File(file) => Ok(file.metadata()?.len()),
+            HostFile::Buffer(buffer) => Ok(buffer.get_ref().len() as u64),
+            _ => Err(io::Error::new(io::ErrorKind::Unsupported, "console has no length")),
+        }
+    }
+
+    pub fn is_console(&mut self, fd: u32) -> io::Result<bool> {
+        Ok(matches!(
+            self.get(fd)?,
+            HostFile::Stdin | HostFile::Stdout | HostFile::Stderr
+        ))
+    }
+
+    pub fn remove(&mut self, path: &str) -> io::Result<()> {
+        self.io_calls += 1;
+        std::fs::remove_file(self.resolve(path)?)
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        self.io_calls += 1;
+        std::fs::rename(self.resolve(from)?, self.resolve(to)?)
+    }
+}
+
+const EBADF: i32 = 9;
+
+fn bad_fd() -> io::Error {
+    io::Error::from_raw_os_error(EBADF)
+}
+
+/// The errno a newlib style guest expects for a host error. The low errno
+/// numbers are the same for newlib and the host
+pub fn errno(e: &io::Error) -> u32 {
+    if let Some(code) = e.raw_os_error() {
+        return code as u32;
+    }
+    match e.kind() {
+        io::ErrorKind::NotFound => 2,          // ENOENT
+        io::ErrorKind::PermissionDenied => 13, // EACCES
+        io::ErrorKind::AlreadyExists => 17,    // EEXIST
+        io::ErrorKind::InvalidInput => 22,     // EINVAL
+        io::ErrorKind::Unsupported => 29,      // ESPIPE
+        _ => 5,                                // EIO
+    }
+}
+
+#[cfg(test)]
+mod host_io_tests {
+    use super::*;
+
+    #[test]
+    fn sandbox() {
+        let io = HostIO::new(PathBuf::from("/sandbox"), vec![]);
+        assert_eq!(io.resolve("a/b.txt").unwrap(), PathBuf::from("/sandbox/a/b.txt"));
+        assert_eq!(io.resolve("/etc/passwd").unwrap(), PathBuf::from("/sandbox/etc/passwd"));
+        assert!(io.resolve("../secret").is_err());
+        assert!(io.resolve("a/../../secret").is_err());
+    }
+
+    #[test]
+    fn skip_stdin() {
+        let mut io = HostIO::new(PathBuf::from("/sandbox"), vec![]);
+        io.set_stdin(Box::new(Cursor::new(b"abcdef".to_vec())));
+        assert_eq!(io.read(0, 2).unwrap(), b"ab");
+        assert!(io.skip_stdin(3).unwrap());
+        assert_eq!(io.stdin_read(), 5);
+        assert_eq!(io.read(0, 8).unwrap(), b"f");
+        assert!(!io.skip_stdin(1).unwrap());
+    }
+}
@@ -0,0 +1,554 @@
+use super::{LineTable, Memory, Registers};
+use elf::abi::{PF_W, PT_LOAD, STT_FUNC};
+use elf::endian::{AnyEndian, EndianParse};
+use elf::ElfBytes;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const EM_ARM: u16 = 0x28;
+
+/// Stack room added to the ram of ELFs that don't give a memory map
+const DEFAULT_STACK_SIZE: u32 = 0x0008_0000;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Elf(String),
+    /// A malformed line in a HEX, SREC or memory map file
+    Parse {
+        line: usize,
+        msg: String,
+    },
+    /// Part of the image falls outside flash and ram
+    OutOfRange {
+        addr: u32,
+        len: u32,
+    },
+    BadMap(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Elf(msg) => write!(f, "invalid ELF: {}", msg),
+            LoadError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            LoadError::OutOfRange { addr, len } => write!(
+                f,
+                "{:#X} bytes at {:#010X} are outside the memory map",
+                len, addr
+            ),
+            LoadError::BadMap(msg) => write!(f, "bad memory map: {}", msg),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<elf::ParseError> for LoadError {
+    fn from(e: elf::ParseError) -> Self {
+        LoadError::Elf(e.to_string())
+    }
+}
+
+/// A read only flash region below a ram region, with the stack at the top of ram
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub flash_start: u32,
+    pub flash_size: u32,
+    pub ram_start: u32,
+    pub ram_size: u32,
+    pub stack_size: u32,
+}
+
+/// The layout of programs/script.ld
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            flash_start: 0x0000_0000,
+            flash_size: 0x0010_0000,
+            ram_start: 0x2000_0000,
+            ram_size: 0x0200_0000,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+}
+
+impl MemoryMap {
+    pub fn from_config(path: &str) -> Result<Self, LoadError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// `name = value` lines with the names of the linker script symbols, with or
+    /// without the leading underscores: flash, flash_size, ram, ram_size and
+    /// optionally stack_size. Values are decimal or 0x hex, # starts a comment
+    pub fn parse(text: &str) -> Result<Self, LoadError> {
+        let mut values = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parse_error = |msg: &str| LoadError::Parse {
+                line: n + 1,
+                msg: msg.to_string(),
+            };
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| parse_error("expected name = value"))?;
+            let name = name.trim().trim_start_matches('_');
+            if !["flash", "flash_size", "ram", "ram_size", "stack_size"].contains(&name) {
+                return Err(parse_error(&format!("unknown name {}", name)));
+            }
+            let value = parse_number(value.trim()).ok_or_else(|| parse_error("bad number"))?;
+            values.insert(name.to_string(), value);
+        }
+
+        let get = |name: &str| {
+            values
+                .get(name)
+                .copied()
+                .ok_or_else(|| LoadError::BadMap(format!("{} is missing", name)))
+        };
+        let ram_size = get("ram_size")?;
+        let map = Self {
+            flash_start: get("flash")?,
+            flash_size: get("flash_size")?,
+            ram_start: get("ram")?,
+            ram_size,
+            stack_size: get("stack_size").unwrap_or(DEFAULT_STACK_SIZE.min(ram_size)),
+        };
+        map.check()?;
+        Ok(map)
+    }
+
+    /// From the symbols programs/script.ld defines, if they're all there
+    fn from_symbols(symbols: &HashMap<String, u64>) -> Option<Self> {
+        let get = |name: &str| symbols.get(name).map(|value| *value as u32);
+        Some(Self {
+            flash_start: get("__flash")?,
+            flash_size: get("__flash_size")?,
+            ram_start: get("__ram")?,
+            ram_size: get("__ram_size")?,
+            stack_size: get("__stack_size")?,
+        })
+    }
+
+    /// Spans the loadable segments, read only ones in flash and writable ones in ram,
+    /// with room for a stack on top of ram
+    fn from_program_headers(elf_file: &ElfBytes<AnyEndian>) -> Result<Self, LoadError> {
+        let segments = elf_file
+            .segments()
+            .ok_or_else(|| LoadError::Elf("no program headers".to_string()))?;
+
+        let mut flash: Option<(u64, u64)> = None;
+        let mut ram: Option<(u64, u64)> = None;
+        let extend = |span: &mut Option<(u64, u64)>, start: u64, len: u64| {
+            let (lo, hi) = span.unwrap_or((start, start + len));
+            *span = Some((lo.min(start), hi.max(start + len)));
+        };
+        for phdr in segments.iter().filter(|phdr| phdr.p_type == PT_LOAD) {
+            if phdr.p_flags & PF_W != 0 {
+                extend(&mut ram, phdr.p_vaddr, phdr.p_memsz);
+                // Initialised data is loaded into flash and copied by the startup code
+                if phdr.p_paddr != phdr.p_vaddr && phdr.p_filesz > 0 {
+                    extend(&mut flash, phdr.p_paddr, phdr.p_filesz);
+                }
+            } else {
+                extend(&mut flash, phdr.p_paddr, phdr.p_memsz);
+            }
+        }
+
+        let default = Self::default();
+        let (ram_start, ram_end) =
+            ram.unwrap_or((default.ram_start as u64, default.ram_start as u64));
+        // Keep ram word aligned so the stack is too
+        let ram_size = ((ram_end - ram_start + DEFAULT_STACK_SIZE as u64 + 7) & !7) as u32;
+        let (flash_start, flash_end) = flash.unwrap_or((ram_start, ram_start));
+        let map = Self {
+            flash_start: flash_start as u32,
+            flash_size: ((flash_end - flash_start + 3) & !3) as u32,
+            ram_start: ram_start as u32,
+            ram_size,
+            stack_size: DEFAULT_STACK_SIZE,
+        };
+        map.check()?;
+        Ok(map)
+    }
+
+    fn check(&self) -> Result<(), LoadError> {
+        if self.ram_size == 0 {
+            Err(LoadError::BadMap("there's no ram".to_string()))
+        } else if (self.flash_start as u64 + self.flash_size as u64) > self.ram_start as u64 {
+            Err(LoadError::BadMap(
+                "flash must end before ram starts".to_string(),
+            ))
+        } else if self.ram_start as u64 + self.ram_size as u64 > u32::MAX as u64 {
+            Err(LoadError::BadMap(
+                "ram runs past the end of memory".to_string(),
+            ))
+        } else if self.stack_size > self.ram_size {
+            Err(LoadError::BadMap(
+                "the stack is bigger than ram".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Elf,
+    Bin,
+    IHex,
+    Srec,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "elf" => Some(ImageFormat::Elf),
+            "bin" => Some(ImageFormat::Bin),
+            "ihex" | "hex" => Some(ImageFormat::IHex),
+            "srec" => Some(ImageFormat::Srec),
+            _ => None,
+        }
+    }
+
+    /// ELFs by their magic number, the rest by file extension. Anything else is
+    /// taken as a raw binary
+    fn detect(path: &str, data: &[u8]) -> Self {
+        if data.starts_with(b"\x7FELF") {
+            return ImageFormat::Elf;
+        }
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        match extension.as_str() {
+            "hex" | "ihex" | "ihx" => ImageFormat::IHex,
+            "srec" | "s19" | "s28" | "s37" | "mot" => ImageFormat::Srec,
+            _ => ImageFormat::Bin,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    /// Detected from the file when not given
+    pub format: Option<ImageFormat>,
+    /// Where a raw binary is loaded, flash by default. HEX and SREC addresses are
+    /// offset by it
+    pub base: Option<u32>,
+    /// Overrides the map from the ELF, and replaces the default for other formats
+    pub memory_map: Option<MemoryMap>,
+    /// Overrides the entry point from the image
+    pub entry: Option<u32>,
+}
+
+/// A run of bytes from an image and where they go
+type Chunk = (u32, Vec<u8>);
+
+impl Memory {
+    /// Load a program image, setting up pc and sp to run it
+    pub fn load(
+        path: &str,
+        options: &LoadOptions,
+        regs: &mut Registers,
+    ) -> Result<Self, LoadError> {
+        let data = fs::read(path)?;
+        let format = options
+            .format
+            .unwrap_or_else(|| ImageFormat::detect(path, &data));
+
+        let mut memory = if format == ImageFormat::Elf {
+            Self::load_elf(&data, Path::new(path), options.memory_map)?
+        } else {
+            let map = options.memory_map.unwrap_or_default();
+            map.check()?;
+            let (chunks, entry) = match format {
+                ImageFormat::Bin => (vec![(options.base.unwrap_or(map.flash_start), data)], None),
+                ImageFormat::IHex => parse_ihex(&String::from_utf8_lossy(&data))?,
+                _ => parse_srec(&String::from_utf8_lossy(&data))?,
+            };
+            let offset = match format {
+                ImageFormat::Bin => 0,
+                _ => options.base.unwrap_or(0),
+            };
+
+            let mut memory = Self::new(
+                &map,
+                true,
+                BTreeMap::new(),
+                HashMap::new(),
+                LineTable::default(),
+            );
+            for (addr, bytes) in &chunks {
+                memory.load_bytes(addr.wrapping_add(offset), bytes)?;
+            }
+            // Without a start address, boot like the core would from the reset vector
+            // in the vector table at the start of the image
+            let base = chunks.iter().map(|(addr, _)| *addr).min().unwrap_or(0);
+            let entry = entry.or_else(|| memory.get_word(base.wrapping_add(offset) + 4).ok());
+            memory.entrypoint = (entry.unwrap_or(map.flash_start) & !1) as usize;
+            memory
+        };
+
+        if let Some(entry) = options.entry {
+            memory.entrypoint = (entry & !1) as usize;
+        }
+        regs.pc = memory.entrypoint as u32;
+        regs.sp = memory.ram_end();
+        Ok(memory)
+    }
+
+    fn load_elf(
+        data: &[u8],
+        path: &Path,
+        memory_map: Option<MemoryMap>,
+    ) -> Result<Self, LoadError> {
+        let elf_file = ElfBytes::<AnyEndian>::minimal_parse(data)?;
+        if elf_file.ehdr.e_machine != EM_ARM {
+            return Err(LoadError::Elf("only ARM is supported".to_string()));
+        }
+
+        let mut symbols = HashMap::new();
+        let mut functions = BTreeMap::new();
+        if let Some((symtab, strtab)) = elf_file.symbol_table()? {
+            for sym in symtab.iter() {
+                let name = strtab.get(sym.st_name as usize)?.to_string();
+                if sym.st_symtype() == STT_FUNC && sym.st_shndx != 0 {
+                    functions.insert(sym.st_value & !1, name.clone());
+                }
+                symbols.insert(name, sym.st_value);
+            }
+        }
+
+        let map = match memory_map.or_else(|| MemoryMap::from_symbols(&symbols)) {
+            Some(map) => {
+                map.check()?;
+                map
+            }
+            None => MemoryMap::from_program_headers(&elf_file)?,
+        };
+
+        // Debug info is only for showing source, broken debug info doesn't stop
+        // the program running
+        let lines = LineTable::from_elf(&elf_file, path).unwrap_or_default();
+        let mut memory = Self::new(
+            &map,
+            elf_file.ehdr.endianness.is_little(),
+            functions,
+            symbols,
+            lines,
+        );
+        if let Some(segments) = elf_file.segments() {
+            for phdr in segments.iter().filter(|phdr| phdr.p_type == PT_LOAD) {
+                let bytes = elf_file.segment_data(&phdr)?;
+                if !bytes.is_empty() {
+                    memory.load_bytes(phdr.p_paddr as u32, bytes)?;
+                }
+            }
+        }
+        memory.entrypoint = (elf_file.ehdr.e_entry & !1) as usize;
+        Ok(memory)
+    }
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => s.replace('_', "").parse().ok(),
+    }
+}
+
+/// The bytes of a record, checked against the checksum in its last byte. The sum
+/// of all the bytes is 0 for Intel HEX, and 0xFF for SREC
+fn record_bytes(hex: &str, line: usize, checksum_sum: u8) -> Result<Vec<u8>, LoadError> {
+    let parse_error = |msg: &str| LoadError::Parse {
+        line,
+        msg: msg.to_string(),
+    };
+    if !hex.len().is_multiple_of(2) || hex.len() < 2 {
+        return Err(parse_error("odd number of hex digits"));
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|n| u8::from_str_radix(&hex[n..n + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| parse_error("bad hex digit"))?;
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if sum != checksum_sum {
+        return Err(parse_error("bad checksum"));
+    }
+    Ok(bytes)
+}
+
+/// Data records and the start address of an Intel HEX file
+fn parse_ihex(text: &str) -> Result<(Vec<Chunk>, Option<u32>), LoadError> {
+    let mut chunks = Vec::new();
+    let mut entry = None;
+    let mut upper = 0u32;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parse_error = |msg: &str| LoadError::Parse {
+            line: n + 1,
+            msg: msg.to_string(),
+        };
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| parse_error("record doesn't start with :"))?;
+        let bytes = record_bytes(hex, n + 1, 0)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(parse_error("wrong record length"));
+        }
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        let halfword = || {
+            data.try_into()
+                .map(|bytes| u16::from_be_bytes(bytes) as u32)
+                .map_err(|_| parse_error("address must be 2 bytes"))
+        };
+        let word = || {
+            data.try_into()
+                .map(u32::from_be_bytes)
+                .map_err(|_| parse_error("start address must be 4 bytes"))
+        };
+        match bytes[3] {
+            0x00 => chunks.push((upper.wrapping_add(offset), data.to_vec())),
+            0x01 => break,
+            // Extended segment address, a real mode segment
+            0x02 => upper = halfword()? << 4,
+            // Start segment address, CS:IP
+            0x03 => {
+                let cs_ip = word()?;
+                entry = Some(((cs_ip >> 16) << 4) + (cs_ip & 0xFFFF));
+            }
+            // Extended linear address, the upper 16 bits
+            0x04 => upper = halfword()? << 16,
+            0x05 => entry = Some(word()?),
+            record_type => {
+                return Err(parse_error(&format!("unknown record type {}", record_type)))
+            }
+        }
+    }
+    Ok((chunks, entry))
+}
+
+/// Data records and the start address of a Motorola SREC file
+fn parse_srec(text: &str) -> Result<(Vec<Chunk>, Option<u32>), LoadError> {
+    let mut chunks = Vec::new();
+    let mut entry = None;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parse_error = |msg: &str| LoadError::Parse {
+            line: n + 1,
+            msg: msg.to_string(),
+        };
+        if !line.starts_with('S') || line.len() < 2 {
+            return Err(parse_error("record doesn't start with S"));
+        }
+        let record_type = line.as_bytes()[1];
+        let bytes = record_bytes(&line[2..], n + 1, 0xFF)?;
+        if bytes.len() != bytes[0] as usize + 1 {
+            return Err(parse_error("wrong record length"));
+        }
+        let address_len = match record_type {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(parse_error("unknown record type")),
+        };
+        if bytes.len() < address_len + 2 {
+            return Err(parse_error("record too short for its address"));
+        }
+        let address = bytes[1..=address_len]
+            .iter()
+            .fold(0u32, |address, byte| (address << 8) | *byte as u32);
+        let data = &bytes[address_len + 1..bytes.len() - 1];
+        match record_type {
+            b'1' | b'2' | b'3' => chunks.push((address, data.to_vec())),
+            b'7' | b'8' | b'9' => entry = Some(address),
+            // Header and record counts
+            _ => {}
+        }
+    }
+    Ok((chunks, entry))
+}
+
+#[cfg(test)]
+mod loader_tests {
+    use super::*;
+
+    #[test]
+    fn ihex_records() {
+        let text = ":0400000001020304F2\n\
+                    :020000042000DA\n\
+                    :02001000AABB89\n\
+                    :0400000500000101F5\n\
+                    :00000001FF\n";
+        let (chunks, entry) = parse_ihex(text).unwrap();
+        assert_eq!(
+            chunks,
+            vec![(0, vec![1, 2, 3, 4]), (0x2000_0010, vec![0xAA, 0xBB])]
+        );
+        assert_eq!(entry, Some(0x101));
+        assert!(parse_ihex(":0400000001020304F3\n").is_err());
+        // Address records with too few bytes
+        assert!(parse_ihex(":0100000400FB\n").is_err());
+        assert!(parse_ihex(":0100000200FD\n").is_err());
+        assert!(parse_ihex(":020000050101F7\n").is_err());
+    }
+
+    #[test]
+    fn srec_records() {
+        let text = "S00600004844521B\n\
+                    S107001001020304DE\n\
+                    S307200000001122A5\n\
+                    S9030101FA\n";
+        let (chunks, entry) = parse_srec(text).unwrap();
+        assert_eq!(
+            chunks,
+            vec![(0x10, vec![1, 2, 3, 4]), (0x2000_0000, vec![0x11, 0x22])]
+        );
+        assert_eq!(entry, Some(0x101));
+        assert!(parse_srec("S107001001020304DF\n").is_err());
+    }
+
+    #[test]
+    fn memory_map_config() {
+        let text = "# Like script.ld\n\
+                    __flash = 0x0\n\
+                    flash_size = 0x1000 # 4K\n\
+                    ram = 0x2000_0000\n\
+                    ram_size = 65536\n";
+        let map = MemoryMap::parse(text).unwrap();
+        assert_eq!(map.flash_size, 0x1000);
+        assert_eq!(map.ram_start, 0x2000_0000);
+        assert_eq!(map.ram_size, 0x10000);
+        assert_eq!(map.stack_size, 0x10000);
+        assert!(MemoryMap::parse("flash = 0\nram = 0x100\n").is_err());
+        assert!(
+            MemoryMap::parse("flash = 0\nflash_size = 0x200\nram = 0x100\nram_size = 0x100\n")
+                .is_err()
+        );
+    }
+}
@@ -1,12 +1,14 @@
 use crate::binary::*;
-use elf::abi::PT_LOAD;
-use elf::endian::{AnyEndian, EndianParse};
-use elf::ElfBytes;
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
-
-use super::Registers;
+use std::collections::{BTreeMap, HashMap};
+
+use super::{LineTable, LoadError, MemoryMap, SourceLine};
+use crate::checkpoint::{CheckpointError, Input, Persist};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Page contents by page number, see `Memory::start_page_log`
+pub type PageLog = HashMap<usize, Box<[u8]>>;
+
 #[derive(Clone)]
 pub struct Memory {
     pub entrypoint: usize,
@@ -15,7 +17,14 @@ pub struct Memory {
     flash_start: u32,
     flash_size: u32,
     ram_start: u32,
-    functions: HashMap<u64, String>,
+    ram_size: u32,
+    /// Function names by start address, without the thumb bit
+    functions: BTreeMap<u64, String>,
+    symbols: HashMap<String, u64>,
+    /// Source lines by address, from the ELF's debug info
+    lines: LineTable,
+    /// Pages as they were before their first write since the log was started
+    page_log: Option<PageLog>,
 }
 
 #[derive(Debug)]
@@ -27,103 +36,171 @@ pub enum MemError {
 
 #[allow(unused)]
 impl Memory {
-    pub fn from_elf(path: &str, regs: &mut Registers) -> Self {
-        let path = PathBuf::from(path);
-        let file_data = fs::read(path).expect("Could not read file");
-
-        let mut magic_number = Vec::new();
-        magic_number.push(file_data[0]);
-        magic_number.push(file_data[1]);
-        magic_number.push(file_data[2]);
-        magic_number.push(file_data[3]);
-
-        let file_data_static = Box::leak(file_data.clone().into_boxed_slice());
+    /// Zeroed memory laid out by the map, for the loader to fill in
+    pub(super) fn new(
+        map: &MemoryMap,
+        is_little_endian: bool,
+        functions: BTreeMap<u64, String>,
+        symbols: HashMap<String, u64>,
+        lines: LineTable,
+    ) -> Self {
+        Memory {
+            entrypoint: map.flash_start as usize,
+            memory: vec![0; (map.flash_size + map.ram_size) as usize],
+            is_little_endian,
+            flash_start: map.flash_start,
+            flash_size: map.flash_size,
+            ram_start: map.ram_start,
+            ram_size: map.ram_size,
+            functions,
+            symbols,
+            lines,
+            page_log: None,
+        }
+    }
 
-        let elf_file = ElfBytes::minimal_parse(file_data_static).expect("Failed to parse ELF file");
+    /// Start keeping the old contents of pages as they are written, returning
+    /// the log kept since the last call. Undoing it puts memory back as it was
+    pub fn start_page_log(&mut self) -> PageLog {
+        self.page_log.replace(PageLog::new()).unwrap_or_default()
+    }
 
-        let pt = elf_file.symbol_table().unwrap().unwrap();
-        let symtab = pt.0;
-        let strtab = pt.1;
+    pub fn undo_pages(&mut self, pages: &PageLog) {
+        for (page, contents) in pages {
+            let start = page * PAGE_SIZE;
+            self.memory[start..start + contents.len()].copy_from_slice(contents);
+        }
+    }
 
-        let mut symtab_map = HashMap::new();
+    /// The layout then the pages that aren't all zero, which is most of them
+    pub fn save_contents(&self, out: &mut Vec<u8>) {
+        [self.flash_start, self.flash_size, self.ram_start, self.ram_size].save(out);
+        let pages: Vec<(usize, Box<[u8]>)> = self
+            .memory
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(|byte| *byte != 0))
+            .map(|(n, page)| (n, page.into()))
+            .collect();
+        pages.save(out);
+    }
 
-        for sym in symtab.iter() {
-            symtab_map.insert(strtab.get(sym.st_name as usize).unwrap(), sym.st_value);
+    /// Read back what save_contents wrote. Only checkpoints of this program with
+    /// this memory map are accepted, as symbols come from the program
+    pub fn load_contents(&mut self, input: &mut Input) -> Result<(), CheckpointError> {
+        let layout = <[u32; 4]>::load(input)?;
+        if layout != [self.flash_start, self.flash_size, self.ram_start, self.ram_size] {
+            return Err(CheckpointError::ProgramMismatch);
         }
+        let mut memory = vec![0; self.memory.len()];
+        for (n, page) in Vec::<(usize, Box<[u8]>)>::load(input)? {
+            let start = n * PAGE_SIZE;
+            memory
+                .get_mut(start..start + page.len())
+                .ok_or(CheckpointError::Invalid("memory page"))?
+                .copy_from_slice(&page);
+        }
+        let flash = self.flash_size as usize;
+        if memory[..flash] != self.memory[..flash] {
+            return Err(CheckpointError::ProgramMismatch);
+        }
+        self.memory = memory;
+        Ok(())
+    }
 
-        let header: elf::file::FileHeader<AnyEndian> = elf_file.ehdr;
-        assert_eq!(header.e_machine, 0x28, "Only ARM architecture is supported");
-
-        let mandatory_sections = vec![
-            "__flash",
-            "__ram",
-            "__flash_size",
-            "__ram_size",
-            "__stack_size",
-        ];
-        for section in mandatory_sections {
-            if !symtab_map.contains_key(section) {
-                panic!("Invalid ELF: Missing symbol: {}", section)
-            }
+    /// Called before writing len bytes at the physical addr
+    fn log_write(&mut self, addr: usize, len: usize) {
+        let Some(log) = self.page_log.as_mut() else {
+            return;
+        };
+        for page in addr / PAGE_SIZE..=(addr + len - 1) / PAGE_SIZE {
+            log.entry(page).or_insert_with(|| {
+                let end = ((page + 1) * PAGE_SIZE).min(self.memory.len());
+                self.memory[page * PAGE_SIZE..end].into()
+            });
         }
+    }
 
-        // Get Functions
-        let functions: HashMap<u64, String> = symtab
-            .iter()
-            .filter(|sym| sym.st_symtype() == 2 && sym.st_shndx != 0)
-            .map(|sym| {
-                (
-                    sym.st_value,
-                    strtab.get(sym.st_name as usize).unwrap().to_string(),
-                )
-            })
-            .collect();
+    /// Copy part of an image in, flash included
+    pub(super) fn load_bytes(&mut self, vaddr: u32, bytes: &[u8]) -> Result<(), LoadError> {
+        let addr = self.mm(vaddr) as usize;
+        let in_flash = vaddr >= self.flash_start
+            && (vaddr as u64 + bytes.len() as u64) <= (self.flash_start + self.flash_size) as u64;
+        let in_ram = vaddr >= self.ram_start
+            && (vaddr as u64 + bytes.len() as u64) <= self.ram_end() as u64;
+        if !in_flash && !in_ram {
+            return Err(LoadError::OutOfRange {
+                addr: vaddr,
+                len: bytes.len() as u32,
+            });
+        }
+        self.memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
 
-        // Set reg values
-        regs.pc = *symtab_map.get("__flash").unwrap() as u32;
-        regs.sp =
-            (*symtab_map.get("__ram").unwrap() + *symtab_map.get("__ram_size").unwrap()) as u32;
+    pub fn get_function_at(&self, addr: u32) -> Option<&String> {
+        self.functions.get(&(addr as u64))
+    }
 
-        // Layout memory
-        let flash_start = *symtab_map.get("__flash").unwrap() as u32;
-        let flash_size = *symtab_map.get("__flash_size").unwrap() as u32;
-        let ram_start = *symtab_map.get("__ram").unwrap() as u32;
-        let ram_size = *symtab_map.get("__ram_size").unwrap() as u32;
-        let mem_size = (flash_size + ram_size) as usize;
+    /// The start and name of the function addr is in, taken to run up to the next one
+    pub fn function_containing(&self, addr: u32) -> Option<(u32, &String)> {
+        self.functions
+            .range(..=addr as u64)
+            .next_back()
+            .map(|(start, name)| (*start as u32, name))
+    }
 
-        let mut memory: Vec<u8> = Vec::new();
+    pub fn get_symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).map(|value| *value as u32)
+    }
 
-        // println!("{:?}", symtab_map);
+    pub fn line_table(&self) -> &LineTable {
+        &self.lines
+    }
 
-        memory.resize(mem_size, 0);
+    /// The source line the code at addr came from, if the program has debug info
+    pub fn source_line(&self, addr: u32) -> Option<SourceLine> {
+        self.lines.line_at(addr)
+    }
 
-        let segment_parse_table = elf_file.segments().unwrap();
-        for phdr in segment_parse_table.into_iter() {
-            if phdr.p_type != PT_LOAD {
-                continue;
-            }
-            let segment_bytes = elf_file.segment_data(&phdr).unwrap();
-            let mut mem_addr = phdr.p_paddr as usize;
+    /// One past the highest ram address, where the stack starts
+    pub fn ram_end(&self) -> u32 {
+        self.ram_start + self.ram_size
+    }
 
-            for byte in segment_bytes {
-                memory[mem_addr] = *byte;
-                mem_addr += 1;
-            }
+    /// Bytes that can be written from vaddr on, none outside ram
+    pub fn writable_from(&self, vaddr: u32) -> u32 {
+        if (self.ram_start..self.ram_end()).contains(&vaddr) {
+            self.ram_end() - vaddr
+        } else {
+            0
         }
+    }
 
-        Memory {
-            entrypoint: elf_file.ehdr.e_entry as usize - 1,
-            memory,
-            is_little_endian: header.endianness.is_little(),
-            flash_start,
-            flash_size,
-            ram_start,
-            functions,
+    pub fn read_bytes(&self, vaddr: u32, len: u32) -> Result<Vec<u8>, MemError> {
+        (0..len).map(|n| self.get_byte(vaddr + n)).collect()
+    }
+
+    pub fn write_bytes(&mut self, vaddr: u32, bytes: &[u8]) -> Result<(), MemError> {
+        for (n, byte) in bytes.iter().enumerate() {
+            self.set_byte(vaddr + n as u32, *byte)?;
         }
+        Ok(())
     }
 
-    pub fn get_function_at(&self, addr: u32) -> Option<&String> {
-        self.functions.get(&(addr as u64))
+    /// Read a NUL terminated string
+    pub fn read_cstring(&self, vaddr: u32) -> Result<String, MemError> {
+        let mut bytes = Vec::new();
+        let mut addr = vaddr;
+        loop {
+            let byte = self.get_byte(addr)?;
+            if byte == 0 {
+                break;
+            }
+            bytes.push(byte);
+            addr += 1;
+        }
+        Ok(String::from_utf8_lossy(&bytes).to_string())
     }
 
     /// Dump from vaddr to mem end
@@ -156,7 +233,7 @@ impl Memory {
         if addr >= self.ram_start {
             addr - self.ram_start + self.flash_size
         } else {
-            addr
+            addr.wrapping_sub(self.flash_start)
         }
     }
 
@@ -178,6 +255,7 @@ impl Memory {
 
     pub fn set_byte_nolog(&mut self, vaddr: u32, value: u8) {
         let addr = self.mm(vaddr) as usize;
+        self.log_write(addr, 1);
         self.memory[addr] = value;
     }
 
@@ -247,23 +325,9 @@ impl Memory {
         }
     }
 
-    pub fn get_word_be(&self, vaddr: u32) -> Result<u32, MemError> {
-        let addr = self.mm(vaddr) as usize;
-        if addr >= self.memory.len() {
-            Err(MemError::LoadOOB)
-        } else {
-            Ok(u32::from_be_bytes([
-                self.memory[addr],
-                self.memory[addr + 1],
-                self.memory[addr + 2],
-                self.memory[addr + 3],
-            ]))
-        }
-    }
-
     pub fn set_word(&mut self, vaddr: u32, value: u32) -> Result<(), MemError> {
         let addr = self.mm(vaddr) as usize;
-        if (addr as u32) < (self.flash_start + self.flash_size) {
+        if (addr as u32) < self.flash_size {
             Err(MemError::SetRO)
         } else if addr >= self.memory.len() - 3 {
             Err(MemError::SetOOB)
@@ -273,6 +337,7 @@ impl Memory {
             } else {
                 value.to_be_bytes()
             };
+            self.log_write(addr, 4);
             self.memory[addr] = bytes[0];
             self.memory[addr + 1] = bytes[1];
             self.memory[addr + 2] = bytes[2];
@@ -283,7 +348,7 @@ impl Memory {
 
     pub fn set_halfword(&mut self, vaddr: u32, value: u16) -> Result<(), MemError> {
         let addr = self.mm(vaddr) as usize;
-        if (addr as u32) < (self.flash_start + self.flash_size) {
+        if (addr as u32) < self.flash_size {
             Err(MemError::SetRO)
         } else if addr >= self.memory.len() - 1 {
             Err(MemError::SetOOB)
@@ -293,6 +358,7 @@ impl Memory {
             } else {
                 value.to_be_bytes()
             };
+            self.log_write(addr, 2);
             self.memory[addr] = bytes[0];
             self.memory[addr + 1] = bytes[1];
             Ok(())
@@ -301,48 +367,14 @@ impl Memory {
 
     pub fn set_byte(&mut self, vaddr: u32, value: u8) -> Result<(), MemError> {
         let addr = self.mm(vaddr) as usize;
-        if (addr as u32) < (self.flash_start + self.flash_size) {
+        if (addr as u32) < self.flash_size {
             Err(MemError::SetRO)
-        } else if addr >= self.memory.len() - 1 {
+        } else if addr >= self.memory.len() {
             Err(MemError::SetOOB)
         } else {
+            self.log_write(addr, 1);
             self.memory[addr] = value;
             Ok(())
         }
     }
-
-    pub fn dump(
-        &self,
-        width: usize,
-        height: usize,
-        bottom: usize,
-        rows_scrolled_up: usize,
-    ) -> String {
-        // A byte is two chars, a word is eight
-        // Addr is eight byes
-        // #aaaaaaaa: 01234567 01234567
-        let width_for_mem = width - 11;
-        let words_per_line = 4;
-
-        let start_addr = bottom - ((words_per_line * (height)) * 4) - (rows_scrolled_up * 4);
-        let mut string = String::new();
-        string.reserve(width * height);
-
-        for y in 0..height {
-            let line_addr = start_addr + (y * words_per_line * 4);
-            string.push_str(&format!("#{:08X?}:", line_addr));
-            for x in 0..words_per_line {
-                let addr = (line_addr + x * 4) as u32;
-                // string.push_str(&format!(" {:08X}", addr));
-                if let Ok(word) = self.get_word_be(addr) {
-                    string.push_str(&format!(" {:08X}", word));
-                } else {
-                    string.push_str(" ________");
-                }
-            }
-            string.push('\n');
-        }
-
-        string
-    }
 }
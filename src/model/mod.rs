@@ -1,11 +1,48 @@
+mod host_io;
+mod loader;
 mod memory;
 mod registers;
+mod source;
+mod uart;
 
-pub use memory::Memory;
-pub use registers::{ASPRUpdate, Registers};
+pub use host_io::*;
+pub use loader::{ImageFormat, LoadError, LoadOptions, MemoryMap};
+pub use memory::{MemError, Memory, PageLog};
+pub use registers::{ASPRUpdate, Registers, ASPR};
+pub use source::{LineTable, SourceLine};
+pub use uart::*;
 
 #[derive(Clone)]
 pub struct ProcessorState {
     pub regs: Registers,
     pub mem: Memory,
 }
+
+impl ProcessorState {
+    /// Lay out argv at the top of the stack as the C runtime would, with the strings
+    /// above a null terminated array of pointers to them, and pass argc and argv to
+    /// main in r0 and r1
+    pub fn set_main_args(&mut self, args: &[String]) -> Result<(), MemError> {
+        let mut sp = self.regs.sp;
+        let mut argv = Vec::with_capacity(args.len() + 1);
+        for arg in args {
+            sp -= arg.len() as u32 + 1;
+            self.mem.write_bytes(sp, arg.as_bytes())?;
+            self.mem.write_bytes(sp + arg.len() as u32, &[0])?;
+            argv.push(sp);
+        }
+        argv.push(0);
+
+        sp &= !3;
+        sp -= 4 * argv.len() as u32;
+        for (n, ptr) in argv.iter().enumerate() {
+            self.mem.set_word(sp + 4 * n as u32, *ptr)?;
+        }
+
+        self.regs.gp[0] = args.len() as u32;
+        self.regs.gp[1] = sp;
+        // The AAPCS wants an 8 byte aligned stack at public interfaces
+        self.regs.sp = sp & !7;
+        Ok(())
+    }
+}
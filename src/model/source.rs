@@ -0,0 +1,204 @@
+//! Source lines from the DWARF line table programs built with -g carry, so code
+//! can be shown as the C it came from. Only `.debug_line` is used, through the
+//! compilation units in `.debug_info` that own the line programs.
+//!
+//! The source itself is read when the program is loaded. Paths in the line table
+//! are where the program was compiled, so a file that isn't there is looked for
+//! relative to the ELF's directory and the ones above it, which finds the
+//! sources next to a checkout of programs/ built somewhere else.
+use elf::endian::{AnyEndian, EndianParse};
+use elf::ElfBytes;
+use gimli::{EndianSlice, RunTimeEndian};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A line of a source file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceLine {
+    /// Index into the line table's files
+    pub file: usize,
+    /// Counting from 1
+    pub line: u32,
+}
+
+#[derive(Clone, Debug)]
+struct SourceFile {
+    /// As the line table has it
+    path: String,
+    /// The contents by line, if the file was found
+    text: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    /// The line of the code from each address up to the next one, none where the
+    /// compiler says the code has no line or between sequences
+    rows: BTreeMap<u32, Option<SourceLine>>,
+    files: Vec<SourceFile>,
+}
+
+impl LineTable {
+    /// The line table of an ELF, empty if it has no debug info. elf_path is where
+    /// the ELF was read from, to find the sources
+    pub fn from_elf(elf_file: &ElfBytes<AnyEndian>, elf_path: &Path) -> Result<Self, gimli::Error> {
+        let endian = if elf_file.ehdr.endianness.is_little() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+            // Compressed sections are left out like missing ones
+            let data = match elf_file.section_header_by_name(id.name()) {
+                Ok(Some(header)) => match elf_file.section_data(&header) {
+                    Ok((data, None)) => data,
+                    _ => &[],
+                },
+                _ => &[],
+            };
+            Ok(EndianSlice::new(data, endian))
+        })?;
+
+        let mut table = Self::default();
+        let mut file_indices: HashMap<PathBuf, usize> = HashMap::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let comp_dir = unit
+                .comp_dir
+                .map(|dir| PathBuf::from(dir.to_string_lossy().as_ref()))
+                .unwrap_or_default();
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let address = row.address() as u32;
+                if row.end_sequence() {
+                    // Another sequence may start where this one ends
+                    table.rows.entry(address).or_insert(None);
+                    continue;
+                }
+                let (Some(file), Some(line)) = (row.file(header), row.line()) else {
+                    table.rows.insert(address, None);
+                    continue;
+                };
+
+                let mut path = comp_dir.clone();
+                if let Some(dir) = file.directory(header) {
+                    path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+                }
+                path.push(
+                    dwarf
+                        .attr_string(&unit, file.path_name())?
+                        .to_string_lossy()
+                        .as_ref(),
+                );
+                let file = *file_indices.entry(path.clone()).or_insert_with(|| {
+                    table.files.push(SourceFile {
+                        text: find_source(&path, &comp_dir, elf_path),
+                        path: path.to_string_lossy().into_owned(),
+                    });
+                    table.files.len() - 1
+                });
+                table.rows.insert(
+                    address,
+                    Some(SourceLine {
+                        file,
+                        line: line.get() as u32,
+                    }),
+                );
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The line the code at addr came from
+    pub fn line_at(&self, addr: u32) -> Option<SourceLine> {
+        *self.rows.range(..=addr).next_back()?.1
+    }
+
+    /// The text of a line, if its file was found
+    pub fn text(&self, line: SourceLine) -> Option<&str> {
+        let text = self.files[line.file].text.as_ref()?;
+        text.get(line.line as usize - 1).map(String::as_str)
+    }
+
+    /// file.c:12, with just the file's name
+    pub fn describe(&self, line: SourceLine) -> String {
+        let path = Path::new(&self.files[line.file].path);
+        let name = path.file_name().map_or(path.as_os_str(), |name| name);
+        format!("{}:{}", name.to_string_lossy(), line.line)
+    }
+}
+
+/// The lines of path, or if it isn't there, of the same path relative to the
+/// compilation directory under the ELF's directory or one above it, or failing
+/// that of the file with the same name there
+fn find_source(path: &Path, comp_dir: &Path, elf_path: &Path) -> Option<Vec<String>> {
+    let relative = path.strip_prefix(comp_dir).ok().map(normalise);
+    let name = path.file_name().map(Path::new);
+    let elf_dir = elf_path.parent().unwrap_or(Path::new(""));
+    let roots = elf_dir.ancestors().map(|dir| {
+        if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        }
+    });
+    let candidates = roots
+        .clone()
+        .filter_map(|root| Some(root.join(relative.as_ref()?)))
+        .chain(roots.filter_map(|root| Some(root.join(name?))));
+    std::iter::once(path.to_path_buf())
+        .chain(candidates)
+        .find_map(|candidate| fs::read_to_string(candidate).ok())
+        .map(|text| text.lines().map(str::to_string).collect())
+}
+
+/// Without the .. components, so benchmarks/../syscalls/syscalls.h is found even
+/// without a benchmarks directory
+fn normalise(path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalised.pop();
+            }
+            Component::CurDir => {}
+            component => normalised.push(component),
+        }
+    }
+    normalised
+}
+
+#[cfg(test)]
+mod source_tests {
+    use crate::model::{LoadOptions, Memory, Registers};
+
+    #[test]
+    fn benchmark_lines() {
+        let mut regs = Registers::new();
+        let mem = Memory::load(
+            "programs/benchmarks/bubble.out",
+            &LoadOptions::default(),
+            &mut regs,
+        )
+        .unwrap();
+        let lines = mem.line_table();
+        let main = mem.get_symbol("main").unwrap() & !1;
+        let line = lines.line_at(main).unwrap();
+        assert!(lines.describe(line).starts_with("bubble.c:"));
+        assert!(lines.text(line).unwrap().contains("main"));
+        // From benchmarks/../syscalls, which the line table has as a separate directory
+        let sbrk = lines.line_at(mem.get_symbol("sbrk").unwrap() & !1).unwrap();
+        assert!(lines.describe(sbrk).starts_with("syscalls.h:"));
+        assert!(lines.text(sbrk).is_some());
+        // Literal pools have no line
+        assert_eq!(lines.line_at(0x44), None);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::checkpoint::persist_fields;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// Start of the ARMv6-M peripheral region, where the UART sits by default
+pub const UART_DEFAULT_BASE: u32 = 0x4000_0000;
+
+/// Register offsets from the UART base address
+/// - DATA: write sends a byte, read pops a byte from the RX FIFO
+/// - STATUS: bit 0 RXNE (RX FIFO not empty), bit 1 TXE (ready to send, always set)
+/// - CTRL: bit 0 RXIE (raise IRQ 0 while the RX FIFO is not empty)
+pub const UART_DATA: u32 = 0x0;
+pub const UART_STATUS: u32 = 0x4;
+pub const UART_CTRL: u32 = 0x8;
+
+pub const UART_STATUS_RXNE: u32 = 1 << 0;
+pub const UART_STATUS_TXE: u32 = 1 << 1;
+pub const UART_CTRL_RXIE: u32 = 1 << 0;
+
+/// The UART IRQ line, taken as exception number 16 + UART_IRQ
+pub const UART_IRQ: u32 = 0;
+
+const UART_SIZE: u32 = 0xC;
+const RX_FIFO_SIZE: usize = 16;
+
+pub enum UartSource {
+    None,
+    /// Bytes read from the host stdin on a background thread, so polling never blocks
+    Stdin(Receiver<u8>),
+    /// Bytes from a file, fed in one byte per cycle as the FIFO has room
+    Script(VecDeque<u8>),
+}
+
+pub enum UartSink {
+    Stdout,
+    File(File),
+    /// Kept until the end of the run, for when stdout belongs to the TUI
+    Capture(String),
+}
+
+/// A minimal memory mapped UART. Reads and writes are byte wide, wider accesses
+/// see the byte in the bottom 8 bits
+pub struct Uart {
+    pub base: u32,
+    rx_fifo: VecDeque<u8>,
+    source: UartSource,
+    sink: UartSink,
+    ctrl: u32,
+    /// Ticks so far, and the most there have ever been. Ticks up to the most are
+    /// a replay after stepping back, see `UartState`
+    ticks: u64,
+    max_ticks: u64,
+    replaying: bool,
+    /// Bytes taken from the source and the tick they were taken on, so a replay
+    /// sees the same input
+    received: Vec<(u64, u8)>,
+    /// Bytes of the source thrown away when resuming from a checkpoint
+    skipped: u64,
+}
+
+/// What `Uart::restore` needs to go back to an earlier tick. Replaying from there
+/// takes input from what was received the first time and sends nothing, as it
+/// was all sent already
+#[derive(Clone)]
+pub struct UartState {
+    rx_fifo: VecDeque<u8>,
+    ctrl: u32,
+    ticks: u64,
+}
+
+impl UartSource {
+    pub fn stdin() -> Self {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            while let Ok(n) = io::stdin().read(&mut buf) {
+                if n == 0 || buf[..n].iter().any(|byte| tx.send(*byte).is_err()) {
+                    break;
+                }
+            }
+        });
+        UartSource::Stdin(rx)
+    }
+
+    pub fn script(path: &str) -> io::Result<Self> {
+        Ok(UartSource::Script(std::fs::read(path)?.into()))
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        match self {
+            UartSource::None => None,
+            UartSource::Stdin(rx) => rx.try_recv().ok(),
+            UartSource::Script(bytes) => bytes.pop_front(),
+        }
+    }
+
+    /// Like next, but waits for the host stdin rather than giving up
+    fn next_blocking(&mut self) -> Option<u8> {
+        match self {
+            UartSource::Stdin(rx) => rx.recv().ok(),
+            _ => self.next(),
+        }
+    }
+}
+
+impl Uart {
+    pub fn new(base: u32, source: UartSource, sink: UartSink) -> Self {
+        Self {
+            base,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_SIZE),
+            source,
+            sink,
+            ctrl: 0,
+            ticks: 0,
+            max_ticks: 0,
+            replaying: false,
+            received: Vec::new(),
+            skipped: 0,
+        }
+    }
+
+    pub fn save(&self) -> UartState {
+        UartState {
+            rx_fifo: self.rx_fifo.clone(),
+            ctrl: self.ctrl,
+            ticks: self.ticks,
+        }
+    }
+
+    pub fn restore(&mut self, state: UartState) {
+        self.rx_fifo = state.rx_fifo;
+        self.ctrl = state.ctrl;
+        self.ticks = state.ticks;
+    }
+
+    /// Bytes taken from the source up to this tick, so a checkpoint can carry on
+    /// after them
+    pub fn consumed(&self) -> u64 {
+        let received = self
+            .received
+            .iter()
+            .take_while(|(tick, _)| *tick <= self.ticks)
+            .count();
+        self.skipped + received as u64
+    }
+
+    /// Throw away the first n bytes of the source, as taken before a checkpoint.
+    /// False if there aren't that many
+    pub fn skip_input(&mut self, n: u64) -> bool {
+        let all = (0..n).all(|_| self.source.next_blocking().is_some());
+        self.skipped += n;
+        all
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        // Wrapping so a UART at the top of memory doesn't overflow
+        addr.wrapping_sub(self.base) < UART_SIZE
+    }
+
+    /// Move at most one byte from the source into the RX FIFO, called once a cycle
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+        self.replaying = self.ticks <= self.max_ticks;
+        if self.replaying {
+            let received = self
+                .received
+                .binary_search_by_key(&self.ticks, |(tick, _)| *tick);
+            if let Ok(n) = received {
+                self.rx_fifo.push_back(self.received[n].1);
+            }
+            return;
+        }
+        self.max_ticks = self.ticks;
+        if self.rx_fifo.len() < RX_FIFO_SIZE {
+            if let Some(byte) = self.source.next() {
+                self.rx_fifo.push_back(byte);
+                self.received.push((self.ticks, byte));
+            }
+        }
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        (self.ctrl & UART_CTRL_RXIE) != 0 && !self.rx_fifo.is_empty()
+    }
+
+    /// Register read. Reading DATA has the side effect of popping the RX FIFO,
+    /// so this must only be called non speculatively
+    pub fn read(&mut self, addr: u32) -> u32 {
+        match addr - self.base {
+            UART_DATA => self.rx_fifo.pop_front().unwrap_or(0) as u32,
+            UART_STATUS => {
+                let rxne = if self.rx_fifo.is_empty() {
+                    0
+                } else {
+                    UART_STATUS_RXNE
+                };
+                rxne | UART_STATUS_TXE
+            }
+            UART_CTRL => self.ctrl,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u32, value: u32) {
+        match addr - self.base {
+            UART_DATA => self.transmit(value as u8),
+            UART_CTRL => self.ctrl = value,
+            _ => {}
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        if self.replaying {
+            return;
+        }
+        match &mut self.sink {
+            UartSink::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(&[byte]).unwrap();
+                stdout.flush().unwrap();
+            }
+            UartSink::File(file) => file.write_all(&[byte]).unwrap(),
+            UartSink::Capture(captured) => captured.push(byte as char),
+        }
+    }
+
+    /// Everything sent while capturing, empty for the other sinks
+    pub fn captured(&self) -> &str {
+        match &self.sink {
+            UartSink::Capture(captured) => captured,
+            _ => "",
+        }
+    }
+}
+
+persist_fields!(UartState {
+    rx_fifo,
+    ctrl,
+    ticks
+});
+
+#[cfg(test)]
+mod uart_tests {
+    use super::*;
+
+    #[test]
+    fn rx_fifo_and_status() {
+        let source = UartSource::Script(VecDeque::from(vec![b'h', b'i']));
+        let mut uart = Uart::new(UART_DEFAULT_BASE, source, UartSink::Capture(String::new()));
+
+        assert_eq!(uart.read(UART_DEFAULT_BASE + UART_STATUS), UART_STATUS_TXE);
+        uart.tick();
+        uart.tick();
+        assert_eq!(
+            uart.read(UART_DEFAULT_BASE + UART_STATUS),
+            UART_STATUS_TXE | UART_STATUS_RXNE
+        );
+        assert_eq!(uart.read(UART_DEFAULT_BASE + UART_DATA), b'h' as u32);
+        assert_eq!(uart.read(UART_DEFAULT_BASE + UART_DATA), b'i' as u32);
+        assert_eq!(uart.read(UART_DEFAULT_BASE + UART_STATUS), UART_STATUS_TXE);
+    }
+
+    #[test]
+    fn contains_at_top_of_memory() {
+        let uart = Uart::new(0xFFFF_FFF4, UartSource::None, UartSink::Capture(String::new()));
+        assert!(uart.contains(0xFFFF_FFF4));
+        assert!(uart.contains(0xFFFF_FFFF));
+        assert!(!uart.contains(0xFFFF_FFF3));
+        assert!(!uart.contains(0));
+    }
+
+    #[test]
+    fn rx_interrupt_and_tx() {
+        let source = UartSource::Script(VecDeque::from(vec![b'x']));
+        let mut uart = Uart::new(UART_DEFAULT_BASE, source, UartSink::Capture(String::new()));
+        uart.tick();
+        assert!(!uart.irq_pending());
+
+        uart.write(UART_DEFAULT_BASE + UART_CTRL, UART_CTRL_RXIE);
+        assert!(uart.irq_pending());
+        uart.read(UART_DEFAULT_BASE + UART_DATA);
+        assert!(!uart.irq_pending());
+
+        uart.write(UART_DEFAULT_BASE + UART_DATA, b'o' as u32);
+        uart.write(UART_DEFAULT_BASE + UART_DATA, b'k' as u32);
+        assert_eq!(uart.captured(), "ok");
+    }
+
+    #[test]
+    fn skip_input() {
+        let source = UartSource::Script(VecDeque::from(vec![b'a', b'b', b'c']));
+        let mut uart = Uart::new(UART_DEFAULT_BASE, source, UartSink::Capture(String::new()));
+        uart.tick();
+        assert_eq!(uart.consumed(), 1);
+        assert!(uart.skip_input(1));
+        assert_eq!(uart.consumed(), 2);
+        uart.tick();
+        assert_eq!(uart.read(UART_DEFAULT_BASE + UART_DATA), b'a' as u32);
+        assert_eq!(uart.read(UART_DEFAULT_BASE + UART_DATA), b'c' as u32);
+        assert!(!uart.skip_input(1));
+    }
+}
@@ -0,0 +1,334 @@
+//! SimPoint-style sampled simulation. A functional run splits the program into
+//! fixed-size intervals and counts the instructions run in each basic block in
+//! each interval. Those basic block vectors are clustered with k-means, and one
+//! interval per cluster, the closest to its centre, stands for the rest. Only
+//! those are simulated in detail, and the whole run's CPI is estimated from
+//! theirs, weighted by how much of the program each cluster covers.
+//!
+//! Profiling and simulating are separate runs joined by a simpoints file, so
+//! each runs the program once from the start.
+use crate::cpu::{FastForward, OoOSpeculative};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::{fs, io};
+
+/// Basic block vectors are randomly projected down to this many dimensions
+/// before clustering, as SimPoint does
+const DIMENSIONS: usize = 15;
+/// Lloyd iterations at most per clustering
+const MAX_ITERATIONS: usize = 100;
+/// Clusterings tried from different starting centres, the tightest is kept
+const SEEDS: u64 = 5;
+
+type Point = [f64; DIMENSIONS];
+
+/// An interval standing for its cluster
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimPoint {
+    /// Index of the interval, it starts interval * interval_size instructions in
+    pub interval: usize,
+    /// Fraction of all instructions in intervals of this one's cluster
+    pub weight: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimPoints {
+    pub interval_size: usize,
+    pub points: Vec<SimPoint>,
+}
+
+/// splitmix64, repeatable without pulling in a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// In [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Normalise the vector to frequencies and project it. Each block's random
+/// direction comes from its address, so it's the same in every interval
+fn project(vector: &HashMap<u32, u64>) -> Point {
+    let total: u64 = vector.values().sum();
+    let mut point = [0.0; DIMENSIONS];
+    for (block, count) in vector {
+        let frequency = *count as f64 / total.max(1) as f64;
+        let mut rng = Rng(*block as u64);
+        for x in point.iter_mut() {
+            *x += frequency * (rng.unit() * 2.0 - 1.0);
+        }
+    }
+    point
+}
+
+fn distance(a: &Point, b: &Point) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+fn nearest(point: &Point, centres: &[Point]) -> usize {
+    (0..centres.len())
+        .min_by(|a, b| distance(point, &centres[*a]).total_cmp(&distance(point, &centres[*b])))
+        .unwrap()
+}
+
+/// Lloyd's algorithm from k-means++ starting centres. Returns the centres, the
+/// cluster of each point and the total squared distance to the centres
+fn k_means(points: &[Point], k: usize, rng: &mut Rng) -> (Vec<Point>, Vec<usize>, f64) {
+    let mut centres = vec![points[(rng.next() % points.len() as u64) as usize]];
+    while centres.len() < k {
+        let distances: Vec<f64> = points
+            .iter()
+            .map(|point| distance(point, &centres[nearest(point, &centres)]))
+            .collect();
+        let total: f64 = distances.iter().sum();
+        // Fewer distinct points than clusters
+        if total == 0.0 {
+            break;
+        }
+        let mut target = rng.unit() * total;
+        let mut chosen = points.len() - 1;
+        for (n, d) in distances.iter().enumerate() {
+            if target < *d {
+                chosen = n;
+                break;
+            }
+            target -= d;
+        }
+        centres.push(points[chosen]);
+    }
+
+    let mut clusters = vec![usize::MAX; points.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let assigned: Vec<usize> = points.iter().map(|p| nearest(p, &centres)).collect();
+        if assigned == clusters {
+            break;
+        }
+        clusters = assigned;
+        for (c, centre) in centres.iter_mut().enumerate() {
+            let members: Vec<&Point> = (0..points.len())
+                .filter(|n| clusters[*n] == c)
+                .map(|n| &points[n])
+                .collect();
+            // An empty cluster keeps its centre
+            if members.is_empty() {
+                continue;
+            }
+            *centre = [0.0; DIMENSIONS];
+            for member in &members {
+                for (x, m) in centre.iter_mut().zip(member.iter()) {
+                    *x += m / members.len() as f64;
+                }
+            }
+        }
+    }
+    let spread = (0..points.len())
+        .map(|n| distance(&points[n], &centres[clusters[n]]))
+        .sum();
+    (centres, clusters, spread)
+}
+
+/// Cluster the intervals' basic block vectors into at most k simulation points
+pub fn choose(vectors: &[HashMap<u32, u64>], k: usize) -> Vec<SimPoint> {
+    if vectors.is_empty() || k == 0 {
+        return Vec::new();
+    }
+    let points: Vec<Point> = vectors.iter().map(project).collect();
+    let k = k.min(points.len());
+    let (centres, clusters, _) = (0..SEEDS)
+        .map(|seed| k_means(&points, k, &mut Rng(seed)))
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .unwrap();
+
+    let sizes: Vec<u64> = vectors.iter().map(|v| v.values().sum()).collect();
+    let total: u64 = sizes.iter().sum();
+    let mut chosen: Vec<SimPoint> = centres
+        .iter()
+        .enumerate()
+        .filter_map(|(c, centre)| {
+            let members = (0..points.len()).filter(|n| clusters[*n] == c);
+            let interval = members.clone().min_by(|a, b| {
+                distance(&points[*a], centre).total_cmp(&distance(&points[*b], centre))
+            })?;
+            let instructions: u64 = members.map(|n| sizes[n]).sum();
+            Some(SimPoint {
+                interval,
+                weight: instructions as f64 / total.max(1) as f64,
+            })
+        })
+        .collect();
+    chosen.sort_by_key(|point| point.interval);
+    chosen
+}
+
+impl SimPoints {
+    /// Lines of interval index and weight, after the interval size
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# aca simulation points: interval index, weight\n");
+        writeln!(text, "interval_size {}", self.interval_size).unwrap();
+        for point in &self.points {
+            writeln!(text, "{} {:.6}", point.interval, point.weight).unwrap();
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut interval_size = None;
+        let mut points = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("invalid line: {}", line);
+            match words[..] {
+                ["interval_size", size] => {
+                    interval_size = Some(size.parse().map_err(|_| invalid())?)
+                }
+                [interval, weight] => points.push(SimPoint {
+                    interval: interval.parse().map_err(|_| invalid())?,
+                    weight: weight.parse().map_err(|_| invalid())?,
+                }),
+                _ => return Err(invalid()),
+            }
+        }
+        points.sort_by_key(|point| point.interval);
+        let interval_size = interval_size.ok_or("no interval_size line")?;
+        if interval_size == 0 {
+            return Err("interval_size must be at least 1".to_string());
+        }
+        Ok(SimPoints {
+            interval_size,
+            points,
+        })
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A simulation point run in detail
+pub struct Measurement {
+    pub point: SimPoint,
+    pub instructions: usize,
+    pub cycles: usize,
+}
+
+/// Fast-forward to each point in turn and simulate it in detail, after warming
+/// up on the warmup instructions before it where they haven't been simulated
+/// already
+pub fn simulate(cpu: &mut OoOSpeculative, points: &SimPoints, warmup: usize) -> Vec<Measurement> {
+    let mut measurements = Vec::new();
+    // Instructions since the start of the program
+    let mut position = 0;
+    for point in &points.points {
+        let start = point.interval * points.interval_size;
+        let warm_from = start.saturating_sub(warmup).max(position);
+        if warm_from > position {
+            position += cpu.fast_forward(FastForward::Instructions(warm_from - position));
+        }
+        cpu.start_measuring();
+        while cpu.halt.is_none() && position + cpu.instructions_committed < start {
+            cpu.tick();
+        }
+        position += cpu.instructions_committed;
+
+        cpu.start_measuring();
+        while cpu.halt.is_none() && cpu.instructions_committed < points.interval_size {
+            cpu.tick();
+        }
+        position += cpu.instructions_committed;
+        if cpu.instructions_committed == 0 {
+            break;
+        }
+        measurements.push(Measurement {
+            point: *point,
+            instructions: cpu.instructions_committed,
+            cycles: cpu.epoch,
+        });
+    }
+    measurements
+}
+
+/// Each point's IPC and the whole program's, estimated from their CPIs
+pub fn report(measurements: &[Measurement]) -> String {
+    if measurements.is_empty() {
+        return "The program ended before the first simulation point".to_string();
+    }
+    let mut text = format!(
+        "{:>10} {:>8} {:>13} {:>10} {:>8}\n",
+        "interval", "weight", "instructions", "cycles", "IPC"
+    );
+    let mut weighted_cpi = 0.0;
+    let mut weights = 0.0;
+    for m in measurements {
+        let cpi = m.cycles as f64 / m.instructions as f64;
+        weighted_cpi += m.point.weight * cpi;
+        weights += m.point.weight;
+        writeln!(
+            text,
+            "{:>10} {:>8.4} {:>13} {:>10} {:>8.3}",
+            m.point.interval,
+            m.point.weight,
+            m.instructions,
+            m.cycles,
+            1.0 / cpi
+        )
+        .unwrap();
+    }
+    // Points past the end of the program don't count
+    let cpi = weighted_cpi / weights;
+    write!(text, "Estimated IPC: {:.3}, CPI: {:.3}", 1.0 / cpi, cpi).unwrap();
+    text
+}
+
+#[cfg(test)]
+mod simpoint_tests {
+    use super::*;
+
+    #[test]
+    fn two_phases() {
+        let a: HashMap<u32, u64> = [(0x100, 900), (0x180, 100)].into();
+        let b: HashMap<u32, u64> = [(0x200, 1000)].into();
+        let vectors = [&a, &a, &b, &a, &b, &a].map(Clone::clone);
+        let points = choose(&vectors, 2);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].interval, 0);
+        assert_eq!(points[1].interval, 2);
+        assert!((points[0].weight - 4.0 / 6.0).abs() < 1e-9);
+        assert!((points[1].weight - 2.0 / 6.0).abs() < 1e-9);
+        // Only as many points as there are different intervals
+        assert_eq!(choose(&vectors, 5).len(), 2);
+    }
+
+    #[test]
+    fn file() {
+        let points = SimPoints {
+            interval_size: 10000,
+            points: vec![
+                SimPoint {
+                    interval: 3,
+                    weight: 0.25,
+                },
+                SimPoint {
+                    interval: 7,
+                    weight: 0.75,
+                },
+            ],
+        };
+        assert_eq!(SimPoints::parse(&points.to_text()), Ok(points));
+        assert!(SimPoints::parse("3 0.5\n").is_err());
+        assert!(SimPoints::parse("interval_size 0\n3 0.5\n").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! A registry of named run statistics. Components report into it through
+//! `Sampled`, once a cycle, and the whole thing is dumped as JSON or CSV at the
+//! end. Names are dotted paths, like rob.occupancy or rs.mul.occupancy.
+use crate::checkpoint::persist_fields;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+/// How often each value was seen, for small whole number values like occupancy
+#[derive(Default, Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: usize) {
+        if self.buckets.len() <= value {
+            self.buckets.resize(value + 1, 0);
+        }
+        self.buckets[value] += 1;
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn mean(&self) -> f64 {
+        let total: u64 = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(value, count)| value as u64 * count)
+            .sum();
+        total as f64 / self.samples().max(1) as f64
+    }
+
+    pub fn max(&self) -> usize {
+        self.buckets
+            .iter()
+            .rposition(|count| *count > 0)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Stats {
+    counters: BTreeMap<String, u64>,
+    ratios: BTreeMap<String, f64>,
+    histograms: BTreeMap<String, Histogram>,
+}
+
+impl Stats {
+    pub fn add(&mut self, name: &str, n: u64) {
+        match self.counters.get_mut(name) {
+            Some(counter) => *counter += n,
+            None => {
+                self.counters.insert(name.to_string(), n);
+            }
+        }
+    }
+
+    /// For counts kept by the component itself
+    pub fn set(&mut self, name: &str, value: u64) {
+        self.counters.insert(name.to_string(), value);
+    }
+
+    pub fn set_ratio(&mut self, name: &str, value: f64) {
+        self.ratios.insert(name.to_string(), value);
+    }
+
+    pub fn record(&mut self, name: &str, value: usize) {
+        match self.histograms.get_mut(name) {
+            Some(histogram) => histogram.record(value),
+            None => {
+                let mut histogram = Histogram::default();
+                histogram.record(value);
+                self.histograms.insert(name.to_string(), histogram);
+            }
+        }
+    }
+
+    pub fn counter(&self, name: &str) -> u64 {
+        self.counters.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn histogram(&self, name: &str) -> Option<&Histogram> {
+        self.histograms.get(name)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n  \"counters\": {");
+        for (n, (name, value)) in self.counters.iter().enumerate() {
+            let sep = if n == 0 { "" } else { "," };
+            write!(json, "{}\n    \"{}\": {}", sep, name, value).unwrap();
+        }
+        json += "\n  },\n  \"ratios\": {";
+        for (n, (name, value)) in self.ratios.iter().enumerate() {
+            let sep = if n == 0 { "" } else { "," };
+            write!(json, "{}\n    \"{}\": {}", sep, name, json_number(*value)).unwrap();
+        }
+        json += "\n  },\n  \"histograms\": {";
+        for (n, (name, histogram)) in self.histograms.iter().enumerate() {
+            let sep = if n == 0 { "" } else { "," };
+            write!(
+                json,
+                "{}\n    \"{}\": {{\"samples\": {}, \"mean\": {}, \"max\": {}, \"buckets\": [{}]}}",
+                sep,
+                name,
+                histogram.samples(),
+                json_number(histogram.mean()),
+                histogram.max(),
+                histogram
+                    .buckets
+                    .iter()
+                    .map(|count| count.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .unwrap();
+        }
+        json += "\n  }\n}\n";
+        json
+    }
+
+    /// One stat per row. Histograms get mean, max and a row per value seen
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("stat,value\n");
+        for (name, value) in self.counters.iter() {
+            writeln!(csv, "{},{}", name, value).unwrap();
+        }
+        for (name, value) in self.ratios.iter() {
+            writeln!(csv, "{},{}", name, value).unwrap();
+        }
+        for (name, histogram) in self.histograms.iter() {
+            writeln!(csv, "{}.samples,{}", name, histogram.samples()).unwrap();
+            writeln!(csv, "{}.mean,{}", name, histogram.mean()).unwrap();
+            writeln!(csv, "{}.max,{}", name, histogram.max()).unwrap();
+            for (value, count) in histogram.buckets.iter().enumerate() {
+                if *count > 0 {
+                    writeln!(csv, "{}[{}],{}", name, value, count).unwrap();
+                }
+            }
+        }
+        csv
+    }
+}
+
+/// A display name as a stat name, "RS full (ALU/shift)" becomes rs_full_alu_shift
+pub fn stat_key(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// JSON has no NaN or infinity
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Something that reports into the registry every cycle, under the given name
+pub trait Sampled {
+    fn sample(&self, stats: &mut Stats, name: &str);
+}
+
+/// Queues report their length
+impl<T> Sampled for VecDeque<T> {
+    fn sample(&self, stats: &mut Stats, name: &str) {
+        stats.record(&format!("{}.occupancy", name), self.len());
+    }
+}
+
+impl<T> Sampled for Vec<T> {
+    fn sample(&self, stats: &mut Stats, name: &str) {
+        stats.record(&format!("{}.occupancy", name), self.len());
+    }
+}
+
+persist_fields!(Histogram { buckets });
+persist_fields!(Stats {
+    counters,
+    ratios,
+    histograms
+});
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::default();
+        for value in [0, 2, 2, 4] {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.samples(), 4);
+        assert_eq!(histogram.mean(), 2.0);
+        assert_eq!(histogram.max(), 4);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(stat_key("RS full (ALU/shift)"), "rs_full_alu_shift");
+        assert_eq!(stat_key("Load/store 1"), "load_store_1");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::decode::{decode, I, IT::*};
+use crate::decode::{decode, I, IT, IT::*};
 
 #[test]
 fn adc_test() {
@@ -217,3 +217,10 @@ fn bl_test() {
         }
     );
 }
+
+#[test]
+fn it_all_test() {
+    for (n, it) in IT::ALL.iter().enumerate() {
+        assert_eq!(*it as usize, n);
+    }
+}
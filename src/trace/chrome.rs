@@ -0,0 +1,146 @@
+use super::{PipelineEvent, Tracer, Unit};
+use crate::cpu::{CDB_WIDTH, N_ALUSHIFTERS, N_CONTROL, N_ISSUE, N_LS_EXECS, N_MULS};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// A row in the timeline
+#[derive(Clone, Copy)]
+enum Track {
+    Fetch(usize),
+    Unit(Unit),
+    Cdb(usize),
+}
+
+impl Track {
+    /// Every track, in the order they are shown
+    fn all() -> Vec<Track> {
+        let mut tracks = Vec::new();
+        tracks.extend((0..N_ISSUE).map(Track::Fetch));
+        tracks.extend((0..N_ALUSHIFTERS).map(|n| Track::Unit(Unit::AluShift(n))));
+        tracks.extend((0..N_MULS).map(|n| Track::Unit(Unit::Mul(n))));
+        tracks.extend((0..N_CONTROL).map(|n| Track::Unit(Unit::Control(n))));
+        tracks.extend((0..N_LS_EXECS).map(|n| Track::Unit(Unit::LoadStore(n))));
+        tracks.extend((0..CDB_WIDTH).map(Track::Cdb));
+        tracks
+    }
+
+    fn tid(self) -> usize {
+        match self {
+            Track::Fetch(n) => n,
+            Track::Unit(Unit::AluShift(n)) => N_ISSUE + n,
+            Track::Unit(Unit::Mul(n)) => N_ISSUE + N_ALUSHIFTERS + n,
+            Track::Unit(Unit::Control(n)) => N_ISSUE + N_ALUSHIFTERS + N_MULS + n,
+            Track::Unit(Unit::LoadStore(n)) => N_ISSUE + N_ALUSHIFTERS + N_MULS + N_CONTROL + n,
+            Track::Cdb(n) => N_ISSUE + N_ALUSHIFTERS + N_MULS + N_CONTROL + N_LS_EXECS + n,
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Track::Fetch(n) => format!("Fetch {}", n),
+            Track::Unit(unit) => unit.to_string(),
+            Track::Cdb(n) => format!("CDB {}", n),
+        }
+    }
+}
+
+/// Writes a Chrome trace event JSON file, as read by Perfetto and chrome://tracing,
+/// showing what occupied each fetch slot, functional unit and CDB slot every cycle.
+/// One cycle shows as one microsecond. Mops are labelled with their disassembly and
+/// carry their seq and pc as arguments, so a mop can be followed across tracks
+pub struct ChromeTrace {
+    out: BufWriter<File>,
+    /// Labels of decoded mops that haven't committed or been squashed yet
+    labels: HashMap<u64, (u32, String)>,
+}
+
+impl ChromeTrace {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(
+            out,
+            "{{\"traceEvents\":[\n{{\"ph\":\"M\",\"pid\":0,\"name\":\"process_name\",\"args\":{{\"name\":\"aca\"}}}}"
+        )?;
+        for track in Track::all() {
+            write!(
+                out,
+                ",\n{{\"ph\":\"M\",\"pid\":0,\"tid\":{0},\"name\":\"thread_name\",\"args\":{{\"name\":\"{1}\"}}}}\
+                 ,\n{{\"ph\":\"M\",\"pid\":0,\"tid\":{0},\"name\":\"thread_sort_index\",\"args\":{{\"sort_index\":{0}}}}}",
+                track.tid(),
+                track.name()
+            )?;
+        }
+        Ok(Self {
+            out,
+            labels: HashMap::new(),
+        })
+    }
+
+    /// A one cycle slice on a track
+    fn slice(&mut self, cycle: usize, track: Track, name: &str, args: &str) -> io::Result<()> {
+        write!(
+            self.out,
+            ",\n{{\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{},\"dur\":1,\"name\":\"{}\",\"args\":{{{}}}}}",
+            track.tid(),
+            cycle,
+            escape(name),
+            args
+        )
+    }
+
+    fn mop_slice(&mut self, cycle: usize, track: Track, seq: u64) -> io::Result<()> {
+        let (pc, label) = match self.labels.get(&seq) {
+            Some((pc, label)) => (*pc, label.clone()),
+            None => return Ok(()),
+        };
+        let args = format!("\"seq\":{},\"pc\":\"0x{:08x}\"", seq, pc);
+        self.slice(cycle, track, &label, &args)
+    }
+}
+
+impl Tracer for ChromeTrace {
+    fn event(&mut self, cycle: usize, event: &PipelineEvent) {
+        let result = match *event {
+            PipelineEvent::Fetch { pc, slot } => {
+                let args = format!("\"pc\":\"0x{:08x}\"", pc);
+                self.slice(cycle, Track::Fetch(slot), &format!("0x{:08x}", pc), &args)
+            }
+            PipelineEvent::Decode { seq, pc, i, .. } => {
+                self.labels.insert(seq, (pc, i.to_string()));
+                Ok(())
+            }
+            PipelineEvent::Issue { .. }
+            | PipelineEvent::HeadStall { .. }
+            | PipelineEvent::LoadBlocked { .. }
+            | PipelineEvent::LoadForwarded { .. } => Ok(()),
+            PipelineEvent::Execute { seq, unit } => self.mop_slice(cycle, Track::Unit(unit), seq),
+            PipelineEvent::Writeback { seq, slot } => self.mop_slice(cycle, Track::Cdb(slot), seq),
+            PipelineEvent::Commit { seq, .. } | PipelineEvent::Squash { seq } => {
+                self.labels.remove(&seq);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            panic!("Could not write Chrome trace: {}", e)
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        write!(self.out, "\n]}}\n")?;
+        self.out.flush()
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
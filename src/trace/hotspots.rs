@@ -0,0 +1,210 @@
+use super::{PipelineEvent, Tracer};
+use crate::model::{LineTable, SourceLine};
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// What happened to one static instruction over the run
+#[derive(Default)]
+struct PcStats {
+    /// Disassembly of each mop, by upc
+    mops: Vec<String>,
+    /// Times the first mop committed
+    executions: u64,
+    mops_committed: u64,
+    /// Issue to commit cycles, summed over committed mops
+    latency: u64,
+    head_stalls: u64,
+    mispredicts: u64,
+    loads_forwarded: u64,
+    load_blocked_cycles: u64,
+}
+
+impl PcStats {
+    /// What the report is sorted by, the commit slots the instruction held up first
+    fn cost(&self) -> (u64, u64) {
+        (self.head_stalls, self.latency)
+    }
+
+    fn add(&mut self, other: &PcStats) {
+        self.executions += other.executions;
+        self.mops_committed += other.mops_committed;
+        self.latency += other.latency;
+        self.head_stalls += other.head_stalls;
+        self.mispredicts += other.mispredicts;
+        self.loads_forwarded += other.loads_forwarded;
+        self.load_blocked_cycles += other.load_blocked_cycles;
+    }
+}
+
+/// Per pc statistics written as annotated disassembly, costliest first. Only
+/// committed mops are counted, apart from head stalls and blocked loads. With
+/// debug info, each pc's source line is given and the counts are summed by line
+/// after that
+pub struct Hotspots {
+    path: String,
+    /// Pc, upc and issue cycle of each mop in flight
+    in_flight: HashMap<u64, (u32, usize, usize)>,
+    stats: BTreeMap<u32, PcStats>,
+    lines: LineTable,
+}
+
+impl Hotspots {
+    pub fn create(path: &str, lines: LineTable) -> io::Result<Self> {
+        // Fail now rather than at the end of the run
+        File::create(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            in_flight: HashMap::new(),
+            stats: BTreeMap::new(),
+            lines,
+        })
+    }
+
+    fn describe(&self, line: Option<SourceLine>) -> String {
+        line.map_or("-".to_string(), |line| self.lines.describe(line))
+    }
+
+    /// The counts summed over each source line's instructions, costliest first
+    fn write_lines(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut by_line: BTreeMap<Option<SourceLine>, PcStats> = BTreeMap::new();
+        for (pc, stats) in &self.stats {
+            by_line
+                .entry(self.lines.line_at(*pc))
+                .or_default()
+                .add(stats);
+        }
+        writeln!(
+            out,
+            "\n{:<24}{:>10}{:>10}{:>12}{:>12}{:>10}{:>10}  source",
+            "line",
+            "executed",
+            "latency",
+            "head stall",
+            "mispredict",
+            "forwarded",
+            "blocked"
+        )?;
+        let by_cost = by_line
+            .iter()
+            .filter(|(_, stats)| stats.mops_committed > 0)
+            .sorted_by_key(|(line, stats)| (Reverse(stats.cost()), **line));
+        for (line, stats) in by_cost {
+            let text = line.and_then(|line| self.lines.text(line)).unwrap_or("");
+            writeln!(
+                out,
+                "{:<24}{:>10}{:>10.2}{:>12}{:>12}{:>10}{:>10}  {}",
+                self.describe(*line),
+                stats.executions,
+                stats.latency as f64 / stats.mops_committed as f64,
+                stats.head_stalls,
+                stats.mispredicts,
+                stats.loads_forwarded,
+                stats.load_blocked_cycles,
+                text.trim()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn stats_for(&mut self, seq: u64) -> Option<&mut PcStats> {
+        let (pc, ..) = self.in_flight.get(&seq)?;
+        self.stats.get_mut(pc)
+    }
+}
+
+impl Tracer for Hotspots {
+    fn event(&mut self, cycle: usize, event: &PipelineEvent) {
+        match *event {
+            PipelineEvent::Decode {
+                seq, pc, upc, i, ..
+            } => {
+                self.in_flight.insert(seq, (pc, upc, 0));
+                let stats = self.stats.entry(pc).or_default();
+                if stats.mops.len() <= upc {
+                    stats.mops.resize(upc + 1, String::new());
+                    stats.mops[upc] = i.to_string();
+                }
+            }
+            PipelineEvent::Issue { seq, .. } => {
+                if let Some((_, _, issued)) = self.in_flight.get_mut(&seq) {
+                    *issued = cycle;
+                }
+            }
+            PipelineEvent::Commit {
+                seq, mispredicted, ..
+            } => {
+                if let Some((pc, upc, issued)) = self.in_flight.remove(&seq) {
+                    let stats = self.stats.entry(pc).or_default();
+                    stats.executions += (upc == 0) as u64;
+                    stats.mops_committed += 1;
+                    stats.latency += (cycle - issued) as u64;
+                    stats.mispredicts += mispredicted as u64;
+                }
+            }
+            PipelineEvent::Squash { seq } => {
+                self.in_flight.remove(&seq);
+            }
+            PipelineEvent::HeadStall { seq } => {
+                if let Some(stats) = self.stats_for(seq) {
+                    stats.head_stalls += 1;
+                }
+            }
+            PipelineEvent::LoadBlocked { seq } => {
+                if let Some(stats) = self.stats_for(seq) {
+                    stats.load_blocked_cycles += 1;
+                }
+            }
+            PipelineEvent::LoadForwarded { seq } => {
+                if let Some(stats) = self.stats_for(seq) {
+                    stats.loads_forwarded += 1;
+                }
+            }
+            PipelineEvent::Fetch { .. }
+            | PipelineEvent::Execute { .. }
+            | PipelineEvent::Writeback { .. } => {}
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        writeln!(
+            out,
+            "{:>8}  {:<32}{:>10}{:>10}{:>12}{:>12}{:>10}{:>10}  line",
+            "pc",
+            "instruction",
+            "count",
+            "latency",
+            "head stall",
+            "mispredict",
+            "forwarded",
+            "blocked"
+        )?;
+        let by_cost = self
+            .stats
+            .iter()
+            .filter(|(_, stats)| stats.mops_committed > 0)
+            .sorted_by_key(|(pc, stats)| (Reverse(stats.cost()), **pc));
+        for (pc, stats) in by_cost {
+            writeln!(
+                out,
+                "{:08x}  {:<32}{:>10}{:>10.2}{:>12}{:>12}{:>10}{:>10}  {}",
+                pc,
+                stats.mops.iter().filter(|mop| !mop.is_empty()).join(" ; "),
+                stats.executions,
+                stats.latency as f64 / stats.mops_committed as f64,
+                stats.head_stalls,
+                stats.mispredicts,
+                stats.loads_forwarded,
+                stats.load_blocked_cycles,
+                self.describe(self.lines.line_at(*pc))
+            )?;
+        }
+        if !self.lines.is_empty() {
+            self.write_lines(&mut out)?;
+        }
+        out.flush()
+    }
+}
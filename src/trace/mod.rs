@@ -0,0 +1,93 @@
+mod chrome;
+mod hotspots;
+mod pipeview;
+
+pub use chrome::ChromeTrace;
+pub use hotspots::Hotspots;
+pub use pipeview::PipeView;
+
+use crate::decode::I;
+use std::fmt;
+use std::io;
+
+/// A functional unit and its index among units of the same kind
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    AluShift(usize),
+    Mul(usize),
+    Control(usize),
+    LoadStore(usize),
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unit::AluShift(n) => write!(f, "ALU/shift {}", n),
+            Unit::Mul(n) => write!(f, "Mul {}", n),
+            Unit::Control(n) => write!(f, "Control {}", n),
+            Unit::LoadStore(n) => write!(f, "Load/store {}", n),
+        }
+    }
+}
+
+/// What the pipeline did with an instruction, reported by each stage as it happens.
+/// seq numbers mops in decode order, so squashed ones get numbers too
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineEvent {
+    /// Read from memory into fetch buffer slot `slot`
+    Fetch {
+        pc: u32,
+        slot: usize,
+    },
+    /// pc is the address of the instruction and upc the mop's index within it. The
+    /// fetch cycle comes along here as fetch buffer entries aren't numbered
+    Decode {
+        seq: u64,
+        pc: u32,
+        upc: usize,
+        i: I,
+        fetched: usize,
+    },
+    /// Into the ROB and a reservation station
+    Issue {
+        seq: u64,
+        rob: usize,
+    },
+    /// Left its reservation station for a functional unit
+    Execute {
+        seq: u64,
+        unit: Unit,
+    },
+    /// Broadcast on the CDB
+    Writeback {
+        seq: u64,
+        slot: usize,
+    },
+    Commit {
+        seq: u64,
+        store: bool,
+        mispredicted: bool,
+    },
+    /// Flushed, from the ROB or before it got there
+    Squash {
+        seq: u64,
+    },
+    /// At the head of the ROB but not ready, costing commit slots this cycle
+    HeadStall {
+        seq: u64,
+    },
+    /// A load in the load queue held back by an older store this cycle
+    LoadBlocked {
+        seq: u64,
+    },
+    /// A load given its value by an older store instead of memory
+    LoadForwarded {
+        seq: u64,
+    },
+}
+
+pub trait Tracer {
+    fn event(&mut self, cycle: usize, event: &PipelineEvent);
+    /// Write out anything still buffered, at the end of the run
+    fn finish(&mut self) -> io::Result<()>;
+}
@@ -0,0 +1,134 @@
+use super::{PipelineEvent, Tracer};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// gem5 reports times in ticks, with 1000 to a cycle at its default 1GHz clock
+const TICKS_PER_CYCLE: usize = 1000;
+
+/// The stage times of one mop, 0 for stages it never reached
+#[derive(Default)]
+struct Record {
+    pc: u32,
+    upc: usize,
+    disasm: String,
+    fetch: usize,
+    decode: usize,
+    issue: usize,
+    execute: usize,
+    writeback: usize,
+}
+
+/// Writes the gem5 O3PipeView format that Konata reads. gem5's stages map onto
+/// ours as: fetch and decode as they are, rename and dispatch both at issue into
+/// the ROB and reservation station, issue at execute, complete at CDB writeback
+/// and retire at commit. Squashed mops retire at tick 0. Each mop is written out
+/// once it commits or is squashed, with its ROB index in the disassembly
+pub struct PipeView {
+    out: BufWriter<File>,
+    in_flight: HashMap<u64, Record>,
+}
+
+impl PipeView {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            in_flight: HashMap::new(),
+        })
+    }
+
+    fn write_record(
+        &mut self,
+        seq: u64,
+        record: &Record,
+        retire: usize,
+        store: usize,
+    ) -> io::Result<()> {
+        let tick = |cycle: usize| cycle * TICKS_PER_CYCLE;
+        writeln!(
+            self.out,
+            "O3PipeView:fetch:{}:0x{:08x}:{}:{}:{}",
+            tick(record.fetch),
+            record.pc,
+            record.upc,
+            seq,
+            record.disasm
+        )?;
+        writeln!(self.out, "O3PipeView:decode:{}", tick(record.decode))?;
+        writeln!(self.out, "O3PipeView:rename:{}", tick(record.issue))?;
+        writeln!(self.out, "O3PipeView:dispatch:{}", tick(record.issue))?;
+        writeln!(self.out, "O3PipeView:issue:{}", tick(record.execute))?;
+        writeln!(self.out, "O3PipeView:complete:{}", tick(record.writeback))?;
+        writeln!(
+            self.out,
+            "O3PipeView:retire:{}:store:{}",
+            tick(retire),
+            tick(store)
+        )
+    }
+}
+
+impl Tracer for PipeView {
+    fn event(&mut self, cycle: usize, event: &PipelineEvent) {
+        let result = match *event {
+            PipelineEvent::Fetch { .. }
+            | PipelineEvent::HeadStall { .. }
+            | PipelineEvent::LoadBlocked { .. }
+            | PipelineEvent::LoadForwarded { .. } => Ok(()),
+            PipelineEvent::Decode {
+                seq,
+                pc,
+                upc,
+                i,
+                fetched,
+            } => {
+                let record = Record {
+                    pc,
+                    upc,
+                    disasm: i.to_string(),
+                    fetch: fetched,
+                    decode: cycle,
+                    ..Default::default()
+                };
+                self.in_flight.insert(seq, record);
+                Ok(())
+            }
+            PipelineEvent::Issue { seq, rob } => {
+                if let Some(record) = self.in_flight.get_mut(&seq) {
+                    record.issue = cycle;
+                    record.disasm = format!("[rob {:2}] {}", rob, record.disasm);
+                }
+                Ok(())
+            }
+            PipelineEvent::Execute { seq, .. } => {
+                if let Some(record) = self.in_flight.get_mut(&seq) {
+                    record.execute = cycle;
+                }
+                Ok(())
+            }
+            PipelineEvent::Writeback { seq, .. } => {
+                if let Some(record) = self.in_flight.get_mut(&seq) {
+                    record.writeback = cycle;
+                }
+                Ok(())
+            }
+            PipelineEvent::Commit { seq, store, .. } => match self.in_flight.remove(&seq) {
+                Some(record) => {
+                    self.write_record(seq, &record, cycle, if store { cycle } else { 0 })
+                }
+                None => Ok(()),
+            },
+            PipelineEvent::Squash { seq, .. } => match self.in_flight.remove(&seq) {
+                Some(record) => self.write_record(seq, &record, 0, 0),
+                None => Ok(()),
+            },
+        };
+        if let Err(e) = result {
+            panic!("Could not write pipeline trace: {}", e)
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}